@@ -13,31 +13,56 @@ pub fn get_antivirus_property(_property_name: String, _include_inactive: bool) -
     Ok(())
 }
 
+#[cfg(unix)]
+pub fn get_active_antivirus_names() -> Vec<String> {
+    Vec::new()
+}
+
 #[cfg(windows)]
-pub fn get_antivirus_property(property_name: String, include_inactive: bool) -> Result<()> {
+fn get_antivirus_products(include_inactive: bool) -> Result<Vec<HashMap<String, wmi::Variant>>> {
     use wmi::Variant;
     use wmi::*;
 
     let wmi_con =
         WMIConnection::with_namespace_path("ROOT\\SecurityCenter2")?;
     let query = format!("SELECT * FROM AntiVirusProduct");
-    let products = wmi_con.raw_query(query)?;
-    let mut is_first = true;
-    for antivirus_product in products {
-        let prod: HashMap<String, Variant> = antivirus_product;
+    let products: Vec<HashMap<String, Variant>> = wmi_con.raw_query(query)?;
+    if include_inactive {
+        return Ok(products);
+    }
+    // Filter only active products
+    Ok(products
+        .into_iter()
+        .filter(|prod| match &prod["productState"] {
+            // magic number of antivirus state: https://mcpforlife.com/2020/04/14/how-to-resolve-this-state-value-of-av-providers/
+            Variant::I8(value) => value & 0b1000000000000 != 0,
+            _ => true,
+        })
+        .collect())
+}
 
-        // Filter only active products
-        if !include_inactive {
-            let product_state = &prod["productState"];
+/// Names of active antivirus products, they have significant impact on build time.
+#[cfg(windows)]
+pub fn get_active_antivirus_names() -> Vec<String> {
+    let products = match get_antivirus_products(false) {
+        Ok(products) => products,
+        Err(_e) => return Vec::new(),
+    };
+    products
+        .iter()
+        .filter_map(|prod| match prod.get("displayName") {
+            Some(wmi::Variant::String(value)) => Some(value.clone()),
+            _ => None,
+        })
+        .collect()
+}
 
-            if let Variant::I8(value) = product_state {
-                // magic number of antivirus state: https://mcpforlife.com/2020/04/14/how-to-resolve-this-state-value-of-av-providers/
-                if value & 0b1000000000000 == 0 {
-                    continue;
-                }
-            }
-        }
+#[cfg(windows)]
+pub fn get_antivirus_property(property_name: String, include_inactive: bool) -> Result<()> {
+    use wmi::Variant;
 
+    let mut is_first = true;
+    for prod in get_antivirus_products(include_inactive)? {
         match property_name == "*" {
             true => {
                 println!("{:#?}", prod)
@@ -1,7 +1,7 @@
 use clap::Arg;
 use clap_nested::{Command, Commander, MultiCommand};
 
-use crate::package::{prepare_package, remove_package};
+use crate::package::{prepare_package, refresh_package, remove_package, SignaturePolicy};
 use std::io::Read;
 use std::process::Stdio;
 
@@ -12,6 +12,7 @@ fn prepare_companion() {
         "https://dl.espressif.com/dl/esp-iwidc/esp-iwidc.zip".to_string(),
         "esp-iwidc.zip",
         "tmp/esp-iwidc".to_string(),
+        SignaturePolicy::Required,
     ) {
         Ok(_) => {
             println!("Ok");
@@ -22,14 +23,35 @@ fn prepare_companion() {
     }
 }
 
+fn refresh_companion() -> Result<()> {
+    refresh_package(
+        "https://dl.espressif.com/dl/esp-iwidc/esp-iwidc.zip".to_string(),
+        "esp-iwidc.zip",
+        "tmp/esp-iwidc".to_string(),
+        SignaturePolicy::Required,
+    )
+}
+
 fn remove_companion() -> Result<()> {
     remove_package("esp-iwidc.zip", "tmp/esp-iwidc")
 }
 
 pub fn get_update_runner(
     _args: &str,
-    _matches: &clap::ArgMatches<'_>,
+    matches: &clap::ArgMatches<'_>,
 ) -> std::result::Result<(), clap::Error> {
+    if matches.is_present("refresh") {
+        match refresh_companion() {
+            Ok(_) => {
+                println!("Web Companion is up to date");
+            }
+            Err(error) => {
+                println!("{}", error);
+            }
+        }
+        return Ok(());
+    }
+
     match remove_companion() {
         Ok(_content) => {
             prepare_companion();
@@ -92,6 +114,14 @@ pub fn get_start_cmd<'a>() -> Command<'a, str> {
 pub fn get_update_cmd<'a>() -> Command<'a, str> {
     Command::new("update")
         .description("Update the companion from the server")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("refresh")
+                    .short("r")
+                    .long("refresh")
+                    .help("Download the companion only when it changed on the server"),
+            )
+        })
         .runner(|_args, matches| get_update_runner(_args, matches))
 }
 
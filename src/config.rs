@@ -33,6 +33,11 @@ pub fn get_python_env_path(idf_version: String, python_version: String) -> Strin
     )
 }
 
+/// Bare repository of ESP-IDF shared by installations created as worktrees.
+pub fn get_reference_repository_path() -> String {
+    format!("{}/idf-reference.git", get_tools_path())
+}
+
 pub fn get_selected_idf_path() -> String {
     let selected_idf_id = get_property("idfSelectedId".to_string());
     get_property_with_idf_id("path".to_string(), selected_idf_id)
@@ -115,6 +120,12 @@ pub fn update_property(property_name: String, property_value: String) {
     fs::write(get_json_path(), format!("{:#}", parsed_json)).unwrap();
 }
 
+pub fn update_json_property(property_name: &str, property_value: JsonValue) {
+    let mut parsed_json = load_json();
+    parsed_json[property_name] = property_value;
+    fs::write(get_json_path(), format!("{:#}", parsed_json)).unwrap();
+}
+
 pub fn add_idf_config(idf_path: String, version: String, python_path: String) {
     let idf_id = get_idf_id(&idf_path);
     let _data = json::object! {
@@ -130,6 +141,35 @@ pub fn add_idf_config(idf_path: String, version: String, python_path: String) {
     fs::write(get_json_path(), format!("{:#}", parsed_json)).unwrap();
 }
 
+pub fn update_idf_property(idf_id: &str, property_name: &str, property_value: JsonValue) {
+    let mut parsed_json = load_json();
+    parsed_json["idfInstalled"][idf_id][property_name] = property_value;
+    fs::write(get_json_path(), format!("{:#}", parsed_json)).unwrap();
+}
+
+/// Remove installation from configuration. When the selected installation is removed,
+/// the first remaining one is selected instead.
+pub fn remove_idf_config(idf_id: &str) -> Option<String> {
+    let mut parsed_json = load_json();
+    parsed_json["idfInstalled"].remove(idf_id);
+    if parsed_json["idfSelectedId"] == idf_id {
+        let next_idf_id = parsed_json["idfInstalled"]
+            .entries()
+            .next()
+            .map(|(next_idf_id, _)| next_idf_id.to_string())
+            .unwrap_or_default();
+        parsed_json["idfSelectedId"] = JsonValue::String(next_idf_id);
+    }
+
+    fs::write(get_json_path(), format!("{:#}", parsed_json)).unwrap();
+    let selected_idf_id = parsed_json["idfSelectedId"].to_string();
+    if selected_idf_id.is_empty() {
+        None
+    } else {
+        Some(selected_idf_id)
+    }
+}
+
 pub fn get_cmd<'a>() -> Command<'a, str> {
     Command::new("get")
         .description("Retrieve configuration")
@@ -0,0 +1,442 @@
+use clap::Arg;
+use clap_nested::Command;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::runtime::Handle;
+
+use crate::antivirus::get_active_antivirus_names;
+use crate::config::{get_json_path, get_tools_path};
+use crate::idf::environment::{find_path_conflicts, TOOLCHAIN_BINARIES};
+#[cfg(windows)]
+use crate::idf::installation::normalize_path;
+use crate::idf::python::check_python_env;
+use crate::idf::tools::{
+    get_tools_platform, parse_always_installed, parse_tool_archives, parse_tools_json, IdfTool,
+};
+use crate::idf::version::describe_idf_version;
+use crate::package::{format_size, get_existing_ancestor};
+
+/// Free space below which installation of another ESP-IDF version fails
+const MINIMAL_FREE_SPACE: u64 = 2 * 1024 * 1024 * 1024;
+/// Free space recommended for tools and build directories
+const RECOMMENDED_FREE_SPACE: u64 = 10 * 1024 * 1024 * 1024;
+
+/// Servers used by installation of ESP-IDF and its tools
+const REACHABILITY_URLS: [&str; 2] = ["https://dl.espressif.com", "https://github.com"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheckStatus {
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl CheckStatus {
+    fn get_name(&self) -> &str {
+        match self {
+            CheckStatus::Pass => "pass",
+            CheckStatus::Warn => "warn",
+            CheckStatus::Fail => "fail",
+        }
+    }
+}
+
+pub struct CheckResult {
+    pub name: String,
+    pub status: CheckStatus,
+    pub message: String,
+    /// Remediation for warn and fail
+    pub hint: Option<String>,
+}
+
+impl CheckResult {
+    fn pass(name: &str, message: &str) -> CheckResult {
+        CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Pass,
+            message: message.to_string(),
+            hint: None,
+        }
+    }
+
+    fn warn(name: &str, message: &str, hint: &str) -> CheckResult {
+        CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Warn,
+            message: message.to_string(),
+            hint: Some(hint.to_string()),
+        }
+    }
+
+    fn fail(name: &str, message: &str, hint: &str) -> CheckResult {
+        CheckResult {
+            name: name.to_string(),
+            status: CheckStatus::Fail,
+            message: message.to_string(),
+            hint: Some(hint.to_string()),
+        }
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            "name": self.name.as_str(),
+            "status": self.status.get_name(),
+            "message": self.message.as_str(),
+            "hint": self.hint.as_deref()
+        }
+    }
+}
+
+/// Configuration is read without load_json, which would create missing file.
+fn check_config() -> (CheckResult, Option<json::JsonValue>) {
+    let json_path = get_json_path();
+    let content = match fs::read_to_string(&json_path) {
+        Ok(content) => content,
+        Err(e) => {
+            let message = format!("{}: {}", json_path, e);
+            return (CheckResult::fail("config", &message, "Install ESP-IDF: idf-env idf install"), None);
+        }
+    };
+    match json::parse(&content) {
+        Ok(config) => (CheckResult::pass("config", &json_path), Some(config)),
+        Err(e) => {
+            let message = format!("{} is not valid JSON: {}", json_path, e);
+            (CheckResult::fail("config", &message, "Fix the file: idf-env config edit"), None)
+        }
+    }
+}
+
+fn check_installation(config: &json::JsonValue) -> CheckResult {
+    let selected_idf_id = config["idfSelectedId"].to_string();
+    let idf = &config["idfInstalled"][selected_idf_id.as_str()];
+    if idf.is_null() {
+        return CheckResult::fail(
+            "installation",
+            "No installation of ESP-IDF is selected",
+            "Install ESP-IDF: idf-env idf install",
+        );
+    }
+    let idf_path = idf["path"].to_string();
+    if !Path::new(&idf_path).join("tools/idf.py").exists() {
+        let message = format!("{} does not contain ESP-IDF", idf_path);
+        return CheckResult::fail("installation", &message, "Reinstall ESP-IDF: idf-env idf install --upgrade");
+    }
+    let version = describe_idf_version(&idf_path).unwrap_or_else(|| idf["version"].to_string());
+    CheckResult::pass("installation", &format!("ESP-IDF {} in {}", version, idf_path))
+}
+
+fn get_command_version(program: &str) -> Option<String> {
+    let output = std::process::Command::new(program).arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    text.lines().next().map(|line| line.trim().to_string())
+}
+
+fn check_git(config: &json::JsonValue) -> CheckResult {
+    let configured = config["gitPath"].as_str().unwrap_or("");
+    let git_path = if configured.is_empty() { "git" } else { configured };
+    match get_command_version(git_path) {
+        Some(version) => CheckResult::pass("git", &format!("{} ({})", version, git_path)),
+        None => CheckResult::fail(
+            "git",
+            &format!("{} can't be started", git_path),
+            "Install Git or configure it: idf-env config set --git <path>",
+        ),
+    }
+}
+
+fn check_python(config: &json::JsonValue) -> CheckResult {
+    let idf = &config["idfInstalled"][config["idfSelectedId"].to_string().as_str()];
+    if idf.is_null() {
+        return CheckResult::warn("python", "No installation selected", "Install ESP-IDF: idf-env idf install");
+    }
+    let python_path = idf["python"].to_string();
+    let problems = check_python_env(&idf["path"].to_string(), &python_path);
+    if problems.is_empty() {
+        let version = get_command_version(&python_path).unwrap_or_default();
+        CheckResult::pass("python", &format!("{} ({})", version, python_path))
+    } else {
+        CheckResult::fail("python", &problems.join("; "), "Repair the environment: idf-env idf python repair")
+    }
+}
+
+/// Tools which idf_tools.py installs by default and which have a download for the host, tools.json
+/// lists also optional tools and tools of other platforms.
+fn read_required_tools(idf_path: &str) -> Result<Vec<IdfTool>, Box<dyn std::error::Error + Send + Sync>> {
+    let content = fs::read_to_string(format!("{}/tools/tools.json", idf_path))?;
+    let platform = get_tools_platform();
+    let always_installed = parse_always_installed(&content, platform)?;
+    let archives = parse_tool_archives(&content, platform)?;
+    Ok(parse_tools_json(&content)?
+        .into_iter()
+        .filter(|tool| always_installed.contains(&tool.name))
+        .filter(|tool| archives.iter().any(|archive| archive.name == tool.name))
+        .collect())
+}
+
+fn check_tools(config: &json::JsonValue) -> CheckResult {
+    let idf = &config["idfInstalled"][config["idfSelectedId"].to_string().as_str()];
+    let tools = match read_required_tools(&idf["path"].to_string()) {
+        Ok(tools) => tools,
+        Err(e) => {
+            return CheckResult::warn("tools", &format!("Unable to read tools.json: {}", e), "Check the installation")
+        }
+    };
+    let missing: Vec<String> = tools
+        .iter()
+        .filter(|tool| !Path::new(&tool.get_install_path()).exists())
+        .map(|tool| format!("{} {}", tool.name, tool.version))
+        .collect();
+    if missing.is_empty() {
+        CheckResult::pass("tools", &format!("{} tools installed", tools.len()))
+    } else {
+        CheckResult::fail(
+            "tools",
+            &format!("Missing: {}", missing.join(", ")),
+            "Install tools: idf-env idf install --upgrade",
+        )
+    }
+}
+
+fn check_path_conflicts() -> CheckResult {
+    let path_entries: Vec<PathBuf> = match std::env::var_os("PATH") {
+        Some(path) => std::env::split_paths(&path).collect(),
+        None => Vec::new(),
+    };
+    let conflicts: Vec<String> = TOOLCHAIN_BINARIES
+        .iter()
+        .flat_map(|binary_name| find_path_conflicts(&path_entries, &get_tools_path(), binary_name))
+        .map(|binary| binary.display().to_string())
+        .collect();
+    if conflicts.is_empty() {
+        CheckResult::pass("path", "No foreign toolchain in PATH")
+    } else {
+        CheckResult::warn(
+            "path",
+            &format!("Toolchain outside of ESP-IDF tools: {}", conflicts.join(", ")),
+            "Remove the directories from PATH or use: idf-env idf exec -- idf.py build",
+        )
+    }
+}
+
+#[cfg(unix)]
+fn get_serial_ports() -> Vec<PathBuf> {
+    let prefixes = ["ttyUSB", "ttyACM", "cu.usbserial", "cu.SLAB_USBtoUART", "cu.usbmodem"];
+    match fs::read_dir("/dev") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| {
+                let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+                prefixes.iter().any(|prefix| name.starts_with(prefix))
+            })
+            .collect(),
+        Err(_e) => Vec::new(),
+    }
+}
+
+/// Port is accessible when it's writable by everybody or by a group of the user.
+/// Ports are not opened, opening toggles DTR and resets the board.
+#[cfg(unix)]
+fn check_serial_ports() -> CheckResult {
+    use std::os::unix::fs::MetadataExt;
+    let ports = get_serial_ports();
+    if ports.is_empty() {
+        return CheckResult::pass("serial", "No serial port connected");
+    }
+    let groups: Vec<u32> = std::process::Command::new("id")
+        .arg("-G")
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .split_whitespace()
+                .filter_map(|gid| gid.parse().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    let inaccessible: Vec<String> = ports
+        .iter()
+        .filter(|port| match fs::metadata(port) {
+            Ok(metadata) => metadata.mode() & 0o002 == 0 && !groups.contains(&metadata.gid()),
+            Err(_e) => true,
+        })
+        .map(|port| port.display().to_string())
+        .collect();
+    if inaccessible.is_empty() {
+        CheckResult::pass("serial", &format!("{} port(s) accessible", ports.len()))
+    } else {
+        CheckResult::fail(
+            "serial",
+            &format!("No permission for {}", inaccessible.join(", ")),
+            "Add the user to group of the port, e.g. sudo usermod -a -G dialout $USER, and log in again",
+        )
+    }
+}
+
+#[cfg(windows)]
+fn check_serial_ports() -> CheckResult {
+    CheckResult::pass("serial", "Serial ports don't require permissions on Windows")
+}
+
+/// Tools are considered excluded when Defender has process exclusion inside the tools directory.
+#[cfg(windows)]
+fn has_tools_exclusion() -> bool {
+    let output = std::process::Command::new("powershell")
+        .args(["-Command", "(Get-MpPreference).ExclusionProcess"])
+        .output();
+    let tools_path = normalize_path(&get_tools_path());
+    match output {
+        Ok(output) => String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| normalize_path(line.trim()).starts_with(&tools_path)),
+        Err(_e) => false,
+    }
+}
+
+#[cfg(unix)]
+fn has_tools_exclusion() -> bool {
+    false
+}
+
+fn check_antivirus() -> CheckResult {
+    let products = get_active_antivirus_names();
+    if products.is_empty() {
+        CheckResult::pass("antivirus", "No active antivirus detected")
+    } else if has_tools_exclusion() {
+        CheckResult::pass("antivirus", &format!("Tools excluded from scanning by {}", products.join(", ")))
+    } else {
+        CheckResult::warn(
+            "antivirus",
+            &format!("Active antivirus: {}", products.join(", ")),
+            "Exclude tools from scanning to speed up builds: idf-env antivirus exclusion add --all",
+        )
+    }
+}
+
+async fn fetch_head(url: String) -> Result<(), String> {
+    let client = reqwest::Client::builder()
+        .timeout(std::time::Duration::from_secs(10))
+        .build()
+        .map_err(|e| e.to_string())?;
+    match client.head(&url).send().await {
+        Ok(_response) => Ok(()),
+        Err(e) => Err(format!("{}: {}", url, e)),
+    }
+}
+
+/// Certificate and proxy problems surface as failed HTTPS request.
+fn check_network() -> CheckResult {
+    let handle = Handle::current().clone();
+    let errors: Vec<String> = REACHABILITY_URLS
+        .iter()
+        .filter_map(|url| {
+            let url = url.to_string();
+            let handle = handle.clone();
+            let th = std::thread::spawn(move || handle.block_on(fetch_head(url)));
+            th.join().unwrap_or_else(|_| Err("Request failed".to_string())).err()
+        })
+        .collect();
+    let proxy = ["HTTPS_PROXY", "https_proxy", "HTTP_PROXY", "http_proxy"]
+        .iter()
+        .find_map(|name| std::env::var(name).ok());
+    let proxy_note = match &proxy {
+        Some(proxy) => format!(" (proxy {})", proxy),
+        None => String::new(),
+    };
+    if errors.is_empty() {
+        CheckResult::pass("network", &format!("{} reachable{}", REACHABILITY_URLS.join(", "), proxy_note))
+    } else {
+        CheckResult::fail(
+            "network",
+            &format!("{}{}", errors.join("; "), proxy_note),
+            "Check HTTPS_PROXY and that the certificate authority is trusted: idf-env certificate verify --url <url>",
+        )
+    }
+}
+
+fn check_disk_space() -> CheckResult {
+    let tools_path = get_tools_path();
+    let available = match fs2::available_space(get_existing_ancestor(Path::new(&tools_path))) {
+        Ok(available) => available,
+        Err(e) => return CheckResult::warn("disk", &format!("Unable to determine free space: {}", e), "Check the disk"),
+    };
+    let message = format!("{} available for {}", format_size(available), tools_path);
+    let hint = "Free disk space or remove unused versions: idf-env idf uninstall <version>";
+    if available < MINIMAL_FREE_SPACE {
+        CheckResult::fail("disk", &message, hint)
+    } else if available < RECOMMENDED_FREE_SPACE {
+        CheckResult::warn("disk", &message, hint)
+    } else {
+        CheckResult::pass("disk", &message)
+    }
+}
+
+pub fn run_checks() -> Vec<CheckResult> {
+    let (config_result, config) = check_config();
+    let mut results = vec![config_result];
+    if let Some(config) = config {
+        let installation_result = check_installation(&config);
+        let has_installation = installation_result.status == CheckStatus::Pass;
+        results.push(installation_result);
+        results.push(check_git(&config));
+        if has_installation {
+            results.push(check_python(&config));
+            results.push(check_tools(&config));
+        }
+    }
+    results.push(check_path_conflicts());
+    results.push(check_serial_ports());
+    results.push(check_antivirus());
+    results.push(check_network());
+    results.push(check_disk_space());
+    results
+}
+
+pub fn print_results(results: &[CheckResult]) {
+    for result in results.iter() {
+        println!("[{}] {}: {}", result.status.get_name().to_uppercase(), result.name, result.message);
+        if let Some(hint) = &result.hint {
+            println!("       {}", hint);
+        }
+    }
+    let count = |status: CheckStatus| results.iter().filter(|result| result.status == status).count();
+    println!(
+        "{} passed, {} warnings, {} failed",
+        count(CheckStatus::Pass),
+        count(CheckStatus::Warn),
+        count(CheckStatus::Fail)
+    );
+}
+
+fn get_doctor_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    let results = run_checks();
+    if matches.is_present("json") {
+        let checks: Vec<json::JsonValue> = results.iter().map(|result| result.to_json()).collect();
+        println!("{:#}", json::object! { "version": env!("CARGO_PKG_VERSION"), "os": std::env::consts::OS, "checks": checks });
+    } else {
+        print_results(&results);
+    }
+    Ok(())
+}
+
+pub fn get_cmd<'a>() -> Command<'a, str> {
+    Command::new("doctor")
+        .description("Check health of ESP-IDF environment")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("json")
+                    .long("json")
+                    .help("Emit the report as JSON, e.g. for support ticket"),
+            )
+        })
+        .runner(|_args, matches| get_doctor_runner(_args, matches))
+}
@@ -4,7 +4,8 @@ use clap_nested::{Command, Commander, MultiCommand};
 use std::collections::HashMap;
 
 use crate::config;
-use crate::package::prepare_package;
+use crate::package::SignaturePolicy;
+use crate::plan::{get_dry_run_arg, get_json_arg, run_plan, InstallPlan, InstallStep};
 
 #[cfg(windows)]
 use core::ptr::null_mut;
@@ -12,9 +13,14 @@ use core::ptr::null_mut;
 #[cfg(windows)]
 pub mod windows;
 
+#[cfg(windows)]
+use std::path::Path;
 use std::{thread, time};
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
+/// Drivers supported by install and download commands, names match their flags
+pub const DRIVER_NAMES: [&str; 4] = ["silabs", "ftdi", "espressif", "wch"];
+
 #[cfg(unix)]
 pub fn get_driver_property(_property_name: String, _filter: String) -> Result<()> {
     Ok(())
@@ -92,12 +98,15 @@ pub fn get_cmd<'a>() -> Command<'a, str> {
 }
 
 #[cfg(unix)]
-pub fn install_driver(driver_inf: String) {}
+pub fn install_driver(_driver_inf: String) -> Result<()> {
+    Ok(())
+}
 
 use widestring::WideCString;
 
+/// Install driver from INF, driver which is already in the driver store is not an error.
 #[cfg(windows)]
-pub fn install_driver(driver_inf: String) {
+pub fn install_driver(driver_inf: String) -> Result<()> {
     print!("Installing driver with INF {} ", driver_inf);
     
     match install_driver_res(driver_inf) {
@@ -114,6 +123,7 @@ pub fn install_driver(driver_inf: String) {
             } else {
                 println!("{}", msg);
             }
+            Ok(())
         }
         Err(e) => {
             let error_str = e.to_string();
@@ -124,10 +134,14 @@ pub fn install_driver(driver_inf: String) {
                 }
             }
             print!("... ");
-            println!("{}", error_str.split(" -> ").next().unwrap_or(&error_str));
+            let message = error_str.split(" -> ").next().unwrap_or(&error_str);
+            println!("{}", message);
+            if message == "Already installed" {
+                return Ok(());
+            }
+            Err(message.to_string().into())
         }
     }
-    
 }
 
 #[cfg(windows)]
@@ -189,11 +203,41 @@ pub fn install_driver_res(driver_inf: String) -> Result<String> {
     Ok("Unix not supported".to_string())
 }
 
+/// Start idf-env again with elevated privileges and the same arguments followed by the given one.
+#[cfg(windows)]
+pub fn run_self_elevated(argument: String) -> Result<()> {
+    windows::run_self_elevated_with_extra_argument(argument)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+pub fn run_self_elevated(_argument: String) -> Result<()> {
+    Err("Elevation is supported only on Windows".into())
+}
+
+#[cfg(windows)]
+fn is_app_elevated() -> bool {
+    windows::is_app_elevated()
+}
+
+/// Drivers are installed only on Windows, elsewhere the plan is only displayed with all steps.
+#[cfg(unix)]
+fn is_app_elevated() -> bool {
+    true
+}
+
 #[cfg(unix)]
 fn get_install_runner(
     _args: &str,
-    _matches: &clap::ArgMatches<'_>,
+    matches: &clap::ArgMatches<'_>,
 ) -> std::result::Result<(), clap::Error> {
+    // Drivers are installed only on Windows, the plan can be still displayed
+    if matches.is_present("dry-run") {
+        if let Err(e) = run_plan(&get_install_plan(matches), matches) {
+            println!("Installation failed: {}", e);
+            std::process::exit(1);
+        }
+    }
     Ok(())
 }
 
@@ -202,6 +246,142 @@ pub fn get_driver_path(driver_name: String) -> String {
     format!("{}/{}", drivers_path, driver_name)
 }
 
+/// Drivers selected by flags of install and download commands
+fn get_selected_drivers<'a>(matches: &clap::ArgMatches<'_>) -> Vec<&'a str> {
+    DRIVER_NAMES
+        .iter()
+        .filter(|name| matches.is_present(name))
+        .copied()
+        .collect()
+}
+
+fn get_download_plan(drivers: &[&str]) -> InstallPlan {
+    let mut plan = InstallPlan::new("Download drivers");
+    let packages = [
+        (
+            "silabs",
+            "https://dl.espressif.com/dl/idf-installer/CP210x_Universal_Windows_Driver.zip",
+            "cp210x.zip",
+            "silabs-2021-05-03",
+        ),
+        (
+            "ftdi",
+            "https://dl.espressif.com/dl/idf-installer/CDM_v2.12.28_WHQL_Certified.zip",
+            "ftdi.zip",
+            "ftdi-2021-05-03",
+        ),
+        (
+            "espressif",
+            "https://dl.espressif.com/dl/idf-driver/idf-driver-esp32-usb-jtag-2021-07-15.zip",
+            "idf-driver-esp32-usb-jtag-2021-07-15.zip",
+            "idf-driver-esp32-usb-jtag-2021-07-15",
+        ),
+        (
+            "wch",
+            "https://dl.espressif.com/dl/idf-installer/CH341SER.ZIP",
+            "whc-ch343ser.zip",
+            "whc-ch343ser-2022-08-02",
+        ),
+    ];
+    for (name, url, archive, directory) in packages.iter() {
+        if drivers.contains(name) {
+            plan.add(InstallStep::Package {
+                url: url.to_string(),
+                archive: archive.to_string(),
+                destination: get_driver_path(directory.to_string()),
+                strip_prefix: None,
+                policy: SignaturePolicy::Required,
+                refresh: false,
+                sha256: None,
+            });
+        }
+    }
+    plan
+}
+
+fn get_driver_plan(drivers: &[&str]) -> InstallPlan {
+    let mut plan = InstallPlan::new("Install drivers");
+    let infs = [
+        ("silabs", "silabs-2021-05-03/silabser.inf"),
+        ("ftdi", "ftdi-2021-05-03/ftdiport.inf"),
+        (
+            "espressif",
+            "idf-driver-esp32-usb-jtag-2021-07-15/usb_jtag_debug_unit.inf",
+        ),
+        ("wch", "whc-ch343ser-2022-08-02/CH343SER/Driver/CH343SER.INF"),
+    ];
+    for (name, inf) in infs.iter() {
+        if drivers.contains(name) {
+            plan.add(InstallStep::Driver {
+                inf: get_driver_path(inf.to_string()),
+            });
+        }
+    }
+    plan
+}
+
+fn get_install_plan(matches: &clap::ArgMatches<'_>) -> InstallPlan {
+    let mut plan = InstallPlan::new("Drivers");
+    let drivers = get_selected_drivers(matches);
+    // Download drivers, if app is self-elevated this flag serves to avoid downloading in elevated mode.
+    if !matches.is_present("no-download") {
+        plan.append(get_download_plan(&drivers));
+    }
+    if is_app_elevated() {
+        plan.append(get_driver_plan(&drivers));
+    } else {
+        // Elevated instance installs drivers from the files downloaded by this one
+        plan.add(InstallStep::Elevate {
+            argument: "--no-download".to_string(),
+        });
+    }
+    plan
+}
+
+/// Drivers are considered installed when their package was downloaded and extracted.
+#[cfg(windows)]
+fn get_missing_drivers(drivers: &[String]) -> Vec<&str> {
+    drivers
+        .iter()
+        .map(|name| name.as_str())
+        .filter(|name| {
+            get_download_plan(&[name]).steps.iter().any(|step| match step {
+                InstallStep::Package { destination, .. } => !Path::new(destination).exists(),
+                _ => false,
+            })
+        })
+        .collect()
+}
+
+/// Plan of drivers missing on the system, drivers are installed only on Windows.
+#[cfg(unix)]
+pub fn get_sync_plan(_drivers: &[String]) -> InstallPlan {
+    InstallPlan::new("Drivers")
+}
+
+/// Plan of drivers missing on the system. Installation requires elevated privileges,
+/// otherwise the plan contains only the hint how to install them.
+#[cfg(windows)]
+pub fn get_sync_plan(drivers: &[String]) -> InstallPlan {
+    let mut plan = InstallPlan::new("Drivers");
+    let missing_drivers = get_missing_drivers(drivers);
+    if missing_drivers.is_empty() {
+        return plan;
+    }
+    if is_app_elevated() {
+        plan.append(get_download_plan(&missing_drivers));
+        plan.append(get_driver_plan(&missing_drivers));
+    } else {
+        plan.add(InstallStep::Skip {
+            reason: format!(
+                "Installation of drivers requires elevated privileges, run: idf-env driver install --{}",
+                missing_drivers.join(" --")
+            ),
+        });
+    }
+    plan
+}
+
 #[cfg(unix)]
 pub fn download_drivers(
     _args: &str,
@@ -213,126 +393,28 @@ pub fn download_drivers(
 #[cfg(windows)]
 pub fn download_drivers(
     _args: &str,
-    _matches: &clap::ArgMatches<'_>,
+    matches: &clap::ArgMatches<'_>,
 ) -> std::result::Result<(), clap::Error> {
-    if _matches.is_present("silabs") {
-        match prepare_package(
-            "https://dl.espressif.com/dl/idf-installer/CP210x_Universal_Windows_Driver.zip"
-                .to_string(),
-            "cp210x.zip",
-            get_driver_path("silabs-2021-05-03".to_string()),
-        ) {
-            Ok(_) => {
-                println!("Ok");
-            }
-            Err(_e) => {
-                println!("Failed");
-            }
-        }
-    }
-    if _matches.is_present("ftdi") {
-        match prepare_package(
-            "https://dl.espressif.com/dl/idf-installer/CDM_v2.12.28_WHQL_Certified.zip".to_string(),
-            "ftdi.zip",
-            get_driver_path("ftdi-2021-05-03".to_string()),
-        ) {
-            Ok(_) => {
-                println!("Ok");
-            }
-            Err(_e) => {
-                println!("Failed");
-            }
-        }
+    if let Err(e) = run_plan(&get_download_plan(&get_selected_drivers(matches)), matches) {
+        println!("Download failed: {}", e);
+        std::process::exit(1);
     }
-    if _matches.is_present("espressif") {
-        match prepare_package(
-            "https://dl.espressif.com/dl/idf-driver/idf-driver-esp32-usb-jtag-2021-07-15.zip"
-                .to_string(),
-            "idf-driver-esp32-usb-jtag-2021-07-15.zip",
-            get_driver_path("idf-driver-esp32-usb-jtag-2021-07-15".to_string()),
-        ) {
-            Ok(_) => {
-                println!("Ok");
-            }
-            Err(_e) => {
-                println!("Failed");
-            }
-        }
-    }
-    if _matches.is_present("wch") {
-        match prepare_package(
-            "https://dl.espressif.com/dl/idf-installer/CH341SER.ZIP".to_string(),
-            "whc-ch343ser.zip",
-            get_driver_path("whc-ch343ser-2022-08-02".to_string()),
-        ) {
-            Ok(_) => {
-                println!("Ok");
-            }
-            Err(_e) => {
-                println!("Failed");
-            }
-        }
-    }
-
     Ok(())
 }
 
 #[cfg(windows)]
 fn get_install_runner(
     _args: &str,
-    _matches: &clap::ArgMatches<'_>,
+    matches: &clap::ArgMatches<'_>,
 ) -> std::result::Result<(), clap::Error> {
-    // Download drivers, if app is self-elevated this flag serves to avoid downloading in elevated mode.
-    if !_matches.is_present("no-download") {
-        match download_drivers(_args, _matches) {
-            Ok(_) => {
-                println!("Ok");
-            }
-            Err(_e) => {
-                println!("Failed");
-            }
-        }
+    let result = run_plan(&get_install_plan(matches), matches);
+    if is_app_elevated() && matches.is_present("wait") && !matches.is_present("dry-run") {
+        println!("Process finished...");
+        thread::sleep(time::Duration::from_millis(100000));
     }
-
-    if windows::is_app_elevated() {
-        if _matches.is_present("silabs") {
-            install_driver(get_driver_path(
-                "silabs-2021-05-03/silabser.inf".to_string(),
-            ));
-        }
-
-        if _matches.is_present("ftdi") {
-            install_driver(get_driver_path("ftdi-2021-05-03/ftdiport.inf".to_string()));
-        }
-
-        if _matches.is_present("espressif") {
-            install_driver(get_driver_path(
-                "idf-driver-esp32-usb-jtag-2021-07-15/usb_jtag_debug_unit.inf".to_string(),
-            ));
-        }
-
-        if _matches.is_present("wch") {
-            install_driver(get_driver_path(
-                "whc-ch343ser-2022-08-02/CH343SER/Driver/CH343SER.INF".to_string(),
-            ));
-        }
-
-        if _matches.is_present("wait") {
-            println!("Process finished...");
-            thread::sleep(time::Duration::from_millis(100000));
-        }
-    } else {
-        if !windows::is_app_elevated() {
-            match windows::run_self_elevated_with_extra_argument("--no-download".to_string()) {
-                Ok(_) => {
-                    println!("Ok");
-                }
-                Err(_e) => {
-                    println!("Failed");
-                }
-            }
-            return Ok(());
-        }
+    if let Err(e) = result {
+        println!("Installation failed: {}", e);
+        std::process::exit(1);
     }
     Ok(())
 }
@@ -384,6 +466,8 @@ pub fn get_install_cmd<'a>() -> Command<'a, str> {
                     .takes_value(false)
                     .help("display diagnostic log after installation"),
             )
+            .arg(get_dry_run_arg())
+            .arg(get_json_arg())
         })
         .runner(|_args, matches| get_install_runner(_args, matches))
 }
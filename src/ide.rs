@@ -4,7 +4,8 @@ use clap_nested::{Command, Commander, MultiCommand};
 use std::fs::File;
 use std::io::{self, BufRead, Write};
 
-use crate::package::prepare_package_strip_prefix;
+use crate::package::SignaturePolicy;
+use crate::plan::{get_dry_run_arg, get_json_arg, run_plan, InstallPlan, InstallStep};
 use std::path::Path;
 
 const DEFAULT_IDE_URL: &str =
@@ -18,20 +19,36 @@ struct Ide {
     prefix: String,
 }
 
-fn install_ide(ide: &Ide) {
-    match prepare_package_strip_prefix(
-        &ide.dist_url,
-        &ide.dist_file,
-        ide.destination_dir.clone(),
-        &ide.prefix,
-    ) {
-        Ok(_) => {
-            println!("Ok");
-        }
-        Err(_e) => {
-            println!("Failed");
-        }
+fn get_install_plan(ide: &Ide) -> InstallPlan {
+    let mut plan = InstallPlan::new("Espressif-IDE");
+    plan.add(InstallStep::Package {
+        url: ide.dist_url.clone(),
+        archive: ide.dist_file.clone(),
+        destination: ide.destination_dir.clone(),
+        strip_prefix: Some(ide.prefix.clone()),
+        policy: SignaturePolicy::Required,
+        refresh: false,
+        sha256: None,
+    });
+    plan
+}
+
+/// Plan of Espressif-IDE installation, the plan is empty when the destination already exists.
+pub fn get_sync_plan(url: Option<&str>, destination: &str) -> InstallPlan {
+    if Path::new(destination).exists() {
+        return InstallPlan::new("Espressif-IDE");
     }
+    let dist_url = url.unwrap_or(DEFAULT_IDE_URL);
+    let dist_file = match url {
+        Some(url) => url.rsplit('/').next().unwrap_or(DEFAULT_IDE_FILE),
+        None => DEFAULT_IDE_FILE,
+    };
+    get_install_plan(&Ide {
+        dist_url: dist_url.to_string(),
+        dist_file: dist_file.to_string(),
+        destination_dir: destination.to_string(),
+        prefix: "Espressif-IDE".to_string(),
+    })
 }
 
 fn get_install_runner(
@@ -45,7 +62,10 @@ fn get_install_runner(
         prefix: "Espressif-IDE".to_string(),
     };
 
-    install_ide(&ide);
+    if let Err(e) = run_plan(&get_install_plan(&ide), matches) {
+        println!("Installation failed: {}", e);
+        std::process::exit(1);
+    }
     Ok(())
 }
 
@@ -149,6 +169,8 @@ pub fn get_install_cmd<'a>() -> Command<'a, str> {
                     .help("Location where Espressif-IDE should be deployed")
                     .takes_value(true),
             )
+            .arg(get_dry_run_arg())
+            .arg(get_json_arg())
         })
         .runner(|_args, matches| get_install_runner(_args, matches))
 }
@@ -2,154 +2,223 @@ use clap::Arg;
 use clap_nested::{Command, Commander, MultiCommand};
 use git2::Repository;
 use std::path::Path;
-use tokio::runtime::Handle;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
-use std::env;
-
 use std::io::Read;
 use std::process::Stdio;
 
-use std::time::Instant;
-
-use crate::config::get_selected_idf_path;
 use crate::config::get_tools_path;
 use crate::config::{
-    add_idf_config, get_dist_path, get_git_path, get_python_env_path, get_tool_path,
+    add_idf_config, get_dist_path, get_idf_id, get_tool_path, load_json, update_idf_property,
     update_property,
 };
-use crate::package::prepare_package;
-use crate::shell::run_command;
+#[cfg(windows)]
+use crate::package::{prepare_package, SignaturePolicy};
+
+mod benchmark;
+mod discover;
+pub mod environment;
+mod exec;
+pub mod installation;
+mod matrix;
+pub mod python;
+pub mod mirror;
+mod new;
+pub mod release;
+pub mod reset;
+pub mod status;
+pub mod submodule;
+pub mod switch;
+pub mod tools;
+mod uninstall;
+pub mod version;
+pub mod worktree;
+use mirror::{change_submodules_mirror, MirrorProfile};
+use python::prepare_python_env;
+use release::{get_remote_releases, IDF_REPOSITORY_URL};
+use submodule::{update_submodules_parallel, UpdateOptions};
+use version::describe_idf_version;
+
+const DEFAULT_IDF_VERSION: &str = "master";
 
-async fn excecute_async(command: String, arguments: Vec<String>) {
-    let _child_process = tokio::process::Command::new(command)
-        .args(arguments)
-        .status()
-        .await;
-}
-
-fn execute_command(command: String, arguments: Vec<String>) -> Result<()> {
-    let argument_string = arguments
-        .clone()
-        .into_iter()
-        .map(|i| format!("{} ", i.to_string()))
-        .collect::<String>();
-    println!("Executing: {} {}", command, argument_string);
-    let handle = Handle::current().clone();
-    let th = std::thread::spawn(move || handle.block_on(excecute_async(command, arguments)));
-    th.join().unwrap();
-    Ok(())
+#[cfg(windows)]
+fn get_idf_base_directory() -> String {
+    "C:/esp".to_string()
 }
 
-fn reset_repository(repository_path: String) -> Result<()> {
-    let idf_path = Path::new(&repository_path);
-    assert!(env::set_current_dir(&idf_path).is_ok());
-    println!("Working directory: {}", idf_path.display());
+#[cfg(unix)]
+fn get_idf_base_directory() -> String {
+    dirs::home_dir().unwrap().display().to_string() + "/esp"
+}
 
-    let git_path = get_git_path();
-    let mut arguments: Vec<String> = [].to_vec();
-    arguments.push("reset".to_string());
-    arguments.push("--hard".to_string());
-    assert!(execute_command(git_path, arguments).is_ok());
-
-    let mut arguments_submodule: Vec<String> = [].to_vec();
-    arguments_submodule.push("submodule".to_string());
-    arguments_submodule.push("foreach".to_string());
-    arguments_submodule.push("git".to_string());
-    arguments_submodule.push("reset".to_string());
-    arguments_submodule.push("--hard".to_string());
-    assert!(execute_command(get_git_path(), arguments_submodule).is_ok());
-
-    let mut arguments_clean: Vec<String> = [].to_vec();
-    arguments_clean.push("clean".to_string());
-    arguments_clean.push("force".to_string());
-    arguments_clean.push("-d".to_string());
-    assert!(execute_command(get_git_path(), arguments_clean).is_ok());
-
-    let mut arguments_status: Vec<String> = [].to_vec();
-    arguments_status.push("status".to_string());
-    assert!(execute_command(get_git_path(), arguments_status).is_ok());
+#[cfg(windows)]
+fn get_esp_idf_directory(idf_name: String) -> String {
+    format!("{}/{}", get_idf_base_directory(), idf_name).replace("/", "\\")
+}
 
+#[cfg(unix)]
+fn get_esp_idf_directory(idf_name: String) -> String {
+    format!("{}/{}", get_idf_base_directory(), idf_name)
+}
+
+/// Run process and report failure exit code. Output is displayed only in verbose mode.
+fn run_process(program: &str, arguments: &[String], verbose: bool) -> Result<()> {
+    println!("Executing: {} {}", program, arguments.join(" "));
+    let mut command = std::process::Command::new(program);
+    command.args(arguments);
+    if !verbose {
+        command.stdout(Stdio::null()).stderr(Stdio::piped());
+    }
+    let output = command.output()?;
+    if !output.status.success() {
+        return Err(format!(
+            "{} failed with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
     Ok(())
 }
 
-fn update_submodule(
-    idf_path: String,
-    submodule: String,
-    depth: String,
-    progress: bool,
-) -> Result<()> {
-    let mut arguments_submodule: Vec<String> = [].to_vec();
-    arguments_submodule.push("-C".to_string());
-    arguments_submodule.push(idf_path);
-    arguments_submodule.push("submodule".to_string());
-    arguments_submodule.push("update".to_string());
-    arguments_submodule.push("--depth".to_string());
-    arguments_submodule.push(depth);
-    if progress {
-        arguments_submodule.push("--progress".to_string());
+/// Clone with full history, the version of ESP-IDF is derived by git describe from the checkout.
+fn clone_repository(git_path: &str, idf_version: &str, idf_path: &str, verbose: bool) -> Result<()> {
+    let arguments = vec![
+        "clone".to_string(),
+        "--jobs".to_string(),
+        "8".to_string(),
+        "--recursive".to_string(),
+        "--branch".to_string(),
+        idf_version.to_string(),
+        IDF_REPOSITORY_URL.to_string(),
+        idf_path.to_string(),
+    ];
+    run_process(git_path, &arguments, verbose)
+}
+
+fn run_git(git_path: &str, idf_path: &str, arguments: &[&str], verbose: bool) -> Result<()> {
+    let mut full_arguments = vec!["-C".to_string(), idf_path.to_string()];
+    full_arguments.extend(arguments.iter().map(|a| a.to_string()));
+    run_process(git_path, &full_arguments, verbose)
+}
+
+/// Check out tag or branch in existing repository, branches are fast-forwarded to the remote state.
+/// Submodules are not updated. With force local modifications of tracked files are discarded.
+fn checkout_version(git_path: &str, idf_path: &str, idf_version: &str, force: bool, verbose: bool) -> Result<()> {
+    let git = |arguments: &[&str]| run_git(git_path, idf_path, arguments, verbose);
+
+    git(&["fetch", "--tags", "origin"])?;
+    if force {
+        git(&["checkout", "--force", idf_version])?;
+    } else {
+        git(&["checkout", idf_version])?;
+    }
+    let repo = Repository::open(idf_path)?;
+    let remote_branch = format!("refs/remotes/origin/{}", idf_version);
+    if repo.find_reference(&remote_branch).is_ok() {
+        git(&["merge", "--ff-only", &format!("origin/{}", idf_version)])?;
     }
-    arguments_submodule.push("--recommend-shallow".to_string());
-    arguments_submodule.push("--recursive".to_string());
-    arguments_submodule.push(submodule);
-    assert!(execute_command(get_git_path(), arguments_submodule).is_ok());
     Ok(())
 }
 
-fn get_reset_cmd<'a>() -> Command<'a, str> {
-    Command::new("reset")
-        .description("Reset ESP-IDF git repository to initial state and wipe out modified data")
-        .options(|app| {
-            app.arg(
-                Arg::with_name("idf-path")
-                    .short("d")
-                    .long("idf-path")
-                    .help("Path to existing ESP-IDF")
-                    .takes_value(true),
-            )
-        })
-        .runner(|_args, matches| {
-            if matches.value_of("idf-path").is_some() {
-                let dir = matches.value_of("idf-path").unwrap();
-                assert!(reset_repository(dir.to_string()).is_ok());
-            }
-            Ok(())
-        })
+/// Sync and update submodules recursively. With mirror, submodules are pointed to the mirror
+/// before they are fetched.
+fn update_submodules(git_path: &str, idf_path: &str, mirror: Option<&MirrorProfile>, force: bool) -> Result<()> {
+    if let Some(mirror) = mirror {
+        change_submodules_mirror(&mut Repository::open(idf_path)?, mirror);
+    }
+    let options = UpdateOptions {
+        git_path: git_path.to_string(),
+        force,
+        ..UpdateOptions::default()
+    };
+    let hook = |submodule_repo: &mut Repository| {
+        if let Some(mirror) = mirror {
+            change_submodules_mirror(submodule_repo, mirror);
+        }
+    };
+    let report = update_submodules_parallel(idf_path, &options, Some(&hook))?;
+    report.print_summary();
+    if !report.get_failed().is_empty() {
+        return Err("Update of submodules failed".into());
+    }
+    Ok(())
 }
 
-#[cfg(windows)]
-fn get_idf_base_directory() -> String {
-    "C:/esp".to_string()
+/// Delegate installation to ESP-IDF Tools Installer for Windows (Inno Setup based).
+fn run_installer(installer: &str, idf_version: &str, idf_path: &str, interactive: bool, verbose: bool) -> Result<()> {
+    let log_path = format!("{}/idf-installer.log", get_tools_path());
+    let mut arguments = vec![
+        format!("/IDFVERSION={}", idf_version),
+        format!("/IDFDIR={}", idf_path),
+        format!("/LOG={}", log_path),
+    ];
+    if !interactive {
+        arguments.push("/VERYSILENT".to_string());
+        arguments.push("/SUPPRESSMSGBOXES".to_string());
+        arguments.push("/SP-".to_string());
+    }
+    println!("Starting installer: {} {}", installer, arguments.join(" "));
+    let status = std::process::Command::new(installer).args(&arguments).status()?;
+    if verbose {
+        match std::fs::read_to_string(&log_path) {
+            Ok(log) => println!("{}", log),
+            Err(_e) => println!("Installer log not found: {}", log_path),
+        }
+    }
+    if !status.success() {
+        return Err(format!("Installer failed with {}, log: {}", status, log_path).into());
+    }
+    Ok(())
 }
 
-#[cfg(unix)]
-fn get_idf_base_directory() -> String {
-    dirs::home_dir().unwrap().display().to_string() + "/esp"
+/// Version recorded in esp_idf.json, e.g. v5.1.2 or v5.2-dev-1234-gabcdef
+fn get_version_name(idf_path: &str) -> Result<String> {
+    describe_idf_version(idf_path)
+        .ok_or_else(|| format!("Unable to determine version of ESP-IDF in {}", idf_path).into())
 }
 
-#[cfg(windows)]
-fn get_esp_idf_directory(idf_name: String) -> String {
-    format!("{}/{}", get_idf_base_directory(), idf_name).replace("/", "\\")
+/// Run command of tools/idf_tools.py, e.g. install or install-python-env
+pub fn run_idf_tools(python_path: &str, esp_idf: &str, command: &[&str], verbose: bool) -> Result<()> {
+    let mut arguments: Vec<String> = [].to_vec();
+    arguments.push(format!("{}/tools/idf_tools.py", esp_idf));
+    arguments.extend(command.iter().map(|argument| argument.to_string()));
+    run_process(python_path, &arguments, verbose)
 }
 
-#[cfg(unix)]
-fn get_esp_idf_directory(idf_name: String) -> String {
-    format!("{}/{}", get_idf_base_directory(), idf_name)
+/// Install tools of ESP-IDF, only tools for the targets are installed when targets are specified.
+pub fn install_idf_tools(python_path: &str, esp_idf: &str, targets: &[String], verbose: bool) -> Result<()> {
+    if targets.is_empty() {
+        run_idf_tools(python_path, esp_idf, &["install"], verbose)
+    } else {
+        let targets_argument = format!("--targets={}", targets.join(","));
+        run_idf_tools(python_path, esp_idf, &["install", &targets_argument], verbose)
+    }
 }
 
-fn get_install_runner(
-    _args: &str,
-    _matches: &clap::ArgMatches<'_>,
-) -> std::result::Result<(), clap::Error> {
-    let esp_idf = get_esp_idf_directory("esp-idf-master/".to_string());
-    println!("ESP-IDF Path: {}", esp_idf);
+/// Default installation directory of the version, e.g. ~/esp/esp-idf-release-v5.1
+pub fn get_default_idf_path(idf_version: &str) -> String {
+    get_esp_idf_directory(format!("esp-idf-{}", idf_version.replace('/', "-")))
+}
 
+/// Clone or upgrade ESP-IDF, install its tools and register the installation as selected one.
+/// Returns id of the installation.
+pub fn install_idf_version(
+    idf_version: &str,
+    esp_idf: &str,
+    targets: &[String],
+    shared: bool,
+    upgrade: bool,
+    verbose: bool,
+) -> Result<String> {
     #[cfg(windows)]
     match prepare_package(
         "https://dl.espressif.com/dl/idf-git/idf-git-2.30.1-win64.zip".to_string(),
         get_dist_path("idf-git-2.30.1-win64.zip").as_str(),
         get_tool_path("idf-git/2.30.1".to_string()),
+        SignaturePolicy::Required,
     ) {
         Ok(_) => {
             println!("Ok");
@@ -163,6 +232,7 @@ fn get_install_runner(
         "https://dl.espressif.com/dl/idf-python/idf-python-3.8.7-embed-win64.zip".to_string(),
         get_dist_path("idf-python-3.8.7-embed-win64.zip").as_str(),
         get_tool_path("idf-python/3.8.7".to_string()),
+        SignaturePolicy::Required,
     ) {
         Ok(_) => {
             println!("Ok");
@@ -179,82 +249,64 @@ fn get_install_runner(
 
     update_property("gitPath".to_string(), git_path.clone());
 
-    #[cfg(windows)]
-    let python_path = get_tool_path("idf-python/3.8.7/python.exe".to_string());
-    #[cfg(unix)]
-    let python_path = "/usr/bin/python".to_string();
-
-    let virtual_env_path = get_python_env_path("4.4".to_string(), "3.8".to_string());
-
-    if !Path::new(&esp_idf).exists() {
-        // let clone_command = format!("git clone --shallow-since=2020-01-01 --jobs 8 --recursive git@github.com:espressif/esp-idf.git ");
-        let mut arguments: Vec<String> = [].to_vec();
-        arguments.push("clone".to_string());
-        arguments.push("--shallow-since=2020-01-01".to_string());
-        arguments.push("--jobs".to_string());
-        arguments.push("8".to_string());
-        arguments.push("--recursive".to_string());
-        arguments.push("https://github.com/espressif/esp-idf.git".to_string());
-        // arguments.push("git@github.com:espressif/esp-idf.git".to_string());
-        arguments.push(esp_idf.clone());
-        println!("Cloning: {} {:?}", git_path, arguments);
-        match run_command(git_path, arguments, "".to_string()) {
-            Ok(_) => {
-                println!("Ok");
-            }
-            Err(_e) => {
-                println!("Failed");
-            }
-        }
+    if !Path::new(esp_idf).exists() && shared {
+        println!("Creating ESP-IDF {} from shared repository", idf_version);
+        worktree::add_worktree(&git_path, idf_version, esp_idf, verbose)?;
+    } else if !Path::new(esp_idf).exists() {
+        println!("Cloning ESP-IDF {}", idf_version);
+        clone_repository(&git_path, idf_version, esp_idf, verbose)?;
+    } else if upgrade {
+        println!("Upgrading ESP-IDF in {} to {}", esp_idf, idf_version);
+        checkout_version(&git_path, esp_idf, idf_version, false, verbose)?;
+        update_submodules(&git_path, esp_idf, None, false)?;
+    } else {
+        println!("Using existing ESP-IDF: {}", esp_idf);
     }
 
-    if !Path::new(&virtual_env_path).exists() {
-        println!("Creating virtual environment: {}", virtual_env_path);
-        let mut arguments: Vec<String> = [].to_vec();
-        arguments.push("-m".to_string());
-        arguments.push("virtualenv".to_string());
-        arguments.push(virtual_env_path.clone());
-        match run_command(python_path, arguments, "".to_string()) {
-            Ok(_) => {
-                println!("Ok");
-            }
-            Err(_e) => {
-                println!("Failed");
-            }
-        }
+    let python_path = prepare_python_env(esp_idf, None, false, verbose)?;
+    install_idf_tools(&python_path, esp_idf, targets, verbose)?;
+
+    let version_name = get_version_name(esp_idf)?;
+    println!("Registering ESP-IDF {} in {}", version_name, esp_idf);
+    add_idf_config(esp_idf.to_string(), version_name, python_path);
+    let idf_id = get_idf_id(esp_idf);
+    if !targets.is_empty() {
+        update_idf_property(&idf_id, "targets", targets.to_vec().into());
     }
-    #[cfg(windows)]
-    let python_path = format!("{}/Scripts/python.exe", virtual_env_path);
-    #[cfg(unix)]
-    let python_path = format!("{}/bin/python", virtual_env_path);
+    Ok(idf_id)
+}
 
-    let idf_tools = format!("{}/tools/idf_tools.py", esp_idf);
+fn install_idf(matches: &clap::ArgMatches<'_>) -> Result<()> {
+    let idf_version = matches.value_of("idf-version").unwrap_or(DEFAULT_IDF_VERSION);
+    let esp_idf = match matches.value_of("idf-path") {
+        Some(idf_path) => idf_path.to_string(),
+        None => get_default_idf_path(idf_version),
+    };
+    let verbose = matches.is_present("verbose");
+    println!("ESP-IDF Path: {}", esp_idf);
 
-    let mut arguments: Vec<String> = [].to_vec();
-    arguments.push(idf_tools.clone());
-    arguments.push("install".to_string());
-    match run_command(python_path.clone(), arguments, "".to_string()) {
-        Ok(_) => {
-            println!("Ok");
-        }
-        Err(_e) => {
-            println!("Failed");
-        }
+    if let Some(installer) = matches.value_of("installer") {
+        return run_installer(installer, idf_version, &esp_idf, matches.is_present("interactive"), verbose);
     }
 
-    let mut arguments: Vec<String> = [].to_vec();
-    arguments.push(idf_tools);
-    arguments.push("install-python-env".to_string());
-    match run_command(python_path.clone(), arguments, "".to_string()) {
-        Ok(_) => {
-            println!("Ok");
-        }
-        Err(_e) => {
-            println!("Failed");
-        }
-    }
+    install_idf_version(
+        idf_version,
+        &esp_idf,
+        &[],
+        matches.is_present("shared"),
+        matches.is_present("upgrade"),
+        verbose,
+    )?;
+    Ok(())
+}
 
-    add_idf_config(esp_idf, "4.4".to_string(), python_path);
+fn get_install_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    if let Err(e) = install_idf(matches) {
+        println!("Installation failed: {}", e);
+    }
     Ok(())
 }
 
@@ -266,6 +318,7 @@ pub fn get_install_cmd<'a>() -> Command<'a, str> {
                 Arg::with_name("installer")
                     .short("e")
                     .long("installer")
+                    .takes_value(true)
                     .help("Path to installer binary"),
             )
             .arg(
@@ -286,7 +339,7 @@ pub fn get_install_cmd<'a>() -> Command<'a, str> {
                     .short("x")
                     .long("idf-version")
                     .takes_value(true)
-                    .help("ESP-IDF version"),
+                    .help("ESP-IDF version - tag or branch, e.g. v5.1.2 or release/v5.1"),
             )
             .arg(
                 Arg::with_name("idf-path")
@@ -295,6 +348,13 @@ pub fn get_install_cmd<'a>() -> Command<'a, str> {
                     .takes_value(true)
                     .help("ESP-IDF installation directory"),
             )
+            .arg(
+                Arg::with_name("shared")
+                    .short("s")
+                    .long("shared")
+                    .takes_value(false)
+                    .help("Create ESP-IDF as worktree of repository shared by all installations"),
+            )
             .arg(
                 Arg::with_name("verbose")
                     .short("w")
@@ -311,11 +371,6 @@ fn get_shell() -> String {
     "/bin/bash".to_string()
 }
 
-#[cfg(unix)]
-fn get_initializer() -> String {
-    format!("{}/export.sh", get_selected_idf_path())
-}
-
 #[cfg(unix)]
 fn get_initializer_arguments() -> Vec<String> {
     let mut arguments: Vec<String> = [].to_vec();
@@ -331,6 +386,31 @@ fn get_shell() -> String {
     "powershell".to_string()
 }
 
+/// Startup files of the shell often extend PATH, they are skipped by clean activation.
+#[cfg(unix)]
+fn get_clean_shell_arguments() -> Vec<String> {
+    vec!["--norc".to_string(), "--noprofile".to_string()]
+}
+
+#[cfg(windows)]
+fn get_clean_shell_arguments() -> Vec<String> {
+    vec!["-NoProfile".to_string(), "-NoExit".to_string()]
+}
+
+/// Interactive shell in clean environment of the installation, export scripts are not used.
+fn start_clean_shell(query: Option<&str>) -> Result<i32> {
+    let config = load_json();
+    let idf_id = installation::resolve_installation(&config, query)?;
+    let environment = environment::get_activation_environment(&config, &idf_id, true)?;
+    let mut command = std::process::Command::new(get_shell());
+    command.args(get_clean_shell_arguments());
+    environment.apply(&mut command);
+    let status = command
+        .status()
+        .map_err(|e| format!("Unable to start {}: {}", get_shell(), e))?;
+    Ok(status.code().unwrap_or(1))
+}
+
 #[cfg(windows)]
 fn get_initializer() -> String {
     format!("{}/Initialize-Idf.ps1", get_tools_path())
@@ -349,8 +429,17 @@ fn get_initializer_arguments() -> Vec<String> {
 
 fn get_shell_runner(
     _args: &str,
-    _matches: &clap::ArgMatches<'_>,
+    matches: &clap::ArgMatches<'_>,
 ) -> std::result::Result<(), clap::Error> {
+    if matches.is_present("clean") {
+        match start_clean_shell(matches.value_of("idf")) {
+            Ok(exit_code) => std::process::exit(exit_code),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+    }
     println!("Starting process");
     // let root = Path::new("C:\\esp");
     // assert!(env::set_current_dir(&root).is_ok());
@@ -383,308 +472,114 @@ pub fn get_shell_cmd<'a>() -> Command<'a, str> {
                     .help("Name of communication port")
                     .takes_value(true),
             )
+            .arg(environment::get_clean_arg())
+            .arg(
+                Arg::with_name("idf")
+                    .long("idf")
+                    .help("Id, version or path of installation used with --clean, selected installation by default")
+                    .takes_value(true),
+            )
         })
         .runner(|_args, matches| get_shell_runner(_args, matches))
 }
 
-#[cfg(unix)]
-fn run_build(
-    idf_path: &String,
-    shell_initializer: &String,
-) -> std::result::Result<(), clap::Error> {
-    // println!("Starting process");
-    let root = Path::new(&idf_path);
-    assert!(env::set_current_dir(&root).is_ok());
-
-    run_idf_command("cd examples/get-started/blink; idf.py fullclean; idf.py build".to_string());
-
-    //println!("output = {:?}", output);
-    Ok(())
-}
-
-fn run_idf_command(command: String) {
-    match run_command(get_shell(), get_initializer_arguments(), command) {
-        Ok(_) => {
-            println!("Ok");
+fn print_remote_releases(as_json: bool) -> Result<()> {
+    let releases = get_remote_releases(IDF_REPOSITORY_URL)?;
+    if as_json {
+        let releases: Vec<json::JsonValue> = releases.iter().map(|release| release.to_json()).collect();
+        println!("{:#}", json::JsonValue::Array(releases));
+        return Ok(());
+    }
+    for release in releases.iter() {
+        let mut flags: Vec<&str> = Vec::new();
+        if release.is_pre_release() {
+            flags.push("pre-release");
         }
-        Err(_e) => {
-            println!("Failed");
+        if release.is_end_of_life() {
+            flags.push("end of life");
         }
-    }
-}
-
-#[cfg(windows)]
-fn run_build(
-    idf_path: &String,
-    _shell_initializer: &String,
-) -> std::result::Result<(), clap::Error> {
-    // println!("Starting process");
-    let root = Path::new(&idf_path);
-    assert!(env::set_current_dir(&root).is_ok());
-
-    run_idf_command("cd examples/get-started/blink; idf.py fullclean; idf.py build\n".to_string());
-
-    Ok(())
-}
-
-fn get_build_runner(
-    _args: &str,
-    matches: &clap::ArgMatches<'_>,
-) -> std::result::Result<(), clap::Error> {
-    let build_repetitions: i32 = matches
-        .value_of("repeat")
-        .unwrap()
-        .to_string()
-        .parse()
-        .unwrap();
-    let idf_path = matches
-        .value_of("idf-path")
-        .unwrap_or(&*get_selected_idf_path())
-        .to_string();
-
-    let initializer = get_initializer();
-    println!("Number of CPU cores: {}", num_cpus::get());
-    println!("ESP-IDF Shell Initializer: {}", initializer);
-    println!("ESP-IDF Path: {}", idf_path);
-    for _build_number in 0..build_repetitions {
-        let start = Instant::now();
-        match run_build(&idf_path, &initializer) {
-            Ok(_) => {
-                println!("Ok");
-            }
-            Err(_e) => {
-                println!("Failed");
-            }
+        if flags.is_empty() {
+            println!("{}", release.name);
+        } else {
+            println!("{} ({})", release.name, flags.join(", "));
         }
-        let duration = start.elapsed();
-        println!("Time elapsed in build: {:?}", duration);
     }
     Ok(())
 }
 
-fn change_submodules_mirror(mut repo: Repository, submodule_url: String) {
-    let mut change_set: Vec<(String, String)> = Vec::new();
-    for submodule in repo.submodules().unwrap() {
-        let repo_name = submodule.name().unwrap().to_string();
-        let original_url = submodule.url().unwrap();
-
-        if !(original_url.starts_with("../../") || original_url.starts_with("https://github.com")) {
-            println!("Submodule: {}, URL: {} - skip", repo_name, original_url);
-            continue;
+fn print_installed_versions(as_json: bool) {
+    let config = load_json();
+    let selected_idf_id = config["idfSelectedId"].to_string();
+    if as_json {
+        let mut installed: Vec<json::JsonValue> = Vec::new();
+        for (idf_id, idf) in config["idfInstalled"].entries() {
+            installed.push(json::object! {
+                "id": idf_id,
+                "version": idf["version"].to_string(),
+                "path": idf["path"].to_string(),
+                "python": idf["python"].to_string(),
+                "selected": idf_id == selected_idf_id
+            });
         }
-
-        let mut old_repo = original_url.split('/').last().unwrap();
-
-        // Correction of some names
-        if old_repo.starts_with("unity") {
-            old_repo = "Unity"
-        } else if old_repo.starts_with("cexception") {
-            old_repo = "CException"
-        }
-
-        let new_url = format!("{}{}", submodule_url, old_repo);
-
-        change_set.push((repo_name, new_url));
+        println!("{:#}", json::JsonValue::Array(installed));
+        return;
     }
-
-    for submodule in change_set {
-        println!("Submodule: {}, new URL: {}", submodule.0, submodule.1);
-        match repo.submodule_set_url(&*submodule.0, &*submodule.1) {
-            Ok(_) => {
-                println!("Ok");
-            }
-            Err(_e) => {
-                println!("Failed");
-            }
-        }
+    for (idf_id, idf) in config["idfInstalled"].entries() {
+        let marker = if idf_id == selected_idf_id { "*" } else { " " };
+        println!("{} {} {} {}", marker, idf["version"], idf_id, idf["path"]);
     }
 }
 
-fn get_mirror_switch_runner(
+fn get_list_runner(
     _args: &str,
     matches: &clap::ArgMatches<'_>,
 ) -> std::result::Result<(), clap::Error> {
-    let idf_path = matches
-        .value_of("idf-path")
-        .unwrap_or(&*get_selected_idf_path())
-        .to_string();
-    let url = matches.value_of("url").unwrap().to_string();
-    let submodule_url = matches.value_of("submodule-url").unwrap().to_string();
-
-    println!("Processing main repository...");
-    match Repository::open(idf_path.clone()) {
-        Ok(repo) => {
-            //repo.find_remote("origin")?.url()
-            if matches.is_present("url") {
-                match repo.remote_set_url("origin", url.as_str()) {
-                    Ok(_) => {
-                        println!("Ok");
-                    }
-                    Err(_e) => {
-                        println!("Failed");
-                    }
-                }
-            }
-
-            change_submodules_mirror(repo, submodule_url.clone());
+    let as_json = matches.is_present("json");
+    if matches.is_present("remote") {
+        if let Err(e) = print_remote_releases(as_json) {
+            println!("Unable to list releases of {}: {}", IDF_REPOSITORY_URL, e);
         }
-        Err(e) => {
-            println!("failed to open: {}", e.to_string());
-            std::process::exit(1);
-        }
-    };
-
-    println!("Processing submodules...");
-    match Repository::open(idf_path.clone()) {
-        Ok(repo) => {
-            //repo.find_remote("origin")?.url()
-            if matches.is_present("url") {
-                match repo.remote_set_url("origin", url.as_str()) {
-                    Ok(_) => {
-                        println!("Ok");
-                    }
-                    Err(_e) => {
-                        println!("Failed");
-                    }
-                }
-            }
-
-            for mut submodule_repo_reference in repo.submodules().unwrap() {
-                match submodule_repo_reference.init(false) {
-                    Ok(_) => {
-                        println!("Ok");
-                    }
-                    Err(_e) => {
-                        println!("Failed");
-                    }
-                }
-                let progress = matches.is_present("progress");
-                if matches.is_present("depth") {
-                    // git2 crate does not support depth for submodules, we need to call git instead
-                    let depth = matches.value_of("depth").unwrap().to_string();
-                    match update_submodule(
-                        idf_path.clone(),
-                        submodule_repo_reference.name().unwrap().to_string(),
-                        depth,
-                        progress,
-                    ) {
-                        Ok(_) => {
-                            println!("Ok");
-                        }
-                        Err(_e) => {
-                            println!("Failed");
-                        }
-                    }
-                } else {
-                    match submodule_repo_reference.update(true, None) {
-                        Ok(_) => {
-                            println!("Ok");
-                        }
-                        Err(_e) => {
-                            println!("Failed");
-                        }
-                    }
-                }
-                match submodule_repo_reference.open() {
-                    Ok(sub_repo) => {
-                        println!("Processing submodule: {:?}", sub_repo.workdir().unwrap());
-                        change_submodules_mirror(sub_repo, submodule_url.clone());
-                    }
-                    Err(_e) => {
-                        println!("Unable to update submodule");
-                    }
-                }
-            }
-        }
-        Err(e) => {
-            println!("failed to open: {}", e.to_string());
-            std::process::exit(1);
-        }
-    };
-
+    } else {
+        print_installed_versions(as_json);
+    }
     Ok(())
 }
 
-pub fn get_build_cmd<'a>() -> Command<'a, str> {
-    Command::new("build")
-        .description("Start build process")
+pub fn get_list_cmd<'a>() -> Command<'a, str> {
+    Command::new("list")
+        .description("List installed ESP-IDF versions or releases available for installation")
         .options(|app| {
             app.arg(
-                Arg::with_name("repeat")
+                Arg::with_name("remote")
                     .short("r")
-                    .long("repeat")
-                    .help("Number of repetitions of the same command")
-                    .takes_value(true)
-                    .default_value("1"),
-            )
-            .arg(
-                Arg::with_name("idf-path")
-                    .short("p")
-                    .long("idf-path")
-                    .help("Path to ESP IDF source code repository")
-                    .takes_value(true),
+                    .long("remote")
+                    .help("List release tags and branches of ESP-IDF repository"),
             )
             .arg(
-                Arg::with_name("tools-path")
-                    .short("t")
-                    .long("tools-path")
-                    .help("Path to Tools directory")
-                    .takes_value(true),
-            )
-        })
-        .runner(|_args, matches| get_build_runner(_args, matches))
-}
-
-pub fn get_mirror_cmd<'a>() -> Command<'a, str> {
-    Command::new("mirror")
-        .description("Switch the URL of repository mirror")
-        .options(|app| {
-            app.arg(
-                Arg::with_name("url")
-                    .short("u")
-                    .long("url")
-                    .help("Base URL of the main repo")
-                    .takes_value(true),
-            )
-            .arg(
-                Arg::with_name("idf-path")
-                    .short("p")
-                    .long("idf-path")
-                    .help("Path to ESP IDF source code repository")
-                    .takes_value(true),
-            )
-            .arg(
-                Arg::with_name("submodule-url")
-                    .short("s")
-                    .long("submodule-url")
-                    .help("Base URL for submodule mirror")
-                    .required(true)
-                    .takes_value(true),
-            )
-            .arg(
-                Arg::with_name("depth")
-                    .short("d")
-                    .long("depth")
-                    .help("Create shallow clone of the repo and submodules")
-                    .takes_value(true),
-            )
-            .arg(
-                Arg::with_name("progress")
-                    .short("r")
-                    .long("progress")
-                    .help("Display progress status of git operation"),
+                Arg::with_name("json")
+                    .long("json")
+                    .help("Emit the list as JSON"),
             )
         })
-        .runner(|_args, matches| get_mirror_switch_runner(_args, matches))
+        .runner(|_args, matches| get_list_runner(_args, matches))
 }
 
 pub fn get_multi_cmd<'a>() -> MultiCommand<'a, str, str> {
     let multi_cmd: MultiCommand<str, str> = Commander::new()
-        .add_cmd(get_build_cmd())
+        .add_cmd(benchmark::get_build_cmd())
+        .add_cmd(discover::get_discover_cmd())
+        .add_cmd(environment::get_env_cmd())
+        .add_cmd(exec::get_exec_cmd())
         .add_cmd(get_install_cmd())
-        .add_cmd(get_mirror_cmd())
-        .add_cmd(get_reset_cmd())
+        .add_cmd(get_list_cmd())
+        .add_cmd(mirror::get_mirror_cmd())
+        .add_cmd(new::get_new_cmd())
+        .add_cmd(python::get_multi_cmd())
+        .add_cmd(reset::get_reset_cmd())
         .add_cmd(get_shell_cmd())
+        .add_cmd(status::get_status_cmd())
+        .add_cmd(switch::get_switch_cmd())
+        .add_cmd(uninstall::get_uninstall_cmd())
         .into_cmd("idf")
         // Optionally specify a description
         .description("Maintain configuration of ESP-IDF installations.");
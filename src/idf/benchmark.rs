@@ -0,0 +1,517 @@
+use clap::Arg;
+use clap_nested::Command;
+use std::fs;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Instant;
+
+use super::discover::{find_python_env_in, is_idf_checkout};
+use super::environment::{get_idf_environment_in, IdfEnvironment};
+use super::exec::get_command;
+use super::installation::{find_installations, normalize_path, resolve_installation};
+use super::matrix::{get_matrix_targets, run_matrix_command};
+use super::version::describe_idf_version;
+use crate::antivirus::get_active_antivirus_names;
+use crate::config::{get_tools_path, load_json};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+pub const DEFAULT_PROJECT: &str = "examples/get-started/blink";
+
+/// Id under which checkout of ESP-IDF given by path is added to the configuration for the build
+const UNREGISTERED_ID: &str = "unregistered";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuildMode {
+    /// Every run starts with idf.py fullclean
+    Clean,
+    /// Project is built once before measurement, runs rebuild only touched sources
+    Incremental,
+}
+
+impl BuildMode {
+    fn get_name(&self) -> &str {
+        match self {
+            BuildMode::Clean => "clean",
+            BuildMode::Incremental => "incremental",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statistics {
+    pub mean: f64,
+    pub median: f64,
+    /// Sample standard deviation, zero for single run
+    pub stddev: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Statistics {
+    pub fn compute(samples: &[f64]) -> Option<Statistics> {
+        if samples.is_empty() {
+            return None;
+        }
+        let mut sorted = samples.to_vec();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let count = sorted.len();
+        let mean = sorted.iter().sum::<f64>() / count as f64;
+        let median = if count.is_multiple_of(2) {
+            (sorted[count / 2 - 1] + sorted[count / 2]) / 2.0
+        } else {
+            sorted[count / 2]
+        };
+        let stddev = if count > 1 {
+            let variance = sorted.iter().map(|sample| (sample - mean).powi(2)).sum::<f64>() / (count - 1) as f64;
+            variance.sqrt()
+        } else {
+            0.0
+        };
+        Some(Statistics {
+            mean,
+            median,
+            stddev,
+            min: sorted[0],
+            max: sorted[count - 1],
+        })
+    }
+
+    fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            "mean": self.mean,
+            "median": self.median,
+            "stddev": self.stddev,
+            "min": self.min,
+            "max": self.max
+        }
+    }
+}
+
+pub struct BuildRun {
+    pub seconds: f64,
+    pub success: bool,
+}
+
+pub struct BenchmarkReport {
+    pub idf_path: String,
+    pub idf_version: String,
+    pub project: String,
+    pub target: Option<String>,
+    pub mode: BuildMode,
+    pub runs: Vec<BuildRun>,
+}
+
+impl BenchmarkReport {
+    /// Only successful runs are included in statistics
+    pub fn get_statistics(&self) -> Option<Statistics> {
+        let samples: Vec<f64> = self.runs.iter().filter(|run| run.success).map(|run| run.seconds).collect();
+        Statistics::compute(&samples)
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        let runs: Vec<json::JsonValue> = self
+            .runs
+            .iter()
+            .enumerate()
+            .map(|(index, run)| {
+                json::object! {
+                    "run": index + 1,
+                    "seconds": run.seconds,
+                    "success": run.success
+                }
+            })
+            .collect();
+        json::object! {
+            "idfPath": self.idf_path.as_str(),
+            "idfVersion": self.idf_version.as_str(),
+            "project": self.project.as_str(),
+            "target": self.target.as_deref(),
+            "mode": self.mode.get_name(),
+            "system": get_system_info(),
+            "runs": runs,
+            "statistics": self.get_statistics().map(|statistics| statistics.to_json())
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut lines = vec!["run,mode,target,seconds,success".to_string()];
+        for (index, run) in self.runs.iter().enumerate() {
+            lines.push(format!(
+                "{},{},{},{:.3},{}",
+                index + 1,
+                self.mode.get_name(),
+                self.target.as_deref().unwrap_or(""),
+                run.seconds,
+                run.success
+            ));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    pub fn print(&self) {
+        println!("Project: {}", self.project);
+        println!("Mode: {}", self.mode.get_name());
+        let failed = self.runs.iter().filter(|run| !run.success).count();
+        println!("Runs: {}, failed: {}", self.runs.len(), failed);
+        if let Some(statistics) = self.get_statistics() {
+            println!(
+                "Mean: {:.2}s, median: {:.2}s, stddev: {:.2}s, min: {:.2}s, max: {:.2}s",
+                statistics.mean, statistics.median, statistics.stddev, statistics.min, statistics.max
+            );
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn get_cpu_model() -> Option<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo
+        .lines()
+        .find(|line| line.starts_with("model name"))
+        .and_then(|line| line.split(':').nth(1))
+        .map(|model| model.trim().to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn get_cpu_model() -> Option<String> {
+    let output = std::process::Command::new("sysctl")
+        .args(["-n", "machdep.cpu.brand_string"])
+        .output()
+        .ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+#[cfg(windows)]
+fn get_cpu_model() -> Option<String> {
+    std::env::var("PROCESSOR_IDENTIFIER").ok()
+}
+
+fn get_system_info() -> json::JsonValue {
+    json::object! {
+        "os": std::env::consts::OS,
+        "cpu": get_cpu_model(),
+        "logicalCores": num_cpus::get(),
+        "physicalCores": num_cpus::get_physical(),
+        "antivirus": get_active_antivirus_names()
+    }
+}
+
+pub fn run_idf_py(environment: &IdfEnvironment, project: &str, arguments: &[&str], verbose: bool) -> Result<bool> {
+    let mut full_arguments = vec!["idf.py", "-C", project];
+    full_arguments.extend_from_slice(arguments);
+    let mut command = get_command(environment, &full_arguments);
+    if !verbose {
+        command.stdout(Stdio::null()).stderr(Stdio::null());
+    }
+    let status = command
+        .status()
+        .map_err(|e| format!("Unable to start idf.py: {}", e))?;
+    Ok(status.success())
+}
+
+/// Rewrite sources of main component, so that incremental build has something to compile.
+fn touch_main_sources(project: &str) -> Result<()> {
+    for entry in fs::read_dir(format!("{}/main", project))? {
+        let path = entry?.path();
+        let is_source = matches!(
+            path.extension().and_then(|extension| extension.to_str()),
+            Some("c") | Some("cpp")
+        );
+        if is_source {
+            let content = fs::read(&path)?;
+            fs::write(&path, content)?;
+        }
+    }
+    Ok(())
+}
+
+/// Installation used for the build: path, version and environment. --idf-path accepts id, version
+/// or path of installation, a checkout of ESP-IDF which is not registered is used with Python
+/// environment and tools of the tools directory, IDF_TOOLS_PATH by default.
+pub fn get_build_installation(query: Option<&str>, tools_path: Option<&str>) -> Result<(String, String, IdfEnvironment)> {
+    let tools_path = tools_path.map(normalize_path).unwrap_or_else(get_tools_path);
+    let mut config = load_json();
+    let unregistered_path = query
+        .filter(|query| find_installations(&config, query).is_empty())
+        .filter(|query| is_idf_checkout(Path::new(query)));
+    let idf_id = match unregistered_path {
+        Some(idf_path) => {
+            let idf_path = normalize_path(idf_path);
+            config["idfInstalled"][UNREGISTERED_ID] = json::object! {
+                "version": describe_idf_version(&idf_path).unwrap_or_default(),
+                "python": find_python_env_in(&idf_path, &tools_path).unwrap_or_default(),
+                "path": idf_path
+            };
+            UNREGISTERED_ID.to_string()
+        }
+        None => resolve_installation(&config, query)?,
+    };
+    let idf = &config["idfInstalled"][idf_id.as_str()];
+    let environment = get_idf_environment_in(&config, &idf_id, &tools_path)?;
+    Ok((normalize_path(&idf["path"].to_string()), idf["version"].to_string(), environment))
+}
+
+/// Project given by user, or the default example of ESP-IDF.
+pub fn get_project_path(idf_path: &str, project: Option<&str>) -> Result<String> {
+    let project = match project {
+        Some(project) => normalize_path(project),
+        None => format!("{}/{}", idf_path, DEFAULT_PROJECT),
+    };
+    if !Path::new(&project).join("CMakeLists.txt").exists() {
+        return Err(format!("{} is not ESP-IDF project", project).into());
+    }
+    Ok(project)
+}
+
+pub fn run_benchmark(
+    query: Option<&str>,
+    tools_path: Option<&str>,
+    project: Option<&str>,
+    target: Option<&str>,
+    mode: BuildMode,
+    repeat: usize,
+    verbose: bool,
+) -> Result<BenchmarkReport> {
+    let (idf_path, idf_version, environment) = get_build_installation(query, tools_path)?;
+    let project = get_project_path(&idf_path, project)?;
+
+    println!("ESP-IDF Path: {}", idf_path);
+    println!("Project: {}", project);
+    println!("Number of CPU cores: {}", num_cpus::get());
+    if let Some(target) = target {
+        println!("Setting target {}", target);
+        if !run_idf_py(&environment, &project, &["set-target", target], verbose)? {
+            return Err(format!("idf.py set-target {} failed", target).into());
+        }
+    }
+    if mode == BuildMode::Incremental {
+        println!("Preparing build for incremental runs");
+        if !run_idf_py(&environment, &project, &["build"], verbose)? {
+            return Err("Initial build failed".into());
+        }
+    }
+
+    let mut runs: Vec<BuildRun> = Vec::new();
+    for run_number in 1..=repeat {
+        match mode {
+            BuildMode::Clean => {
+                run_idf_py(&environment, &project, &["fullclean"], verbose)?;
+            }
+            BuildMode::Incremental => touch_main_sources(&project)?,
+        }
+        let start = Instant::now();
+        let success = run_idf_py(&environment, &project, &["build"], verbose)?;
+        let seconds = start.elapsed().as_secs_f64();
+        println!(
+            "Run {}/{}: {:.2}s{}",
+            run_number,
+            repeat,
+            seconds,
+            if success { "" } else { " (failed)" }
+        );
+        runs.push(BuildRun { seconds, success });
+    }
+
+    Ok(BenchmarkReport {
+        idf_path,
+        idf_version,
+        project,
+        target: target.map(|target| target.to_string()),
+        mode,
+        runs,
+    })
+}
+
+fn format_change(current: f64, previous: f64) -> String {
+    if previous <= 0.0 {
+        return format!("{:.2}s", current);
+    }
+    format!("{:.2}s ({:+.1}%)", current, (current - previous) / previous * 100.0)
+}
+
+/// Compare statistics with JSON report of previous benchmark.
+fn print_comparison(report: &BenchmarkReport, previous_path: &str) -> Result<()> {
+    let previous = json::parse(&fs::read_to_string(previous_path)?)?;
+    let statistics = report.get_statistics().ok_or("No successful run to compare")?;
+    let previous_statistics = &previous["statistics"];
+    if previous_statistics.is_null() {
+        return Err(format!("{} contains no statistics", previous_path).into());
+    }
+    println!("Compared to {}:", previous_path);
+    println!("  mean: {}", format_change(statistics.mean, previous_statistics["mean"].as_f64().unwrap_or(0.0)));
+    println!("  median: {}", format_change(statistics.median, previous_statistics["median"].as_f64().unwrap_or(0.0)));
+    Ok(())
+}
+
+fn get_build_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    let targets = get_matrix_targets(
+        matches.values_of("targets").map(|targets| targets.collect()),
+        matches.value_of("project"),
+    );
+    match targets {
+        Ok(targets) if !targets.is_empty() => {
+            run_matrix_command(matches, &targets);
+            return Ok(());
+        }
+        Ok(_) => {}
+        Err(e) => {
+            println!("{}", e);
+            return Ok(());
+        }
+    }
+    let repeat: usize = match matches.value_of("repeat").unwrap().parse() {
+        Ok(repeat) if repeat > 0 => repeat,
+        _ => {
+            println!("Number of repetitions must be a positive number");
+            return Ok(());
+        }
+    };
+    let mode = match matches.value_of("mode") {
+        Some("incremental") => BuildMode::Incremental,
+        _ => BuildMode::Clean,
+    };
+    let report = match run_benchmark(
+        matches.value_of("idf-path"),
+        matches.value_of("tools-path"),
+        matches.value_of("project"),
+        matches.value_of("target"),
+        mode,
+        repeat,
+        matches.is_present("verbose"),
+    ) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("Build failed: {}", e);
+            return Ok(());
+        }
+    };
+    report.print();
+
+    if let Some(output) = matches.value_of("output") {
+        let content = if output.ends_with(".csv") {
+            report.to_csv()
+        } else {
+            format!("{:#}", report.to_json())
+        };
+        match fs::write(output, content) {
+            Ok(_) => println!("Report written to {}", output),
+            Err(e) => println!("Unable to write report {}: {}", output, e),
+        }
+    }
+    if let Some(previous_path) = matches.value_of("compare") {
+        if let Err(e) = print_comparison(&report, previous_path) {
+            println!("Unable to compare with {}: {}", previous_path, e);
+        }
+    }
+    Ok(())
+}
+
+pub fn get_build_cmd<'a>() -> Command<'a, str> {
+    Command::new("build")
+        .description("Measure build time of ESP-IDF project")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("repeat")
+                    .short("r")
+                    .long("repeat")
+                    .help("Number of repetitions of the same command")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::with_name("idf-path")
+                    .short("p")
+                    .long("idf-path")
+                    .help("Path to ESP IDF source code repository, or id or version of installation")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("tools-path")
+                    .short("t")
+                    .long("tools-path")
+                    .help("Tools directory used by the build instead of IDF_TOOLS_PATH, installations are still read from IDF_TOOLS_PATH")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("project")
+                    .short("c")
+                    .long("project")
+                    .help("Path to project, examples/get-started/blink of ESP-IDF by default")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("target")
+                    .short("g")
+                    .long("target")
+                    .help("Target chip, e.g. esp32s3")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("targets")
+                    .long("targets")
+                    .help("Build once for each chip in separate build directory, e.g. esp32,esp32s3. [build] targets of idf-env.toml are used by default")
+                    .takes_value(true)
+                    .use_delimiter(true)
+                    .conflicts_with("target"),
+            )
+            .arg(
+                Arg::with_name("jobs")
+                    .short("j")
+                    .long("jobs")
+                    .help("Number of targets built in parallel, projects using the component manager must be built with 1")
+                    .takes_value(true)
+                    .default_value("1"),
+            )
+            .arg(
+                Arg::with_name("mode")
+                    .short("m")
+                    .long("mode")
+                    .help("Build from scratch or rebuild after change of main component")
+                    .takes_value(true)
+                    .possible_values(&["clean", "incremental"])
+                    .default_value("clean"),
+            )
+            .arg(
+                Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .help("Write report to file, CSV when the name ends with .csv, JSON otherwise")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("compare")
+                    .long("compare")
+                    .help("JSON report of previous benchmark to compare with")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("verbose")
+                    .short("w")
+                    .long("verbose")
+                    .help("Display output of idf.py"),
+            )
+        })
+        .runner(|_args, matches| get_build_runner(_args, matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_statistics() {
+        let statistics = Statistics::compute(&[12.0, 10.0, 14.0, 10.0]).unwrap();
+        assert_eq!(statistics.mean, 11.5);
+        assert_eq!(statistics.median, 11.0);
+        assert_eq!(statistics.min, 10.0);
+        assert_eq!(statistics.max, 14.0);
+        assert!((statistics.stddev - 1.9149).abs() < 0.001);
+        assert_eq!(Statistics::compute(&[5.0]).unwrap().stddev, 0.0);
+        assert!(Statistics::compute(&[]).is_none());
+        assert_eq!(format_change(11.0, 10.0), "11.00s (+10.0%)");
+    }
+}
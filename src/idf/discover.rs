@@ -0,0 +1,242 @@
+use clap::Arg;
+use clap_nested::Command;
+use dirs::home_dir;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use super::installation::normalize_path;
+use super::python::get_env_python_path;
+use super::uninstall::confirm;
+use super::version::{describe_idf_version, read_idf_version};
+use crate::config::{add_idf_config, get_tools_path, load_json, update_property};
+
+/// Checkouts are usually ~/esp/esp-idf or ~/esp/v5.1.2/esp-idf, deeper directories are not scanned.
+const MAX_SCAN_DEPTH: usize = 3;
+
+#[derive(Debug, PartialEq)]
+pub struct DiscoveredCheckout {
+    pub path: String,
+    pub version: String,
+    /// Python of matching environment in python_env, None when no environment exists
+    pub python: Option<String>,
+    pub registered: bool,
+}
+
+/// Locations used by manual installation, VS Code extension and Windows installer.
+fn get_default_roots() -> Vec<PathBuf> {
+    let tools_path = PathBuf::from(get_tools_path());
+    let mut roots = vec![tools_path.join("frameworks"), tools_path];
+    if let Some(home) = home_dir() {
+        roots.push(home.join("esp"));
+    }
+    if cfg!(windows) {
+        roots.push(PathBuf::from("C:/esp"));
+        roots.push(PathBuf::from("C:/Espressif/frameworks"));
+    }
+    roots
+}
+
+pub fn is_idf_checkout(path: &Path) -> bool {
+    path.join("tools/idf.py").is_file() && path.join("tools/cmake/version.cmake").is_file()
+}
+
+/// Directories with ESP-IDF checkout under the roots, checkouts are not searched for nested ones.
+pub fn find_checkouts(roots: &[PathBuf]) -> Vec<PathBuf> {
+    let mut checkouts: Vec<PathBuf> = Vec::new();
+    for root in roots {
+        let mut entries = WalkDir::new(root).max_depth(MAX_SCAN_DEPTH).into_iter();
+        while let Some(entry) = entries.next() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_e) => continue,
+            };
+            if !entry.file_type().is_dir() {
+                continue;
+            }
+            let is_hidden = entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.');
+            if is_hidden {
+                entries.skip_current_dir();
+                continue;
+            }
+            if is_idf_checkout(entry.path()) {
+                if !checkouts.iter().any(|checkout| checkout == entry.path()) {
+                    checkouts.push(entry.path().to_path_buf());
+                }
+                entries.skip_current_dir();
+            }
+        }
+    }
+    checkouts
+}
+
+/// Parse Python version from name of environment created for the ESP-IDF version,
+/// e.g. idf5.1_py3.11_env for 5.1
+pub fn parse_python_env_name(name: &str, idf_major_minor: &str) -> Option<(u32, u32)> {
+    let python_version = name
+        .strip_prefix(&format!("idf{}_py", idf_major_minor))?
+        .strip_suffix("_env")?;
+    let (major, minor) = python_version.split_once('.')?;
+    Some((major.parse().ok()?, minor.parse().ok()?))
+}
+
+/// Python of environment created for the checkout by idf_tools.py or idf-env, the newest Python wins.
+pub fn find_python_env(idf_path: &str) -> Option<String> {
+    find_python_env_in(idf_path, &get_tools_path())
+}
+
+/// Python environment for the checkout in the given tools directory instead of IDF_TOOLS_PATH
+pub fn find_python_env_in(idf_path: &str, tools_path: &str) -> Option<String> {
+    let idf_major_minor = read_idf_version(idf_path)?.get_major_minor();
+    let python_env_root = format!("{}/python_env", normalize_path(tools_path));
+    fs::read_dir(python_env_root)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().to_string();
+            let python_version = parse_python_env_name(&name, &idf_major_minor)?;
+            let python_path = get_env_python_path(&normalize_path(&entry.path().display().to_string()));
+            if Path::new(&python_path).exists() {
+                Some((python_version, python_path))
+            } else {
+                None
+            }
+        })
+        .max_by_key(|(python_version, _)| *python_version)
+        .map(|(_, python_path)| python_path)
+}
+
+pub fn discover_checkouts(roots: &[PathBuf]) -> Vec<DiscoveredCheckout> {
+    let config = load_json();
+    let registered_paths: Vec<String> = config["idfInstalled"]
+        .entries()
+        .map(|(_, idf)| normalize_path(&idf["path"].to_string()))
+        .collect();
+    find_checkouts(roots)
+        .iter()
+        .filter_map(|checkout| {
+            let path = normalize_path(&checkout.display().to_string());
+            let version = describe_idf_version(&path)?;
+            Some(DiscoveredCheckout {
+                python: find_python_env(&path),
+                registered: registered_paths.contains(&path),
+                path,
+                version,
+            })
+        })
+        .collect()
+}
+
+/// Register checkouts which are not in the configuration. Selected installation stays selected,
+/// the first registered checkout is selected only when there was no valid selection.
+fn adopt_checkouts(checkouts: &[DiscoveredCheckout], assume_yes: bool) -> usize {
+    let config = load_json();
+    let selected_idf_id = config["idfSelectedId"].to_string();
+    let has_selection = !config["idfInstalled"][selected_idf_id.as_str()].is_null();
+    let mut registered_count = 0;
+    for checkout in checkouts.iter().filter(|checkout| !checkout.registered) {
+        if !assume_yes && !confirm(&format!("Register {} ({})?", checkout.path, checkout.version)) {
+            continue;
+        }
+        add_idf_config(
+            checkout.path.clone(),
+            checkout.version.clone(),
+            checkout.python.clone().unwrap_or_default(),
+        );
+        if checkout.python.is_none() {
+            println!(
+                "No Python environment found for {}, create it by: idf-env idf python repair --idf {}",
+                checkout.path, checkout.path
+            );
+        }
+        registered_count += 1;
+    }
+    if has_selection {
+        update_property("idfSelectedId".to_string(), selected_idf_id);
+    }
+    registered_count
+}
+
+fn get_discover_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    let mut roots: Vec<PathBuf> = match matches.values_of("root") {
+        Some(roots) => roots.map(PathBuf::from).collect(),
+        None => Vec::new(),
+    };
+    roots.extend(get_default_roots());
+
+    let checkouts = discover_checkouts(&roots);
+    if checkouts.is_empty() {
+        println!("No ESP-IDF checkout found");
+        return Ok(());
+    }
+    for checkout in &checkouts {
+        println!(
+            "{} {} python: {}{}",
+            checkout.version,
+            checkout.path,
+            checkout.python.as_deref().unwrap_or("not found"),
+            if checkout.registered { " (registered)" } else { "" }
+        );
+    }
+    if matches.is_present("list") || checkouts.iter().all(|checkout| checkout.registered) {
+        return Ok(());
+    }
+    let registered_count = adopt_checkouts(&checkouts, matches.is_present("yes"));
+    println!("Registered installations: {}", registered_count);
+    Ok(())
+}
+
+pub fn get_discover_cmd<'a>() -> Command<'a, str> {
+    Command::new("discover")
+        .description("Find ESP-IDF checkouts which are not in the configuration and register them")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("root")
+                    .short("r")
+                    .long("root")
+                    .help("Additional directory to scan, e.g. C:/projects")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("list")
+                    .short("l")
+                    .long("list")
+                    .help("Only print found checkouts"),
+            )
+            .arg(
+                Arg::with_name("yes")
+                    .short("y")
+                    .long("yes")
+                    .help("Register all found checkouts without asking"),
+            )
+        })
+        .runner(|_args, matches| get_discover_runner(_args, matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDirectory;
+
+    #[test]
+    fn test_find_checkouts() {
+        let test_directory = TestDirectory::new("discover");
+        let root = test_directory.path.clone();
+        let checkout = root.join("v5.1.2/esp-idf");
+        fs::create_dir_all(checkout.join("tools/cmake")).unwrap();
+        fs::write(checkout.join("tools/idf.py"), "").unwrap();
+        fs::write(checkout.join("tools/cmake/version.cmake"), "").unwrap();
+        fs::create_dir_all(root.join(".cache/esp-idf/tools/cmake")).unwrap();
+        fs::write(root.join(".cache/esp-idf/tools/idf.py"), "").unwrap();
+        fs::write(root.join(".cache/esp-idf/tools/cmake/version.cmake"), "").unwrap();
+
+        assert_eq!(find_checkouts(&[root.clone(), checkout.clone()]), vec![checkout]);
+        assert_eq!(parse_python_env_name("idf5.1_py3.11_env", "5.1"), Some((3, 11)));
+        assert_eq!(parse_python_env_name("idf5.0_py3.11_env", "5.1"), None);
+    }
+}
@@ -0,0 +1,535 @@
+use clap::Arg;
+use clap_nested::Command;
+use std::env;
+use std::path::{Path, PathBuf};
+
+use super::installation::{normalize_path, resolve_installation};
+use super::python::get_python_env_directory_in;
+use super::tools::read_idf_tools;
+use super::version::read_idf_version;
+use crate::config::{get_git_path, get_tools_path, load_json};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[cfg(windows)]
+const DEFAULT_SHELL: &str = "powershell";
+#[cfg(unix)]
+const DEFAULT_SHELL: &str = "bash";
+
+/// Variables of the host which change behavior of Python or tools, e.g. left by other virtual
+/// environment. Clean activation removes them together with IDF_ variables of other installations.
+const LEAKING_VARIABLES: [&str; 6] = [
+    "PYTHONPATH",
+    "PYTHONHOME",
+    "VIRTUAL_ENV",
+    "CONDA_PREFIX",
+    "OPENOCD_SCRIPTS",
+    "ESP_ROM_ELF_DIR",
+];
+
+/// Compilers which shadow the ones installed by idf_tools.py when they are earlier in PATH
+pub const TOOLCHAIN_BINARIES: [&str; 2] = ["xtensa-esp32-elf-gcc", "riscv32-esp-elf-gcc"];
+
+/// Variables and PATH entries which export.sh or Initialize-Idf.ps1 would set for the installation.
+#[derive(Debug, Default, PartialEq)]
+pub struct IdfEnvironment {
+    pub variables: Vec<(String, String)>,
+    /// Directories prepended to PATH in this order
+    pub paths: Vec<String>,
+    /// System directories of clean activation, PATH is replaced by paths followed by these
+    /// directories instead of being prepended to PATH of the host
+    pub base_paths: Option<Vec<String>>,
+    /// Variables of the host removed by clean activation
+    pub removed_variables: Vec<String>,
+}
+
+#[cfg(unix)]
+fn get_system_paths() -> Vec<String> {
+    ["/usr/bin", "/bin", "/usr/sbin", "/sbin"].iter().map(|path| path.to_string()).collect()
+}
+
+#[cfg(windows)]
+fn get_system_paths() -> Vec<String> {
+    let system_root = normalize_path(&env::var("SystemRoot").unwrap_or_else(|_e| "C:/Windows".to_string()));
+    vec![
+        format!("{}/System32", system_root),
+        system_root.clone(),
+        format!("{}/System32/Wbem", system_root),
+        format!("{}/System32/WindowsPowerShell/v1.0", system_root),
+        format!("{}/System32/OpenSSH", system_root),
+    ]
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata()
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn is_executable(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("");
+    path.is_file() && ["exe", "bat", "cmd"].contains(&extension.to_lowercase().as_str())
+}
+
+/// Executables with the name found in PATH entries outside of the tools directory.
+pub fn find_path_conflicts(path_entries: &[PathBuf], tools_path: &str, binary_name: &str) -> Vec<PathBuf> {
+    let tools_path = normalize_path(tools_path);
+    path_entries
+        .iter()
+        .map(|entry| entry.join(format!("{}{}", binary_name, env::consts::EXE_SUFFIX)))
+        .filter(|binary| binary.is_file())
+        .filter(|binary| !normalize_path(&binary.display().to_string()).starts_with(&tools_path))
+        .collect()
+}
+
+fn get_executable_names(directory: &str) -> Vec<String> {
+    match std::fs::read_dir(directory) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| is_executable(path))
+            .filter_map(|path| path.file_name().map(|name| name.to_string_lossy().to_string()))
+            .collect(),
+        Err(_e) => Vec::new(),
+    }
+}
+
+/// Escape value for double quoted string of the shell, so that paths with quotes or `$` are
+/// exported verbatim instead of being expanded.
+fn escape_value(shell: &str, value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for character in value.chars() {
+        match (shell, character) {
+            ("bash" | "zsh", '"' | '$' | '`' | '\\') | ("fish", '"' | '$' | '\\') => {
+                escaped.push('\\');
+                escaped.push(character);
+            }
+            ("powershell", '"' | '$' | '`') => {
+                escaped.push('`');
+                escaped.push(character);
+            }
+            ("cmd", '%') => escaped.push_str("%%"),
+            _ => escaped.push(character),
+        }
+    }
+    escaped
+}
+
+impl IdfEnvironment {
+    pub fn to_json(&self) -> json::JsonValue {
+        let mut variables = json::JsonValue::new_object();
+        for (name, value) in self.variables.iter() {
+            variables[name.as_str()] = value.as_str().into();
+        }
+        let mut environment = json::object! {
+            "variables": variables,
+            "path": self.paths.clone()
+        };
+        if let Some(base_paths) = &self.base_paths {
+            environment["basePath"] = base_paths.clone().into();
+            environment["removedVariables"] = self.removed_variables.clone().into();
+        }
+        environment
+    }
+
+    /// Activation which doesn't inherit PATH and Python or ESP-IDF variables of the host.
+    /// PATH consists of the environment and minimal set of system directories.
+    pub fn into_clean(self) -> IdfEnvironment {
+        let mut base_paths = get_system_paths();
+        // Git is necessary for idf.py, on Windows it's not in system directories
+        let git_path = get_git_path();
+        if let Some(git_directory) = Path::new(&git_path).parent().filter(|directory| directory.is_absolute()) {
+            let git_directory = normalize_path(&git_directory.display().to_string());
+            if !base_paths.contains(&git_directory) {
+                base_paths.push(git_directory);
+            }
+        }
+        let host_variables: Vec<String> = env::vars().map(|(name, _)| name).collect();
+        self.with_base_paths(base_paths, &host_variables)
+    }
+
+    /// Clean activation with the given system directories, variables of the host which would
+    /// leak into the environment are removed.
+    fn with_base_paths(self, base_paths: Vec<String>, host_variables: &[String]) -> IdfEnvironment {
+        let removed_variables = host_variables
+            .iter()
+            .filter(|name| LEAKING_VARIABLES.contains(&name.as_str()) || name.starts_with("IDF_"))
+            .filter(|name| !self.variables.iter().any(|(variable_name, _)| variable_name == *name))
+            .cloned()
+            .collect();
+        IdfEnvironment {
+            base_paths: Some(base_paths),
+            removed_variables,
+            ..self
+        }
+    }
+
+    /// Entries of PATH with clean activation, None when the environment is prepended to PATH of the host.
+    fn get_clean_paths(&self) -> Option<Vec<String>> {
+        let base_paths = self.base_paths.as_ref()?;
+        Some(self.paths.iter().chain(base_paths.iter()).cloned().collect())
+    }
+
+    /// Warnings about PATH of clean activation: duplicate entries, executables provided by more
+    /// than one directory of the environment and ESP toolchains in system directories.
+    pub fn find_path_problems(&self) -> Vec<String> {
+        let mut problems: Vec<String> = Vec::new();
+        let mut seen_paths: Vec<String> = Vec::new();
+        for path in self.paths.iter().chain(self.base_paths.iter().flatten()) {
+            let normalized_path = normalize_path(path);
+            if seen_paths.contains(&normalized_path) {
+                problems.push(format!("{} is in PATH more than once", path));
+            } else {
+                seen_paths.push(normalized_path);
+            }
+        }
+
+        let mut providers: Vec<(String, String)> = Vec::new();
+        for path in self.paths.iter() {
+            for name in get_executable_names(path) {
+                match providers.iter().find(|(provided_name, _)| *provided_name == name) {
+                    Some((_, provider)) if normalize_path(provider) != normalize_path(path) => problems.push(
+                        format!("{} is provided by {} and {}, the first one is used", name, provider, path),
+                    ),
+                    Some(_) => {}
+                    None => providers.push((name, path.clone())),
+                }
+            }
+        }
+
+        if let Some(base_paths) = &self.base_paths {
+            let base_paths: Vec<PathBuf> = base_paths.iter().map(PathBuf::from).collect();
+            for binary_name in TOOLCHAIN_BINARIES.iter() {
+                // Toolchain of the installation precedes system directories
+                let binary_file_name = format!("{}{}", binary_name, env::consts::EXE_SUFFIX);
+                if self.paths.iter().any(|path| Path::new(path).join(&binary_file_name).is_file()) {
+                    continue;
+                }
+                for binary in find_path_conflicts(&base_paths, &get_tools_path(), binary_name) {
+                    problems.push(format!("{} from system directory is used", binary.display()));
+                }
+            }
+        }
+        problems
+    }
+
+    /// Script which applies the environment in the given shell.
+    pub fn format(&self, shell: &str) -> Result<String> {
+        let escape = |value: &str| escape_value(shell, value);
+        let escape_paths = |paths: &[String], separator: &str| -> String {
+            paths.iter().map(|path| escape(path)).collect::<Vec<String>>().join(separator)
+        };
+        let mut lines: Vec<String> = Vec::new();
+        match shell {
+            "bash" | "zsh" => {
+                for name in self.removed_variables.iter() {
+                    lines.push(format!("unset {}", name));
+                }
+                for (name, value) in self.variables.iter() {
+                    lines.push(format!("export {}=\"{}\"", name, escape(value)));
+                }
+                match self.get_clean_paths() {
+                    Some(paths) => lines.push(format!("export PATH=\"{}\"", escape_paths(&paths, ":"))),
+                    None => lines.push(format!("export PATH=\"{}:$PATH\"", escape_paths(&self.paths, ":"))),
+                }
+            }
+            "fish" => {
+                for name in self.removed_variables.iter() {
+                    lines.push(format!("set -e {}", name));
+                }
+                for (name, value) in self.variables.iter() {
+                    lines.push(format!("set -gx {} \"{}\"", name, escape(value)));
+                }
+                let quote = |paths: &[String]| -> String {
+                    paths.iter().map(|path| format!("\"{}\"", escape(path))).collect::<Vec<String>>().join(" ")
+                };
+                match self.get_clean_paths() {
+                    Some(paths) => lines.push(format!("set -gx PATH {}", quote(&paths))),
+                    None => lines.push(format!("set -gx PATH {} $PATH", quote(&self.paths))),
+                }
+            }
+            "powershell" => {
+                for name in self.removed_variables.iter() {
+                    lines.push(format!("Remove-Item Env:{} -ErrorAction SilentlyContinue", name));
+                }
+                for (name, value) in self.variables.iter() {
+                    lines.push(format!("$env:{} = \"{}\"", name, escape(value)));
+                }
+                match self.get_clean_paths() {
+                    Some(paths) => lines.push(format!("$env:PATH = \"{}\"", escape_paths(&paths, ";"))),
+                    None => lines.push(format!("$env:PATH = \"{};\" + $env:PATH", escape_paths(&self.paths, ";"))),
+                }
+            }
+            "cmd" => {
+                for name in self.removed_variables.iter() {
+                    lines.push(format!("set \"{}=\"", name));
+                }
+                for (name, value) in self.variables.iter() {
+                    lines.push(format!("set \"{}={}\"", name, escape(value)));
+                }
+                match self.get_clean_paths() {
+                    Some(paths) => lines.push(format!("set \"PATH={}\"", escape_paths(&paths, ";"))),
+                    None => lines.push(format!("set \"PATH={};%PATH%\"", escape_paths(&self.paths, ";"))),
+                }
+            }
+            "json" => return Ok(format!("{:#}", self.to_json())),
+            _ => return Err(format!("Unsupported shell: {}", shell).into()),
+        }
+        Ok(lines.join("\n"))
+    }
+
+    pub fn get_variable(&self, name: &str) -> Option<&str> {
+        self.variables
+            .iter()
+            .find(|(variable_name, _)| variable_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Apply the environment to a child process, PATH entries are prepended to the current PATH
+    /// unless the activation is clean.
+    pub fn apply(&self, command: &mut std::process::Command) {
+        for name in self.removed_variables.iter() {
+            command.env_remove(name);
+        }
+        for (name, value) in self.variables.iter() {
+            command.env(name, value);
+        }
+        let paths: Vec<PathBuf> = match self.get_clean_paths() {
+            Some(paths) => paths.iter().map(PathBuf::from).collect(),
+            None => {
+                let mut paths: Vec<PathBuf> = self.paths.iter().map(PathBuf::from).collect();
+                if let Some(current_path) = env::var_os("PATH") {
+                    paths.extend(env::split_paths(&current_path));
+                }
+                paths
+            }
+        };
+        if let Ok(joined_paths) = env::join_paths(paths) {
+            command.env("PATH", joined_paths);
+        }
+    }
+}
+
+#[cfg(windows)]
+pub fn get_python_bin_path(python_env_path: &str) -> String {
+    format!("{}/Scripts", python_env_path)
+}
+
+#[cfg(unix)]
+pub fn get_python_bin_path(python_env_path: &str) -> String {
+    format!("{}/bin", python_env_path)
+}
+
+/// Compute environment of installation without running Python. Tools which are not installed
+/// are skipped, as export.sh does.
+pub fn get_idf_environment(config: &json::JsonValue, idf_id: &str) -> Result<IdfEnvironment> {
+    get_idf_environment_in(config, idf_id, &get_tools_path())
+}
+
+/// Environment of installation with tools of the given directory instead of IDF_TOOLS_PATH.
+pub fn get_idf_environment_in(config: &json::JsonValue, idf_id: &str, tools_path: &str) -> Result<IdfEnvironment> {
+    let idf = &config["idfInstalled"][idf_id];
+    let idf_path = normalize_path(&idf["path"].to_string());
+    let tools_path = normalize_path(tools_path);
+    let mut variables: Vec<(String, String)> = vec![
+        ("IDF_PATH".to_string(), idf_path.clone()),
+        ("IDF_TOOLS_PATH".to_string(), tools_path.clone()),
+    ];
+    let mut paths: Vec<String> = Vec::new();
+
+    if let Some(python_env_path) = get_python_env_directory_in(&idf["python"].to_string(), &tools_path) {
+        variables.push(("IDF_PYTHON_ENV_PATH".to_string(), python_env_path.clone()));
+        paths.push(get_python_bin_path(&python_env_path));
+    }
+    if let Some(version) = read_idf_version(&idf_path) {
+        variables.push(("ESP_IDF_VERSION".to_string(), version.get_major_minor()));
+    }
+
+    for tool in read_idf_tools(&idf_path)? {
+        let tool_path = normalize_path(&tool.get_install_path_in(&tools_path));
+        if !Path::new(&tool_path).exists() {
+            continue;
+        }
+        for export_path in tool.export_paths.iter() {
+            if export_path.is_empty() {
+                paths.push(tool_path.clone());
+            } else {
+                paths.push(format!("{}/{}", tool_path, export_path));
+            }
+        }
+        for (name, value) in tool.export_vars.iter() {
+            variables.push((name.clone(), value.replace("${TOOL_PATH}", &tool_path)));
+        }
+    }
+    paths.push(format!("{}/tools", idf_path));
+
+    Ok(IdfEnvironment {
+        variables,
+        paths,
+        ..IdfEnvironment::default()
+    })
+}
+
+/// Environment of the installation for activation. Warnings of clean activation are printed
+/// to stderr, so that they don't mix with the activation script.
+pub fn get_activation_environment(config: &json::JsonValue, idf_id: &str, clean: bool) -> Result<IdfEnvironment> {
+    let environment = get_idf_environment(config, idf_id)?;
+    if !clean {
+        return Ok(environment);
+    }
+    let environment = environment.into_clean();
+    for problem in environment.find_path_problems() {
+        eprintln!("Warning: {}", problem);
+    }
+    Ok(environment)
+}
+
+pub fn get_clean_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("clean")
+        .long("clean")
+        .help("Don't inherit PATH of the host, use only tools of the installation and system directories")
+}
+
+fn get_env_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    let config = load_json();
+    let environment = resolve_installation(&config, matches.value_of("idf"))
+        .and_then(|idf_id| get_activation_environment(&config, &idf_id, matches.is_present("clean")))
+        .and_then(|environment| environment.format(matches.value_of("shell").unwrap()));
+    match environment {
+        Ok(script) => println!("{}", script),
+        Err(e) => eprintln!("{}", e),
+    }
+    Ok(())
+}
+
+pub fn get_env_cmd<'a>() -> Command<'a, str> {
+    Command::new("env")
+        .description("Print environment of ESP-IDF installation for activation in shell")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("shell")
+                    .short("s")
+                    .long("shell")
+                    .help("Format of the output")
+                    .takes_value(true)
+                    .possible_values(&["bash", "zsh", "fish", "powershell", "cmd", "json"])
+                    .default_value(DEFAULT_SHELL),
+            )
+            .arg(
+                Arg::with_name("idf")
+                    .long("idf")
+                    .help("Id, version or path of installation, selected installation is used by default")
+                    .takes_value(true),
+            )
+            .arg(get_clean_arg())
+        })
+        .runner(|_args, matches| get_env_runner(_args, matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDirectory;
+
+    #[test]
+    fn test_format_environment() {
+        let environment = IdfEnvironment {
+            variables: vec![("IDF_PATH".to_string(), "/esp/esp-idf".to_string())],
+            paths: vec!["/esp/tools/cmake/bin".to_string(), "/esp/esp-idf/tools".to_string()],
+            ..IdfEnvironment::default()
+        };
+        assert_eq!(
+            environment.format("bash").unwrap(),
+            "export IDF_PATH=\"/esp/esp-idf\"\nexport PATH=\"/esp/tools/cmake/bin:/esp/esp-idf/tools:$PATH\""
+        );
+        assert_eq!(
+            environment.format("fish").unwrap(),
+            "set -gx IDF_PATH \"/esp/esp-idf\"\nset -gx PATH \"/esp/tools/cmake/bin\" \"/esp/esp-idf/tools\" $PATH"
+        );
+        assert_eq!(
+            environment.format("cmd").unwrap(),
+            "set \"IDF_PATH=/esp/esp-idf\"\nset \"PATH=/esp/tools/cmake/bin;/esp/esp-idf/tools;%PATH%\""
+        );
+        assert!(environment.format("tcsh").is_err());
+
+        let quoted_environment = IdfEnvironment {
+            variables: vec![("IDF_PATH".to_string(), "/esp/$HOME/\"idf\"`id`%x%".to_string())],
+            ..IdfEnvironment::default()
+        };
+        assert!(quoted_environment.format("bash").unwrap().starts_with("export IDF_PATH=\"/esp/\\$HOME/\\\"idf\\\"\\`id\\`%x%\"\n"));
+        assert!(quoted_environment.format("fish").unwrap().starts_with("set -gx IDF_PATH \"/esp/\\$HOME/\\\"idf\\\"`id`%x%\"\n"));
+        assert!(quoted_environment.format("powershell").unwrap().starts_with("$env:IDF_PATH = \"/esp/`$HOME/`\"idf`\"``id``%x%\"\n"));
+        assert!(quoted_environment.format("cmd").unwrap().starts_with("set \"IDF_PATH=/esp/$HOME/\"idf\"`id`%%x%%\"\n"));
+
+        let clean_environment = IdfEnvironment {
+            base_paths: Some(vec!["/opt/system/bin".to_string(), "/esp/tools/cmake/bin".to_string()]),
+            removed_variables: vec!["PYTHONPATH".to_string()],
+            ..environment
+        };
+        assert_eq!(
+            clean_environment.format("bash").unwrap(),
+            "unset PYTHONPATH\nexport IDF_PATH=\"/esp/esp-idf\"\nexport PATH=\"/esp/tools/cmake/bin:/esp/esp-idf/tools:/opt/system/bin:/esp/tools/cmake/bin\""
+        );
+        assert_eq!(
+            clean_environment.format("powershell").unwrap(),
+            "Remove-Item Env:PYTHONPATH -ErrorAction SilentlyContinue\n$env:IDF_PATH = \"/esp/esp-idf\"\n$env:PATH = \"/esp/tools/cmake/bin;/esp/esp-idf/tools;/opt/system/bin;/esp/tools/cmake/bin\""
+        );
+        assert_eq!(
+            clean_environment.find_path_problems(),
+            vec!["/esp/tools/cmake/bin is in PATH more than once".to_string()]
+        );
+    }
+
+    #[test]
+    fn test_find_path_conflicts() {
+        let test_directory = TestDirectory::new("conflicts");
+        let tools_bin = test_directory.join("tools/xtensa-esp-elf/bin");
+        let system_bin = test_directory.join("usr/bin");
+        std::fs::create_dir_all(&tools_bin).unwrap();
+        std::fs::create_dir_all(&system_bin).unwrap();
+        let binary_name = format!("xtensa-esp32-elf-gcc{}", env::consts::EXE_SUFFIX);
+        std::fs::write(tools_bin.join(&binary_name), "").unwrap();
+        std::fs::write(system_bin.join(&binary_name), "").unwrap();
+        let tools_path = test_directory.join("tools").display().to_string();
+
+        let conflicts = find_path_conflicts(&[system_bin.clone(), tools_bin.clone()], &tools_path, "xtensa-esp32-elf-gcc");
+        assert_eq!(conflicts, vec![system_bin.join(&binary_name)]);
+        assert!(find_path_conflicts(&[tools_bin], &tools_path, "xtensa-esp32-elf-gcc").is_empty());
+        assert!(find_path_conflicts(&[system_bin], &tools_path, "riscv32-esp-elf-gcc").is_empty());
+    }
+
+    #[test]
+    fn test_apply_clean_environment() {
+        let host_variables: Vec<String> = ["PYTHONPATH", "IDF_ENV_TEST_LEAK", "IDF_PATH", "HOME"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let environment = IdfEnvironment {
+            variables: vec![("IDF_PATH".to_string(), "/esp/esp-idf".to_string())],
+            paths: vec!["/esp/tools/cmake/bin".to_string()],
+            ..IdfEnvironment::default()
+        }
+        .with_base_paths(vec!["/usr/bin".to_string()], &host_variables);
+        assert_eq!(environment.removed_variables, vec!["PYTHONPATH".to_string(), "IDF_ENV_TEST_LEAK".to_string()]);
+
+        let mut command = std::process::Command::new("true");
+        environment.apply(&mut command);
+        let envs: Vec<(String, Option<String>)> = command
+            .get_envs()
+            .map(|(name, value)| {
+                (name.to_string_lossy().to_string(), value.map(|value| value.to_string_lossy().to_string()))
+            })
+            .collect();
+        assert!(envs.contains(&("PYTHONPATH".to_string(), None)));
+        assert!(envs.contains(&("IDF_ENV_TEST_LEAK".to_string(), None)));
+        assert!(envs.contains(&("IDF_PATH".to_string(), Some("/esp/esp-idf".to_string()))));
+        let expected_path = env::join_paths(["/esp/tools/cmake/bin", "/usr/bin"]).unwrap();
+        assert!(envs.contains(&("PATH".to_string(), Some(expected_path.to_string_lossy().to_string()))));
+    }
+}
@@ -0,0 +1,131 @@
+use clap::{AppSettings, Arg};
+use clap_nested::Command;
+use std::env::consts::EXE_SUFFIX;
+use std::path::{Path, PathBuf};
+
+use super::environment::{get_activation_environment, get_clean_arg, get_python_bin_path, IdfEnvironment};
+use super::installation::resolve_installation;
+use crate::config::load_json;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Python scripts like idf.py can't be started directly on Windows, they are passed to Python
+/// of the installation instead. Scripts are looked up in PATH entries of the environment.
+fn find_python_script(environment: &IdfEnvironment, program: &str) -> Option<PathBuf> {
+    if !program.ends_with(".py") {
+        return None;
+    }
+    if Path::new(program).is_file() {
+        return Some(PathBuf::from(program));
+    }
+    environment
+        .paths
+        .iter()
+        .map(|path| Path::new(path).join(program))
+        .find(|script| script.is_file())
+}
+
+pub fn get_command(environment: &IdfEnvironment, arguments: &[&str]) -> std::process::Command {
+    let program = arguments[0];
+    let python_env_path = environment.get_variable("IDF_PYTHON_ENV_PATH");
+    let mut command = match (find_python_script(environment, program), python_env_path) {
+        (Some(script), Some(python_env_path)) => {
+            let python = format!("{}/python{}", get_python_bin_path(python_env_path), EXE_SUFFIX);
+            let mut command = std::process::Command::new(python);
+            command.arg(script);
+            command
+        }
+        _ => std::process::Command::new(program),
+    };
+    command.args(&arguments[1..]);
+    environment.apply(&mut command);
+    command
+}
+
+/// Run the command with inherited stdio and return its exit code.
+fn execute(idf_id: Option<&str>, name: Option<&str>, arguments: &[&str], clean: bool) -> Result<i32> {
+    let config = load_json();
+    let idf_id = match idf_id {
+        Some(idf_id) if config["idfInstalled"][idf_id].is_null() => {
+            return Err(format!("Installation {} not found", idf_id).into())
+        }
+        Some(idf_id) => idf_id.to_string(),
+        None => resolve_installation(&config, name)?,
+    };
+    let environment = get_activation_environment(&config, &idf_id, clean)?;
+    let status = get_command(&environment, arguments)
+        .status()
+        .map_err(|e| format!("Unable to start {}: {}", arguments[0], e))?;
+    // Process terminated by signal has no exit code
+    Ok(status.code().unwrap_or(1))
+}
+
+fn get_exec_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    let arguments: Vec<&str> = matches.values_of("command").unwrap().collect();
+    match execute(
+        matches.value_of("idf-id"),
+        matches.value_of("name"),
+        &arguments,
+        matches.is_present("clean"),
+    ) {
+        Ok(exit_code) => std::process::exit(exit_code),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+pub fn get_exec_cmd<'a>() -> Command<'a, str> {
+    Command::new("exec")
+        .description("Run command in environment of ESP-IDF installation, e.g. idf-env idf exec -- idf.py build")
+        .options(|app| {
+            app.setting(AppSettings::TrailingVarArg)
+                .arg(
+                    Arg::with_name("idf-id")
+                        .long("idf-id")
+                        .help("Id of installation, selected installation is used by default")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("name")
+                        .long("name")
+                        .help("Version, path or directory name of installation")
+                        .takes_value(true)
+                        .conflicts_with("idf-id"),
+                )
+                .arg(get_clean_arg())
+                .arg(
+                    Arg::with_name("command")
+                        .help("Command with arguments")
+                        .required(true)
+                        .multiple(true)
+                        .last(true),
+                )
+        })
+        .runner(|_args, matches| get_exec_runner(_args, matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDirectory;
+
+    #[test]
+    fn test_find_python_script() {
+        let test_directory = TestDirectory::new("exec");
+        let tools_path = &test_directory.path;
+        std::fs::write(tools_path.join("idf.py"), "").unwrap();
+        let environment = IdfEnvironment {
+            variables: Vec::new(),
+            paths: vec![tools_path.display().to_string()],
+            ..IdfEnvironment::default()
+        };
+        assert_eq!(find_python_script(&environment, "idf.py"), Some(tools_path.join("idf.py")));
+        assert_eq!(find_python_script(&environment, "esptool.py"), None);
+        assert_eq!(find_python_script(&environment, "cmake"), None);
+    }
+}
@@ -0,0 +1,70 @@
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+pub fn normalize_path(path: &str) -> String {
+    path.replace('\\', "/").trim_end_matches('/').to_string()
+}
+
+/// Find installations matching id, version, path or directory name of the checkout.
+pub fn find_installations(config: &json::JsonValue, query: &str) -> Vec<String> {
+    let normalized_query = normalize_path(query);
+    config["idfInstalled"]
+        .entries()
+        .filter(|(idf_id, idf)| {
+            let idf_path = normalize_path(&idf["path"].to_string());
+            let directory_name = idf_path.rsplit('/').next().unwrap_or("").to_string();
+            *idf_id == query
+                || idf["version"] == query
+                || idf_path == normalized_query
+                || directory_name == query
+        })
+        .map(|(idf_id, _)| idf_id.to_string())
+        .collect()
+}
+
+/// Return id of installation matching the query, the selected installation is used without query.
+pub fn resolve_installation(config: &json::JsonValue, query: Option<&str>) -> Result<String> {
+    let query = match query {
+        Some(query) => query,
+        None => {
+            let selected_idf_id = config["idfSelectedId"].to_string();
+            if config["idfInstalled"][selected_idf_id.as_str()].is_null() {
+                return Err("No installation of ESP-IDF is selected".into());
+            }
+            return Ok(selected_idf_id);
+        }
+    };
+    let matching_ids = find_installations(config, query);
+    match matching_ids.len() {
+        0 => Err(format!("No installation of ESP-IDF matches {}", query).into()),
+        1 => Ok(matching_ids[0].clone()),
+        _ => Err(format!(
+            "{} is ambiguous, use one of ids: {}",
+            query,
+            matching_ids.join(", ")
+        )
+        .into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_installations() {
+        let config = json::object! {
+            "idfSelectedId": "esp-idf-2",
+            "idfInstalled": {
+                "esp-idf-1": { "version": "v5.1.2", "path": "C:\\esp\\esp-idf-v5.1", "python": "" },
+                "esp-idf-2": { "version": "v5.2", "path": "/home/user/esp/esp-idf", "python": "" }
+            }
+        };
+        assert_eq!(find_installations(&config, "esp-idf-2"), vec!["esp-idf-2"]);
+        assert_eq!(find_installations(&config, "v5.1.2"), vec!["esp-idf-1"]);
+        assert_eq!(find_installations(&config, "C:/esp/esp-idf-v5.1/"), vec!["esp-idf-1"]);
+        assert_eq!(find_installations(&config, "esp-idf"), vec!["esp-idf-2"]);
+        assert!(find_installations(&config, "v4.4").is_empty());
+        assert_eq!(resolve_installation(&config, None).unwrap(), "esp-idf-2");
+        assert!(resolve_installation(&config, Some("v4.4")).is_err());
+    }
+}
@@ -0,0 +1,316 @@
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use super::benchmark::{get_build_installation, get_project_path};
+use super::environment::IdfEnvironment;
+use super::exec::get_command;
+use crate::package::format_size;
+use crate::project::{find_manifest, read_manifest, IDF_TARGETS};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Build directories and logs of targets are stored in the project, e.g. build-matrix/esp32s3
+const MATRIX_DIRECTORY: &str = "build-matrix";
+
+pub struct TargetBuild {
+    pub target: String,
+    pub success: bool,
+    pub seconds: f64,
+    /// Size of application binary, None when the build failed
+    pub binary_size: Option<u64>,
+}
+
+pub struct MatrixReport {
+    pub idf_path: String,
+    pub idf_version: String,
+    pub project: String,
+    pub builds: Vec<TargetBuild>,
+}
+
+impl MatrixReport {
+    pub fn is_success(&self) -> bool {
+        self.builds.iter().all(|build| build.success)
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        let builds: Vec<json::JsonValue> = self
+            .builds
+            .iter()
+            .map(|build| {
+                json::object! {
+                    "target": build.target.as_str(),
+                    "success": build.success,
+                    "seconds": build.seconds,
+                    "binarySize": build.binary_size
+                }
+            })
+            .collect();
+        json::object! {
+            "idfPath": self.idf_path.as_str(),
+            "idfVersion": self.idf_version.as_str(),
+            "project": self.project.as_str(),
+            "builds": builds
+        }
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut lines = vec!["target,success,seconds,binary_size".to_string()];
+        for build in self.builds.iter() {
+            lines.push(format!(
+                "{},{},{:.3},{}",
+                build.target,
+                build.success,
+                build.seconds,
+                build.binary_size.map(|size| size.to_string()).unwrap_or_default()
+            ));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    pub fn format_table(&self) -> String {
+        let mut lines = vec![format!("{:<10} {:<6} {:>10} {:>12}", "Target", "Result", "Duration", "Binary size")];
+        for build in self.builds.iter() {
+            lines.push(format!(
+                "{:<10} {:<6} {:>10} {:>12}",
+                build.target,
+                if build.success { "pass" } else { "fail" },
+                format!("{:.2}s", build.seconds),
+                build.binary_size.map(format_size).unwrap_or_else(|| "-".to_string())
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Targets from --targets, or from [build] section of idf-env.toml of the project.
+pub fn get_matrix_targets(targets: Option<Vec<&str>>, project: Option<&str>) -> Result<Vec<String>> {
+    let targets: Vec<String> = match targets {
+        Some(targets) => targets.iter().map(|target| target.to_string()).collect(),
+        None => match project.and_then(|project| find_manifest(Path::new(project))) {
+            Some(manifest_path) => read_manifest(&manifest_path)?.build_targets,
+            None => Vec::new(),
+        },
+    };
+    match targets.iter().find(|target| !IDF_TARGETS.contains(&target.as_str())) {
+        Some(target) => Err(format!("Unknown target {}, use one of: {}", target, IDF_TARGETS.join(", ")).into()),
+        None => Ok(targets),
+    }
+}
+
+/// Application binary listed in project_description.json generated by CMake.
+fn get_binary_size(build_dir: &str) -> Option<u64> {
+    let description = fs::read_to_string(format!("{}/project_description.json", build_dir)).ok()?;
+    let app_bin = json::parse(&description).ok()?["app_bin"].as_str()?.to_string();
+    fs::metadata(format!("{}/{}", build_dir, app_bin)).ok().map(|metadata| metadata.len())
+}
+
+fn run_target_idf_py(
+    environment: &IdfEnvironment,
+    project: &str,
+    build_dir: &str,
+    arguments: &[&str],
+    log: Option<&File>,
+) -> Result<bool> {
+    // Each target has its own sdkconfig, so that builds can run in parallel
+    let sdkconfig = format!("SDKCONFIG={}/sdkconfig", build_dir);
+    let mut full_arguments = vec!["idf.py", "-C", project, "-B", build_dir, "-D", &sdkconfig];
+    full_arguments.extend_from_slice(arguments);
+    let mut command = get_command(environment, &full_arguments);
+    if let Some(log) = log {
+        command.stdout(log.try_clone()?).stderr(log.try_clone()?);
+    }
+    let status = command
+        .stdin(Stdio::null())
+        .status()
+        .map_err(|e| format!("Unable to start idf.py: {}", e))?;
+    Ok(status.success())
+}
+
+fn build_target(environment: &IdfEnvironment, project: &str, target: &str, verbose: bool) -> Result<TargetBuild> {
+    let matrix_dir = format!("{}/{}", project, MATRIX_DIRECTORY);
+    fs::create_dir_all(&matrix_dir)?;
+    let build_dir = format!("{}/{}", matrix_dir, target);
+    // idf.py set-target cleans the build directory, the log is stored next to it
+    let log_path = format!("{}/{}.log", matrix_dir, target);
+    let log = if verbose { None } else { Some(File::create(&log_path)?) };
+
+    let start = Instant::now();
+    let success = run_target_idf_py(environment, project, &build_dir, &["set-target", target], log.as_ref())?
+        && run_target_idf_py(environment, project, &build_dir, &["build"], log.as_ref())?;
+    let seconds = start.elapsed().as_secs_f64();
+    if !success && !verbose {
+        println!("Build for {} failed, see {}", target, log_path);
+    }
+    Ok(TargetBuild {
+        target: target.to_string(),
+        success,
+        seconds,
+        binary_size: if success { get_binary_size(&build_dir) } else { None },
+    })
+}
+
+/// Component manager downloads dependencies to managed_components and writes dependencies.lock
+/// in the project directory, both are shared by build directories of all targets.
+fn uses_component_manager(project: &str) -> bool {
+    let project = Path::new(project);
+    if project.join("idf_component.yml").is_file() || project.join("main/idf_component.yml").is_file() {
+        return true;
+    }
+    match fs::read_dir(project.join("components")) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .any(|entry| entry.path().join("idf_component.yml").is_file()),
+        Err(_e) => false,
+    }
+}
+
+/// Build the project for each target in separate build directory. Up to jobs targets are built
+/// at the same time, the report keeps order of targets.
+pub fn run_matrix(
+    query: Option<&str>,
+    tools_path: Option<&str>,
+    project: Option<&str>,
+    targets: &[String],
+    jobs: usize,
+    verbose: bool,
+) -> Result<MatrixReport> {
+    let (idf_path, idf_version, environment) = get_build_installation(query, tools_path)?;
+    let project = get_project_path(&idf_path, project)?;
+    if jobs > 1 && targets.len() > 1 && uses_component_manager(&project) {
+        return Err(format!(
+            "{} uses the component manager, its managed_components and dependencies.lock are shared by targets, use --jobs 1",
+            project
+        )
+        .into());
+    }
+
+    println!("ESP-IDF Path: {}", idf_path);
+    println!("Project: {}", project);
+    println!("Targets: {}", targets.join(", "));
+    let queue: Mutex<VecDeque<&String>> = Mutex::new(targets.iter().collect());
+    let builds: Mutex<Vec<TargetBuild>> = Mutex::new(Vec::new());
+    let errors: Mutex<Vec<String>> = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, targets.len().max(1)) {
+            scope.spawn(|| loop {
+                let target = match queue.lock().unwrap().pop_front() {
+                    Some(target) => target,
+                    None => break,
+                };
+                println!("Building for {}", target);
+                match build_target(&environment, &project, target, verbose) {
+                    Ok(build) => builds.lock().unwrap().push(build),
+                    Err(e) => errors.lock().unwrap().push(format!("{}: {}", target, e)),
+                }
+            });
+        }
+    });
+    let errors = errors.into_inner().unwrap();
+    if !errors.is_empty() {
+        return Err(errors.join(", ").into());
+    }
+    let mut builds = builds.into_inner().unwrap();
+    builds.sort_by_key(|build| targets.iter().position(|target| *target == build.target));
+
+    Ok(MatrixReport {
+        idf_path,
+        idf_version,
+        project,
+        builds,
+    })
+}
+
+/// Run matrix requested by idf build, the process fails when any target fails.
+pub fn run_matrix_command(matches: &clap::ArgMatches<'_>, targets: &[String]) {
+    let jobs: usize = match matches.value_of("jobs").unwrap().parse() {
+        Ok(jobs) => jobs,
+        Err(_e) => {
+            println!("Number of jobs must be a positive number");
+            return;
+        }
+    };
+    let report = match run_matrix(
+        matches.value_of("idf-path"),
+        matches.value_of("tools-path"),
+        matches.value_of("project"),
+        targets,
+        jobs,
+        matches.is_present("verbose"),
+    ) {
+        Ok(report) => report,
+        Err(e) => {
+            println!("Build failed: {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!("{}", report.format_table());
+
+    if let Some(output) = matches.value_of("output") {
+        let content = if output.ends_with(".csv") {
+            report.to_csv()
+        } else {
+            format!("{:#}", report.to_json())
+        };
+        match fs::write(output, content) {
+            Ok(_) => println!("Report written to {}", output),
+            Err(e) => println!("Unable to write report {}: {}", output, e),
+        }
+    }
+    if !report.is_success() {
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDirectory;
+
+    #[test]
+    fn test_format_table() {
+        let report = MatrixReport {
+            idf_path: "/esp/esp-idf".to_string(),
+            idf_version: "v5.1.2".to_string(),
+            project: "/esp/blink".to_string(),
+            builds: vec![
+                TargetBuild {
+                    target: "esp32".to_string(),
+                    success: true,
+                    seconds: 42.5,
+                    binary_size: Some(180 * 1024),
+                },
+                TargetBuild {
+                    target: "esp32c6".to_string(),
+                    success: false,
+                    seconds: 3.0,
+                    binary_size: None,
+                },
+            ],
+        };
+        assert!(!report.is_success());
+        assert_eq!(
+            report.format_table(),
+            "Target     Result   Duration  Binary size\n\
+             esp32      pass       42.50s     180.0 KB\n\
+             esp32c6    fail        3.00s            -"
+        );
+        assert_eq!(report.to_csv(), "target,success,seconds,binary_size\nesp32,true,42.500,184320\nesp32c6,false,3.000,\n");
+        assert!(get_matrix_targets(Some(vec!["esp32", "esp8266"]), None).is_err());
+    }
+
+    #[test]
+    fn test_uses_component_manager() {
+        let test_directory = TestDirectory::new("matrix");
+        let test_path = &test_directory.path;
+        fs::create_dir_all(test_path.join("main")).unwrap();
+        fs::create_dir_all(test_path.join("components/sensor")).unwrap();
+        let project = test_path.display().to_string();
+        assert!(!uses_component_manager(&project));
+        fs::write(test_path.join("components/sensor/idf_component.yml"), "dependencies: {}\n").unwrap();
+        assert!(uses_component_manager(&project));
+    }
+}
@@ -0,0 +1,489 @@
+use clap::Arg;
+use clap_nested::Command;
+use git2::build::CheckoutBuilder;
+use git2::Repository;
+use json::JsonValue;
+
+use super::release::IDF_REPOSITORY_URL;
+use super::status::{find_local_changes, get_stash_arg, protect_local_changes};
+use super::submodule::{update_submodules_parallel, UpdateOptions};
+use super::worktree::resolve_submodule_url;
+use crate::config::{
+    get_git_path, get_idf_id, get_selected_idf_path, load_json, update_idf_property,
+    update_json_property,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Name of profile created from --url and --submodule-url
+const CUSTOM_PROFILE: &str = "custom";
+
+/// Location of ESP-IDF and its submodules on a mirror. Submodule URLs are created from template
+/// with {owner} and {repo} placeholders taken from the upstream URL, e.g. espressif and esptool.git.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MirrorProfile {
+    pub name: String,
+    pub url: String,
+    pub submodule_url: String,
+    /// Repositories which don't follow the template, upstream repository name -> mirror URL
+    pub submodules: Vec<(String, String)>,
+}
+
+impl MirrorProfile {
+    pub fn from_json(name: &str, profile: &JsonValue) -> MirrorProfile {
+        MirrorProfile {
+            name: name.to_string(),
+            url: profile["url"].to_string(),
+            submodule_url: profile["submoduleUrl"].to_string(),
+            submodules: profile["submodules"]
+                .entries()
+                .map(|(repo, url)| (repo.to_string(), url.to_string()))
+                .collect(),
+        }
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut submodules = JsonValue::new_object();
+        for (repo, url) in self.submodules.iter() {
+            submodules[repo.as_str()] = url.as_str().into();
+        }
+        json::object! {
+            "url": self.url.as_str(),
+            "submoduleUrl": self.submodule_url.as_str(),
+            "submodules": submodules
+        }
+    }
+
+    /// Mirror URL of submodule, None when the submodule is not hosted on GitHub and it's kept.
+    pub fn get_submodule_url(&self, upstream_url: &str) -> Option<String> {
+        let upstream_url = resolve_submodule_url(IDF_REPOSITORY_URL, upstream_url);
+        let path = upstream_url.strip_prefix("https://github.com/")?;
+        let mut segments = path.rsplit('/');
+        let repo = segments.next()?;
+        let owner = segments.next().unwrap_or("");
+        if let Some((_, url)) = self.submodules.iter().find(|(name, _)| name == repo) {
+            return Some(url.clone());
+        }
+        Some(self.submodule_url.replace("{owner}", owner).replace("{repo}", repo))
+    }
+}
+
+/// Hosts which mirror Unity and CException under capitalized names instead of the upstream ones
+const CAPITALIZED_HOSTS: [&str; 1] = ["gitee.com"];
+
+/// Submodules of profile whose mirror name differs from upstream, derived from host of the template.
+fn get_submodule_fixups(submodule_url: &str) -> Vec<(String, String)> {
+    let host = submodule_url
+        .split("://")
+        .nth(1)
+        .and_then(|location| location.split('/').next())
+        .unwrap_or("");
+    if !CAPITALIZED_HOSTS.contains(&host) {
+        return Vec::new();
+    }
+    [("unity.git", "Unity"), ("cexception.git", "CException")]
+        .iter()
+        .map(|(repo, name)| {
+            let url = submodule_url.replace("{owner}", "ThrowTheSwitch").replace("{repo}", name);
+            (repo.to_string(), url)
+        })
+        .collect()
+}
+
+fn get_builtin_profiles() -> Vec<MirrorProfile> {
+    vec![
+        MirrorProfile {
+            name: "github".to_string(),
+            url: IDF_REPOSITORY_URL.to_string(),
+            submodule_url: "https://github.com/{owner}/{repo}".to_string(),
+            submodules: Vec::new(),
+        },
+        MirrorProfile {
+            name: "gitee".to_string(),
+            url: "https://gitee.com/EspressifSystems/esp-idf.git".to_string(),
+            submodule_url: "https://gitee.com/esp-submodules/{repo}".to_string(),
+            submodules: get_submodule_fixups("https://gitee.com/esp-submodules/{repo}"),
+        },
+        MirrorProfile {
+            name: "jihulab".to_string(),
+            url: "https://jihulab.com/esp-mirror/espressif/esp-idf.git".to_string(),
+            submodule_url: "https://jihulab.com/esp-mirror/{owner}/{repo}".to_string(),
+            submodules: Vec::new(),
+        },
+    ]
+}
+
+/// Profiles stored in mirrorProfiles of the configuration take precedence over built-in ones.
+pub fn get_mirror_profile(config: &JsonValue, name: &str) -> Option<MirrorProfile> {
+    if !config["mirrorProfiles"][name].is_null() {
+        return Some(MirrorProfile::from_json(name, &config["mirrorProfiles"][name]));
+    }
+    get_builtin_profiles().into_iter().find(|profile| profile.name == name)
+}
+
+fn get_profile_names(config: &JsonValue) -> Vec<String> {
+    let mut names: Vec<String> = get_builtin_profiles().into_iter().map(|profile| profile.name).collect();
+    for (name, _) in config["mirrorProfiles"].entries() {
+        if !names.iter().any(|known_name| known_name == name) {
+            names.push(name.to_string());
+        }
+    }
+    names
+}
+
+/// Restore .gitmodules of the checked out commit, i.e. upstream URLs of submodules.
+fn restore_gitmodules(repo: &Repository) -> Result<()> {
+    repo.checkout_head(Some(CheckoutBuilder::new().force().path(".gitmodules")))?;
+    Ok(())
+}
+
+/// Point submodules of the repository to the mirror. URLs are written to .gitmodules
+/// and synced to configuration of initialized submodules.
+pub fn change_submodules_mirror(repo: &mut Repository, profile: &MirrorProfile) {
+    if let Err(e) = restore_gitmodules(repo) {
+        println!("Unable to restore .gitmodules: {}", e);
+    }
+    let mut change_set: Vec<(String, String)> = Vec::new();
+    for submodule in repo.submodules().unwrap() {
+        let repo_name = submodule.name().unwrap().to_string();
+        let original_url = submodule.url().unwrap();
+
+        match profile.get_submodule_url(original_url) {
+            Some(new_url) => change_set.push((repo_name, new_url)),
+            None => println!("Submodule: {}, URL: {} - skip", repo_name, original_url),
+        }
+    }
+
+    for submodule in change_set {
+        println!("Submodule: {}, new URL: {}", submodule.0, submodule.1);
+        let result = repo
+            .submodule_set_url(&submodule.0, &submodule.1)
+            .and_then(|_| repo.find_submodule(&submodule.0))
+            .and_then(|mut changed_submodule| changed_submodule.sync());
+        match result {
+            Ok(_) => {
+                println!("Ok");
+            }
+            Err(_e) => {
+                println!("Failed");
+            }
+        }
+    }
+}
+
+/// Restore upstream URLs of the repository and its initialized submodules recursively.
+fn reset_mirror(repo: &Repository) -> Result<()> {
+    restore_gitmodules(repo)?;
+    for mut submodule in repo.submodules()? {
+        submodule.sync()?;
+        if let Ok(submodule_repo) = submodule.open() {
+            reset_mirror(&submodule_repo)?;
+        }
+    }
+    Ok(())
+}
+
+fn print_remotes(repo: &Repository, indent: usize) -> Result<()> {
+    for submodule in repo.submodules()? {
+        let submodule_repo = submodule.open().ok();
+        let url = submodule_repo
+            .as_ref()
+            .and_then(|submodule_repo| submodule_repo.find_remote("origin").ok())
+            .and_then(|remote| remote.url().map(|url| url.to_string()))
+            .unwrap_or_else(|| format!("{} (not initialized)", submodule.url().unwrap_or_default()));
+        println!("{:indent$}{}: {}", "", submodule.name().unwrap_or_default(), url, indent = indent);
+        if let Some(submodule_repo) = submodule_repo {
+            print_remotes(&submodule_repo, indent + 2)?;
+        }
+    }
+    Ok(())
+}
+
+fn show_mirror(idf_path: &str) -> Result<()> {
+    let config = load_json();
+    let profile = &config["idfInstalled"][get_idf_id(idf_path).as_str()]["mirrorProfile"];
+    let repo = Repository::open(idf_path)?;
+    println!("Profile: {}", if profile.is_null() { "none" } else { profile.as_str().unwrap_or("") });
+    println!("Available profiles: {}", get_profile_names(&config).join(", "));
+    let origin = repo.find_remote("origin")?;
+    println!("origin: {}", origin.url().unwrap_or_default());
+    print_remotes(&repo, 2)
+}
+
+fn get_custom_profile(url: &str, submodule_url: &str) -> MirrorProfile {
+    let submodule_url = if submodule_url.contains("{repo}") {
+        submodule_url.to_string()
+    } else {
+        format!("{}{{repo}}", submodule_url)
+    };
+    MirrorProfile {
+        name: CUSTOM_PROFILE.to_string(),
+        url: url.to_string(),
+        submodules: get_submodule_fixups(&submodule_url),
+        submodule_url,
+    }
+}
+
+/// Profile selected by --profile, or custom profile from --url and --submodule-url which is
+/// stored in configuration. Bare --submodule-url is a base to which repository name is appended.
+fn get_requested_profile(matches: &clap::ArgMatches<'_>) -> Result<MirrorProfile> {
+    let config = load_json();
+    if let Some(name) = matches.value_of("profile") {
+        return get_mirror_profile(&config, name).ok_or_else(|| {
+            format!(
+                "Unknown mirror profile {}, available profiles: {}",
+                name,
+                get_profile_names(&config).join(", ")
+            )
+            .into()
+        });
+    }
+    let submodule_url = matches
+        .value_of("submodule-url")
+        .ok_or("Use --profile, --submodule-url, --reset or --show")?;
+    let profile = get_custom_profile(matches.value_of("url").unwrap_or(""), submodule_url);
+    let mut profiles = config["mirrorProfiles"].clone();
+    if profiles.is_null() {
+        profiles = JsonValue::new_object();
+    }
+    profiles[CUSTOM_PROFILE] = profile.to_json();
+    update_json_property("mirrorProfiles", profiles);
+    Ok(profile)
+}
+
+/// Mirror profile used by the installation is remembered, so that idf switch can apply it
+/// to submodules of other versions.
+fn remember_profile(idf_path: &str, profile: JsonValue) {
+    let idf_id = get_idf_id(idf_path);
+    if !load_json()["idfInstalled"][idf_id.as_str()].is_null() {
+        update_idf_property(&idf_id, "mirrorProfile", profile);
+    }
+}
+
+/// .gitmodules of the repository and submodules is restored before URLs are changed. It's ignored
+/// when the installation already uses a mirror, because the mirror rewrote it.
+fn check_local_changes(idf_path: &str, matches: &clap::ArgMatches<'_>) -> Result<()> {
+    let has_mirror = !load_json()["idfInstalled"][get_idf_id(idf_path).as_str()]["mirrorProfile"].is_null();
+    let changes = find_local_changes(idf_path, false, has_mirror)?;
+    protect_local_changes(&changes, matches.is_present("stash"), matches.is_present("force"), "mirror")
+}
+
+fn get_mirror_switch_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    let idf_path = matches
+        .value_of("idf-path")
+        .unwrap_or(&*get_selected_idf_path())
+        .to_string();
+
+    if matches.is_present("show") {
+        if let Err(e) = show_mirror(&idf_path) {
+            println!("Failed: {}", e);
+        }
+        return Ok(());
+    }
+
+    if let Err(e) = check_local_changes(&idf_path, matches) {
+        println!("{}", e);
+        std::process::exit(1);
+    }
+
+    if matches.is_present("reset") {
+        println!("Restoring upstream URLs: {}", idf_path);
+        let result = Repository::open(&idf_path)
+            .map_err(|e| e.into())
+            .and_then(|repo| {
+                repo.remote_set_url("origin", IDF_REPOSITORY_URL)?;
+                reset_mirror(&repo)
+            });
+        match result {
+            Ok(_) => {
+                remember_profile(&idf_path, JsonValue::Null);
+                println!("Ok");
+            }
+            Err(e) => {
+                println!("Failed: {}", e);
+            }
+        }
+        return Ok(());
+    }
+
+    let profile = match get_requested_profile(matches) {
+        Ok(profile) => profile,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Processing main repository...");
+    match Repository::open(idf_path.clone()) {
+        Ok(mut repo) => {
+            if !profile.url.is_empty() {
+                match repo.remote_set_url("origin", profile.url.as_str()) {
+                    Ok(_) => {
+                        println!("Ok");
+                    }
+                    Err(_e) => {
+                        println!("Failed");
+                    }
+                }
+            }
+
+            change_submodules_mirror(&mut repo, &profile);
+        }
+        Err(e) => {
+            println!("failed to open: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    println!("Processing submodules...");
+    let options = UpdateOptions {
+        git_path: get_git_path(),
+        jobs: matches
+            .value_of("jobs")
+            .and_then(|jobs| jobs.parse().ok())
+            .unwrap_or_else(num_cpus::get),
+        depth: matches.value_of("depth").and_then(|depth| depth.parse().ok()),
+        progress: matches.is_present("progress"),
+        ..UpdateOptions::default()
+    };
+    let hook = |sub_repo: &mut Repository| {
+        println!("Processing submodule: {:?}", sub_repo.workdir().unwrap());
+        change_submodules_mirror(sub_repo, &profile);
+    };
+    match update_submodules_parallel(&idf_path, &options, Some(&hook)) {
+        Ok(report) => report.print_summary(),
+        Err(e) => {
+            println!("failed to open: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    remember_profile(&idf_path, profile.name.as_str().into());
+    Ok(())
+}
+
+pub fn get_mirror_cmd<'a>() -> Command<'a, str> {
+    Command::new("mirror")
+        .description("Switch the URL of repository mirror")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("profile")
+                    .short("m")
+                    .long("profile")
+                    .help("Name of mirror profile, e.g. github, gitee or jihulab")
+                    .takes_value(true)
+                    .conflicts_with_all(&["url", "submodule-url"]),
+            )
+            .arg(
+                Arg::with_name("url")
+                    .short("u")
+                    .long("url")
+                    .help("Base URL of the main repo")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("idf-path")
+                    .short("p")
+                    .long("idf-path")
+                    .help("Path to ESP IDF source code repository")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("submodule-url")
+                    .short("s")
+                    .long("submodule-url")
+                    .help("Base URL for submodule mirror, stored as custom profile")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("reset")
+                    .long("reset")
+                    .help("Restore upstream URLs of the repository and submodules")
+                    .conflicts_with_all(&["profile", "url", "submodule-url", "show"]),
+            )
+            .arg(
+                Arg::with_name("show")
+                    .long("show")
+                    .help("Print remote URLs of the repository and submodules"),
+            )
+            .arg(
+                Arg::with_name("depth")
+                    .short("d")
+                    .long("depth")
+                    .help("Create shallow clone of the repo and submodules")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("progress")
+                    .short("r")
+                    .long("progress")
+                    .help("Display progress status of git operation"),
+            )
+            .arg(
+                Arg::with_name("jobs")
+                    .short("j")
+                    .long("jobs")
+                    .help("Number of submodules updated in parallel, number of CPU cores by default")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("force")
+                    .short("f")
+                    .long("force")
+                    .help("Proceed even when ESP-IDF has local modifications, .gitmodules is overwritten"),
+            )
+            .arg(get_stash_arg())
+        })
+        .runner(|_args, matches| get_mirror_switch_runner(_args, matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_submodule_url() {
+        let config = json::object! {
+            "mirrorProfiles": {
+                "company": { "url": "https://git.example.com/esp-idf.git", "submoduleUrl": "https://git.example.com/{repo}" }
+            }
+        };
+        let gitee = get_mirror_profile(&config, "gitee").unwrap();
+        assert_eq!(
+            gitee.get_submodule_url("../../espressif/esptool.git"),
+            Some("https://gitee.com/esp-submodules/esptool.git".to_string())
+        );
+        assert_eq!(
+            gitee.get_submodule_url("../../ThrowTheSwitch/unity.git"),
+            Some("https://gitee.com/esp-submodules/Unity".to_string())
+        );
+        assert_eq!(gitee.get_submodule_url("https://gitlab.com/other/repo.git"), None);
+        let jihulab = get_mirror_profile(&config, "jihulab").unwrap();
+        assert_eq!(
+            jihulab.get_submodule_url("https://github.com/espressif/esp-mqtt.git"),
+            Some("https://jihulab.com/esp-mirror/espressif/esp-mqtt.git".to_string())
+        );
+        let company = get_mirror_profile(&config, "company").unwrap();
+        assert_eq!(company, MirrorProfile::from_json("company", &company.to_json()));
+        assert_eq!(
+            company.get_submodule_url("../../espressif/esptool.git"),
+            Some("https://git.example.com/esptool.git".to_string())
+        );
+        assert!(get_mirror_profile(&config, "unknown").is_none());
+
+        let custom = get_custom_profile("", "https://gitee.com/company-mirror/");
+        assert_eq!(
+            custom.get_submodule_url("../../throwtheswitch/cexception.git"),
+            Some("https://gitee.com/company-mirror/CException".to_string())
+        );
+        let custom = get_custom_profile("", "https://git.example.com/{owner}/{repo}");
+        assert_eq!(
+            custom.get_submodule_url("../../ThrowTheSwitch/unity.git"),
+            Some("https://git.example.com/ThrowTheSwitch/unity.git".to_string())
+        );
+    }
+}
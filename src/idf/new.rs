@@ -0,0 +1,232 @@
+use clap::Arg;
+use clap_nested::Command;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use super::benchmark::{run_idf_py, DEFAULT_PROJECT};
+use super::environment::get_idf_environment;
+use super::installation::{normalize_path, resolve_installation};
+use super::version::get_checkout_reference;
+use crate::config::load_json;
+use crate::project::{IdfRequirement, ProjectManifest, IDF_TARGETS, MANIFEST_FILE};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Build outputs and generated files which must not be copied from the example
+const SKIPPED_NAMES: [&str; 5] = ["build", "managed_components", "sdkconfig", "sdkconfig.old", "dependencies.lock"];
+
+fn check_project_name(name: &str) -> Result<()> {
+    let is_valid = !name.is_empty()
+        && name
+            .chars()
+            .all(|character| character.is_ascii_alphanumeric() || character == '_' || character == '-');
+    if is_valid {
+        Ok(())
+    } else {
+        Err(format!("Invalid project name {}, use letters, digits, '_' or '-'", name).into())
+    }
+}
+
+/// Replace name in project() call of top level CMakeLists.txt, other arguments are kept.
+pub fn rename_project(content: &str, name: &str) -> Option<String> {
+    let mut renamed = false;
+    let lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let indentation = &line[..line.len() - line.trim_start().len()];
+            match line.trim_start().strip_prefix("project(") {
+                Some(arguments) if !renamed => {
+                    let arguments = arguments.trim_start();
+                    let rest = arguments
+                        .find(|character: char| character.is_whitespace() || character == ')')
+                        .map(|end| &arguments[end..])
+                        .unwrap_or(")");
+                    renamed = true;
+                    format!("{}project({}{}", indentation, name, rest)
+                }
+                _ => line.to_string(),
+            }
+        })
+        .collect();
+    if !renamed {
+        return None;
+    }
+    let mut renamed_content = lines.join("\n");
+    if content.ends_with('\n') {
+        renamed_content.push('\n');
+    }
+    Some(renamed_content)
+}
+
+fn copy_project(source: &Path, destination: &Path) -> Result<()> {
+    let entries = WalkDir::new(source).into_iter().filter_entry(|entry| {
+        entry.depth() == 0
+            || !SKIPPED_NAMES.contains(&entry.file_name().to_str().unwrap_or_default())
+    });
+    for entry in entries {
+        let entry = entry?;
+        let target_path = destination.join(entry.path().strip_prefix(source)?);
+        if entry.file_type().is_dir() {
+            fs::create_dir_all(&target_path)?;
+        } else {
+            fs::copy(entry.path(), &target_path)?;
+        }
+    }
+    Ok(())
+}
+
+fn set_target(config: &json::JsonValue, idf_id: &str, project: &Path, target: &str, verbose: bool) -> Result<()> {
+    let environment = get_idf_environment(config, idf_id)?;
+    if !run_idf_py(&environment, &project.to_string_lossy(), &["set-target", target], verbose)? {
+        return Err(format!("idf.py set-target {} failed", target).into());
+    }
+    Ok(())
+}
+
+pub fn create_project(
+    query: Option<&str>,
+    name: &str,
+    example: &str,
+    target: Option<&str>,
+    path: Option<&str>,
+    verbose: bool,
+) -> Result<PathBuf> {
+    check_project_name(name)?;
+    if let Some(target) = target {
+        if !IDF_TARGETS.contains(&target) {
+            return Err(format!("Unknown target {}, use one of: {}", target, IDF_TARGETS.join(", ")).into());
+        }
+    }
+    let config = load_json();
+    let idf_id = resolve_installation(&config, query)?;
+    let idf = &config["idfInstalled"][idf_id.as_str()];
+    let idf_path = normalize_path(&idf["path"].to_string());
+    let source = Path::new(&idf_path).join(example);
+    if !source.join("CMakeLists.txt").exists() {
+        return Err(format!("{} is not ESP-IDF project", source.display()).into());
+    }
+    let destination = PathBuf::from(path.unwrap_or(name));
+    if destination.exists() {
+        return Err(format!("{} already exists", destination.display()).into());
+    }
+
+    println!("Copying {} to {}", source.display(), destination.display());
+    copy_project(&source, &destination)?;
+    let cmake_path = destination.join("CMakeLists.txt");
+    let content = fs::read_to_string(&cmake_path)?;
+    match rename_project(&content, name) {
+        Some(renamed_content) => fs::write(&cmake_path, renamed_content)?,
+        None => println!("Warning: project() not found in {}, name was not changed", cmake_path.display()),
+    }
+
+    if let Some(target) = target {
+        println!("Setting target {}", target);
+        if let Err(e) = set_target(&config, &idf_id, &destination, target, verbose) {
+            // Half-created project would block another attempt with the same name
+            if let Err(remove_error) = fs::remove_dir_all(&destination) {
+                println!("Unable to remove {}: {}", destination.display(), remove_error);
+            }
+            return Err(e);
+        }
+    }
+
+    let version = get_checkout_reference(&idf_path).unwrap_or_else(|| idf["version"].to_string());
+    let manifest = ProjectManifest {
+        idf: Some(IdfRequirement {
+            version,
+            targets: target.into_iter().map(|target| target.to_string()).collect(),
+        }),
+        ..ProjectManifest::default()
+    };
+    fs::write(destination.join(MANIFEST_FILE), manifest.to_toml())?;
+    Ok(destination)
+}
+
+fn get_new_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    let name = matches.value_of("name").unwrap();
+    match create_project(
+        matches.value_of("idf"),
+        name,
+        matches.value_of("from").unwrap(),
+        matches.value_of("target"),
+        matches.value_of("path"),
+        matches.is_present("verbose"),
+    ) {
+        Ok(destination) => println!("Project {} created in {}", name, destination.display()),
+        Err(e) => println!("Unable to create project {}: {}", name, e),
+    }
+    Ok(())
+}
+
+pub fn get_new_cmd<'a>() -> Command<'a, str> {
+    Command::new("new")
+        .description("Create project from example of ESP-IDF installation")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("name")
+                    .help("Name of the project")
+                    .required(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("from")
+                    .short("f")
+                    .long("from")
+                    .help("Example relative to ESP-IDF directory")
+                    .takes_value(true)
+                    .default_value(DEFAULT_PROJECT),
+            )
+            .arg(
+                Arg::with_name("target")
+                    .short("g")
+                    .long("target")
+                    .help("Target chip, e.g. esp32c3")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("path")
+                    .short("p")
+                    .long("path")
+                    .help("Directory of the new project, name of the project in current directory by default")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("idf")
+                    .long("idf")
+                    .help("Id, version or path of installation, selected installation is used by default")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("verbose")
+                    .short("w")
+                    .long("verbose")
+                    .help("Display output of idf.py"),
+            )
+        })
+        .runner(|_args, matches| get_new_runner(_args, matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_project() {
+        let content = "cmake_minimum_required(VERSION 3.16)\n\ninclude($ENV{IDF_PATH}/tools/cmake/project.cmake)\nproject(blink)\n";
+        assert_eq!(
+            rename_project(content, "my_app").unwrap(),
+            "cmake_minimum_required(VERSION 3.16)\n\ninclude($ENV{IDF_PATH}/tools/cmake/project.cmake)\nproject(my_app)\n"
+        );
+        assert_eq!(
+            rename_project("  project( hello_world LANGUAGES C )", "demo").unwrap(),
+            "  project(demo LANGUAGES C )"
+        );
+        assert_eq!(rename_project("idf_component_register(SRCS \"main.c\")\n", "demo"), None);
+        assert!(check_project_name("my-app_2").is_ok());
+        assert!(check_project_name("../app").is_err());
+    }
+}
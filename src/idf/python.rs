@@ -0,0 +1,431 @@
+use clap::Arg;
+use clap_nested::{Command, Commander, MultiCommand};
+use std::env::consts::EXE_SUFFIX;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use super::environment::get_python_bin_path;
+use super::installation::{normalize_path, resolve_installation};
+use super::run_process;
+use super::uninstall::remove_directory;
+use super::version::read_idf_version;
+use crate::config::{get_python_env_path, get_tools_path, load_json, update_idf_property};
+use crate::package::refresh_file;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Oldest Python supported by ESP-IDF 5.x
+const MINIMAL_PYTHON_VERSION: (u32, u32) = (3, 8);
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PythonInterpreter {
+    pub path: String,
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl PythonInterpreter {
+    /// Version used in names of Python environments, e.g. 3.11
+    pub fn get_major_minor(&self) -> String {
+        format!("{}.{}", self.major, self.minor)
+    }
+}
+
+/// Parse output of python --version, e.g. "Python 3.11.4"
+pub fn parse_python_version(text: &str) -> Option<(u32, u32)> {
+    let version = text.trim().strip_prefix("Python ")?;
+    let mut parts = version.split('.');
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.trim_end_matches(|c: char| !c.is_ascii_digit()).parse().ok()?;
+    Some((major, minor))
+}
+
+pub fn get_python_interpreter(python_path: &str) -> Option<PythonInterpreter> {
+    let output = std::process::Command::new(python_path)
+        .arg("--version")
+        .output()
+        .ok()?;
+    // Python 2 prints version to stderr
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let (major, minor) = parse_python_version(&text)?;
+    Some(PythonInterpreter {
+        path: python_path.to_string(),
+        major,
+        minor,
+    })
+}
+
+/// Debian and Ubuntu ship Python without venv and ensurepip unless python3-venv is installed.
+fn has_venv_module(python_path: &str) -> bool {
+    std::process::Command::new(python_path)
+        .args(["-c", "import venv, ensurepip"])
+        .output()
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+#[cfg(windows)]
+fn get_interpreter_candidates() -> Vec<String> {
+    vec![
+        crate::config::get_tool_path("idf-python/3.11.2/python.exe".to_string()),
+        crate::config::get_tool_path("idf-python/3.8.7/python.exe".to_string()),
+        "python3.exe".to_string(),
+        "python.exe".to_string(),
+    ]
+}
+
+#[cfg(unix)]
+fn get_interpreter_candidates() -> Vec<String> {
+    let mut candidates = vec!["python3".to_string()];
+    for minor in (8..=13).rev() {
+        candidates.push(format!("python3.{}", minor));
+    }
+    candidates.push("/usr/bin/python3".to_string());
+    candidates.push("python".to_string());
+    candidates
+}
+
+/// Interpreters on the system which can create environment for ESP-IDF, in order of preference.
+pub fn find_interpreters() -> Vec<PythonInterpreter> {
+    let mut interpreters: Vec<PythonInterpreter> = Vec::new();
+    for candidate in get_interpreter_candidates() {
+        let interpreter = match get_python_interpreter(&candidate) {
+            Some(interpreter) => interpreter,
+            None => continue,
+        };
+        if (interpreter.major, interpreter.minor) < MINIMAL_PYTHON_VERSION || !has_venv_module(&candidate) {
+            continue;
+        }
+        // python3 and python3.11 are often the same binary
+        if !interpreters.iter().any(|known| known.get_major_minor() == interpreter.get_major_minor()) {
+            interpreters.push(interpreter);
+        }
+    }
+    interpreters
+}
+
+fn select_interpreter(python: Option<&str>) -> Result<PythonInterpreter> {
+    match python {
+        Some(python_path) => get_python_interpreter(python_path)
+            .ok_or_else(|| format!("Unable to determine version of Python {}", python_path).into()),
+        None => find_interpreters().into_iter().next().ok_or_else(|| {
+            format!(
+                "No Python {}.{} or newer with venv module found",
+                MINIMAL_PYTHON_VERSION.0, MINIMAL_PYTHON_VERSION.1
+            )
+            .into()
+        }),
+    }
+}
+
+/// Python environment is stored as python_env/idf5.1_py3.11_env/bin/python in the configuration.
+/// Directories outside of python_env are never removed.
+pub fn get_python_env_directory(python_path: &str) -> Option<String> {
+    get_python_env_directory_in(python_path, &get_tools_path())
+}
+
+/// Python environment directory in the given tools directory instead of IDF_TOOLS_PATH
+pub fn get_python_env_directory_in(python_path: &str, tools_path: &str) -> Option<String> {
+    let python_env_root = format!("{}/python_env/", normalize_path(tools_path));
+    let env_directory = Path::new(python_path).parent()?.parent()?;
+    let env_directory = normalize_path(&env_directory.display().to_string());
+    if env_directory.starts_with(&python_env_root) {
+        Some(env_directory)
+    } else {
+        None
+    }
+}
+
+pub fn get_env_python_path(env_directory: &str) -> String {
+    format!("{}/python{}", get_python_bin_path(env_directory), EXE_SUFFIX)
+}
+
+/// Requirements of ESP-IDF 5.x are split by features, core is necessary for idf.py.
+fn get_requirements_path(idf_path: &str) -> Option<String> {
+    [
+        format!("{}/tools/requirements/requirements.core.txt", idf_path),
+        format!("{}/requirements.txt", idf_path),
+    ]
+    .iter()
+    .find(|path| Path::new(path).exists())
+    .cloned()
+}
+
+/// Constraints are published for each release and change with bugfix releases, idf_tools.py
+/// also downloads them again after a day.
+const CONSTRAINTS_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+
+fn is_fresh(path: &str, max_age: Duration) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .map(|age| age < max_age)
+        .unwrap_or(false)
+}
+
+/// Constraints file pins versions of packages for the release, it's stored in the same place
+/// as by idf_tools.py. Stale file is used when the update fails, e.g. offline.
+fn prepare_constraints(idf_path: &str) -> Option<String> {
+    let version = read_idf_version(idf_path)?.get_major_minor();
+    let file_name = format!("espidf.constraints.v{}.txt", version);
+    let constraints_path = format!("{}/{}", get_tools_path(), file_name);
+    if is_fresh(&constraints_path, CONSTRAINTS_MAX_AGE) {
+        return Some(constraints_path);
+    }
+    let url = format!("https://dl.espressif.com/dl/esp-idf/{}", file_name);
+    match refresh_file(url, constraints_path.clone()) {
+        Ok(updated) => {
+            if !updated {
+                // Restart max age, so that the server is not asked again until the next day
+                if let Ok(file) = std::fs::File::options().append(true).open(&constraints_path) {
+                    let _ = file.set_modified(SystemTime::now());
+                }
+            }
+            Some(constraints_path)
+        }
+        Err(e) if Path::new(&constraints_path).exists() => {
+            println!("Unable to update constraints {}, using cached file: {}", file_name, e);
+            Some(constraints_path)
+        }
+        Err(e) => {
+            println!("Unable to download constraints {}: {}", file_name, e);
+            None
+        }
+    }
+}
+
+pub fn install_requirements(idf_path: &str, python_path: &str, verbose: bool) -> Result<()> {
+    let requirements_path = get_requirements_path(idf_path)
+        .ok_or_else(|| format!("No requirements file found in {}", idf_path))?;
+    let pip = |arguments: &[&str]| -> Result<()> {
+        let mut full_arguments = vec!["-m".to_string(), "pip".to_string()];
+        full_arguments.extend(arguments.iter().map(|a| a.to_string()));
+        run_process(python_path, &full_arguments, verbose)
+    };
+    pip(&["install", "--upgrade", "pip", "setuptools"])?;
+    match prepare_constraints(idf_path) {
+        Some(constraints_path) => pip(&[
+            "install",
+            "--upgrade",
+            "-r",
+            &requirements_path,
+            "--constraint",
+            &constraints_path,
+        ]),
+        None => pip(&["install", "--upgrade", "-r", &requirements_path]),
+    }
+}
+
+fn create_python_env(interpreter: &PythonInterpreter, env_directory: &str, verbose: bool) -> Result<()> {
+    println!("Creating virtual environment: {}", env_directory);
+    let arguments = vec!["-m".to_string(), "venv".to_string(), env_directory.to_string()];
+    run_process(&interpreter.path, &arguments, verbose)
+}
+
+/// Create Python environment for version of ESP-IDF in the checkout and install its requirements.
+/// Existing environment is reused unless recreate is requested. Returns path to Python of the environment.
+pub fn prepare_python_env(idf_path: &str, python: Option<&str>, recreate: bool, verbose: bool) -> Result<String> {
+    let interpreter = select_interpreter(python)?;
+    let version = read_idf_version(idf_path)
+        .ok_or_else(|| format!("Unable to determine ESP-IDF version of {}", idf_path))?;
+    let env_directory = normalize_path(&get_python_env_path(
+        version.get_major_minor(),
+        interpreter.get_major_minor(),
+    ));
+    let python_path = get_env_python_path(&env_directory);
+
+    if recreate && Path::new(&env_directory).exists() {
+        println!("Removing virtual environment: {}", env_directory);
+        remove_directory(&env_directory)?;
+    }
+    if !Path::new(&python_path).exists() {
+        create_python_env(&interpreter, &env_directory, verbose)?;
+    }
+    println!("Installing Python requirements of ESP-IDF {}", version);
+    install_requirements(idf_path, &python_path, verbose)?;
+    Ok(python_path)
+}
+
+/// Problems of Python environment of the installation, empty when the environment is usable.
+pub fn check_python_env(idf_path: &str, python_path: &str) -> Vec<String> {
+    let mut problems: Vec<String> = Vec::new();
+    if get_python_interpreter(python_path).is_none() {
+        problems.push(format!("Python {} can't be started", python_path));
+        return problems;
+    }
+    let pip_check = vec!["-m".to_string(), "pip".to_string(), "check".to_string()];
+    if let Err(e) = run_process(python_path, &pip_check, false) {
+        problems.push(format!("Packages have broken dependencies: {}", e));
+    }
+    let checker = format!("{}/tools/check_python_dependencies.py", idf_path);
+    if let Some(requirements_path) = get_requirements_path(idf_path) {
+        if Path::new(&checker).exists() {
+            let arguments = vec![checker, "-r".to_string(), requirements_path];
+            if let Err(e) = run_process(python_path, &arguments, false) {
+                problems.push(format!("Requirements of ESP-IDF are not satisfied: {}", e));
+            }
+        }
+    }
+    problems
+}
+
+fn print_interpreters() {
+    let interpreters = find_interpreters();
+    if interpreters.is_empty() {
+        println!("No suitable Python found");
+    }
+    for interpreter in interpreters.iter() {
+        println!("{} {}", interpreter.get_major_minor(), interpreter.path);
+    }
+}
+
+fn check_installation(query: Option<&str>) -> Result<bool> {
+    let config = load_json();
+    let idf_id = resolve_installation(&config, query)?;
+    let idf = &config["idfInstalled"][idf_id.as_str()];
+    println!("Checking Python environment: {}", idf["python"]);
+    let problems = check_python_env(&idf["path"].to_string(), &idf["python"].to_string());
+    for problem in problems.iter() {
+        println!("  {}", problem);
+    }
+    Ok(problems.is_empty())
+}
+
+/// Repair keeps working environment and only reinstalls requirements, environment with
+/// broken interpreter is recreated.
+fn repair_installation(query: Option<&str>, python: Option<&str>, recreate: bool, verbose: bool) -> Result<()> {
+    let config = load_json();
+    let idf_id = resolve_installation(&config, query)?;
+    let idf = &config["idfInstalled"][idf_id.as_str()];
+    let idf_path = idf["path"].to_string();
+    let current_python = idf["python"].to_string();
+    let broken = get_python_interpreter(&current_python).is_none();
+
+    // Environment registered outside of python_env is not managed by idf-env
+    if recreate || broken {
+        if let Some(env_directory) = get_python_env_directory(&current_python) {
+            if Path::new(&env_directory).exists() {
+                println!("Removing virtual environment: {}", env_directory);
+                remove_directory(&env_directory)?;
+            }
+        }
+    }
+    let python_path = prepare_python_env(&idf_path, python, recreate || broken, verbose)?;
+    update_idf_property(&idf_id, "python", python_path.into());
+    Ok(())
+}
+
+fn get_installation_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("idf")
+        .long("idf")
+        .help("Id, version or path of installation, selected installation is used by default")
+        .takes_value(true)
+}
+
+fn get_python_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("python")
+        .short("p")
+        .long("python")
+        .help("Python interpreter used for the environment, first suitable one is used by default")
+        .takes_value(true)
+}
+
+fn get_verbose_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("verbose")
+        .short("w")
+        .long("verbose")
+        .help("Display output of pip")
+}
+
+pub fn get_list_cmd<'a>() -> Command<'a, str> {
+    Command::new("list")
+        .description("List Python interpreters suitable for ESP-IDF")
+        .runner(|_args, _matches| {
+            print_interpreters();
+            Ok(())
+        })
+}
+
+pub fn get_check_cmd<'a>() -> Command<'a, str> {
+    Command::new("check")
+        .description("Check that Python environment of installation satisfies requirements of ESP-IDF")
+        .options(|app| app.arg(get_installation_arg()))
+        .runner(|_args, matches| {
+            match check_installation(matches.value_of("idf")) {
+                Ok(true) => println!("Ok"),
+                Ok(false) => println!("Python environment is broken, run: idf-env idf python repair"),
+                Err(e) => println!("{}", e),
+            }
+            Ok(())
+        })
+}
+
+pub fn get_repair_cmd<'a>() -> Command<'a, str> {
+    Command::new("repair")
+        .description("Reinstall requirements of ESP-IDF, broken environment is recreated")
+        .options(|app| {
+            app.arg(get_installation_arg())
+                .arg(get_python_arg())
+                .arg(get_verbose_arg())
+        })
+        .runner(|_args, matches| {
+            let verbose = matches.is_present("verbose");
+            match repair_installation(matches.value_of("idf"), matches.value_of("python"), false, verbose) {
+                Ok(_) => println!("Ok"),
+                Err(e) => println!("Repair failed: {}", e),
+            }
+            Ok(())
+        })
+}
+
+pub fn get_recreate_cmd<'a>() -> Command<'a, str> {
+    Command::new("recreate")
+        .description("Remove Python environment of installation and create it again")
+        .options(|app| {
+            app.arg(get_installation_arg())
+                .arg(get_python_arg())
+                .arg(get_verbose_arg())
+        })
+        .runner(|_args, matches| {
+            let verbose = matches.is_present("verbose");
+            match repair_installation(matches.value_of("idf"), matches.value_of("python"), true, verbose) {
+                Ok(_) => println!("Ok"),
+                Err(e) => println!("Recreate failed: {}", e),
+            }
+            Ok(())
+        })
+}
+
+pub fn get_multi_cmd<'a>() -> MultiCommand<'a, str, str> {
+    Commander::new()
+        .add_cmd(get_check_cmd())
+        .add_cmd(get_list_cmd())
+        .add_cmd(get_recreate_cmd())
+        .add_cmd(get_repair_cmd())
+        .into_cmd("python")
+        .description("Maintain Python environments of ESP-IDF installations.")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_python_version() {
+        assert_eq!(parse_python_version("Python 3.11.4\n"), Some((3, 11)));
+        assert_eq!(parse_python_version("Python 3.13.0rc1"), Some((3, 13)));
+        assert_eq!(parse_python_version("Python 3.12a"), Some((3, 12)));
+        assert_eq!(parse_python_version("python: command not found"), None);
+        let interpreter = PythonInterpreter {
+            path: "python3".to_string(),
+            major: 3,
+            minor: 8,
+        };
+        assert_eq!(interpreter.get_major_minor(), "3.8");
+    }
+}
@@ -0,0 +1,247 @@
+use git2::{Direction, Remote};
+use std::cmp::Ordering;
+
+pub const IDF_REPOSITORY_URL: &str = "https://github.com/espressif/esp-idf.git";
+
+/// Number of the newest release series which are maintained. ESP-IDF releases are supported
+/// for 30 months and a new series is released about every 6 months,
+/// see https://github.com/espressif/esp-idf/blob/master/SUPPORT_POLICY.md
+const MAINTAINED_SERIES: usize = 5;
+
+/// Pre-release suffixes from the oldest, e.g. v5.2-dev precedes v5.2-beta1 and v5.2-rc1
+const PRE_RELEASE_STAGES: [&str; 3] = ["dev", "beta", "rc"];
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReleaseKind {
+    Tag,
+    Branch,
+}
+
+/// Installable release of ESP-IDF - release tag (v5.1.2) or maintained branch (release/v5.1).
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdfRelease {
+    pub name: String,
+    pub kind: ReleaseKind,
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+    /// Suffix of pre-release tags, e.g. beta1, rc2 or dev
+    pub pre_release: Option<String>,
+    /// Series is older than the maintained ones, see mark_end_of_life
+    pub end_of_life: bool,
+}
+
+impl IdfRelease {
+    pub fn is_pre_release(&self) -> bool {
+        self.pre_release.is_some()
+    }
+
+    pub fn is_end_of_life(&self) -> bool {
+        self.end_of_life
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        json::object! {
+            "name": self.name.as_str(),
+            "type": match self.kind {
+                ReleaseKind::Tag => "tag",
+                ReleaseKind::Branch => "branch",
+            },
+            "version": format!("{}.{}.{}", self.major, self.minor, self.patch),
+            "preRelease": self.is_pre_release(),
+            "endOfLife": self.is_end_of_life()
+        }
+    }
+
+    fn compare(&self, other: &IdfRelease) -> Ordering {
+        (self.major, self.minor, self.patch)
+            .cmp(&(other.major, other.minor, other.patch))
+            // Final release is newer than its pre-releases
+            .then_with(|| match (&self.pre_release, &other.pre_release) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => get_pre_release_rank(a).cmp(&get_pre_release_rank(b)),
+            })
+    }
+}
+
+/// Rank of pre-release suffix by stage and number, e.g. beta2 precedes beta10.
+/// Unknown stages are ordered after the known ones.
+fn get_pre_release_rank(suffix: &str) -> (usize, u32, &str) {
+    let stage = suffix.trim_end_matches(|c: char| c.is_ascii_digit());
+    let number = suffix[stage.len()..].parse().unwrap_or(0);
+    let stage_rank = PRE_RELEASE_STAGES
+        .iter()
+        .position(|known_stage| *known_stage == stage)
+        .unwrap_or(PRE_RELEASE_STAGES.len());
+    (stage_rank, number, stage)
+}
+
+/// Parse version in form v5.1, v5.1.2 or v5.2-beta1
+fn parse_version(name: &str, kind: ReleaseKind) -> Option<IdfRelease> {
+    let version = name.strip_prefix('v')?;
+    let (numbers, pre_release) = match version.split_once('-') {
+        Some((numbers, suffix)) => (numbers, Some(suffix.to_string())),
+        None => (version, None),
+    };
+    let parts: Vec<u32> = numbers
+        .split('.')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    if parts.len() < 2 || parts.len() > 3 {
+        return None;
+    }
+    Some(IdfRelease {
+        name: String::new(),
+        kind,
+        major: parts[0],
+        minor: parts[1],
+        patch: *parts.get(2).unwrap_or(&0),
+        pre_release,
+        end_of_life: false,
+    })
+}
+
+/// Convert reference advertised by remote, e.g. refs/tags/v5.1.2 or refs/heads/release/v5.1
+pub fn parse_release_ref(reference: &str) -> Option<IdfRelease> {
+    // Peeled tags are advertised twice, once with ^{} suffix
+    if reference.ends_with("^{}") {
+        return None;
+    }
+    if let Some(tag) = reference.strip_prefix("refs/tags/") {
+        let mut release = parse_version(tag, ReleaseKind::Tag)?;
+        release.name = tag.to_string();
+        return Some(release);
+    }
+    if let Some(branch) = reference.strip_prefix("refs/heads/") {
+        let mut release = parse_version(branch.strip_prefix("release/")?, ReleaseKind::Branch)?;
+        if release.pre_release.is_some() {
+            return None;
+        }
+        release.name = branch.to_string();
+        return Some(release);
+    }
+    None
+}
+
+/// Sort releases from the newest, branches are listed before tags of the same series.
+pub fn sort_releases(releases: &mut [IdfRelease]) {
+    releases.sort_by(|a, b| {
+        (b.major, b.minor)
+            .cmp(&(a.major, a.minor))
+            .then_with(|| match (&a.kind, &b.kind) {
+                (ReleaseKind::Branch, ReleaseKind::Tag) => Ordering::Less,
+                (ReleaseKind::Tag, ReleaseKind::Branch) => Ordering::Greater,
+                _ => b.compare(a),
+            })
+    });
+}
+
+/// Mark releases older than the newest maintained series which have a final release tag.
+pub fn mark_end_of_life(releases: &mut [IdfRelease]) {
+    let mut released_series: Vec<(u32, u32)> = releases
+        .iter()
+        .filter(|release| release.kind == ReleaseKind::Tag && !release.is_pre_release())
+        .map(|release| (release.major, release.minor))
+        .collect();
+    released_series.sort_unstable_by(|a, b| b.cmp(a));
+    released_series.dedup();
+    released_series.truncate(MAINTAINED_SERIES);
+    let oldest_maintained = match released_series.last() {
+        Some(series) => *series,
+        None => return,
+    };
+    for release in releases.iter_mut() {
+        release.end_of_life = (release.major, release.minor) < oldest_maintained;
+    }
+}
+
+/// List releases without cloning the repository, only refs are transferred.
+pub fn get_remote_releases(url: &str) -> Result<Vec<IdfRelease>, git2::Error> {
+    let mut remote = Remote::create_detached(url)?;
+    remote.connect(Direction::Fetch)?;
+    let mut releases: Vec<IdfRelease> = remote
+        .list()?
+        .iter()
+        .filter_map(|head| parse_release_ref(head.name()))
+        .collect();
+    remote.disconnect()?;
+    sort_releases(&mut releases);
+    mark_end_of_life(&mut releases);
+    Ok(releases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_release_ref() {
+        let release = parse_release_ref("refs/tags/v5.1.2").unwrap();
+        assert_eq!(release.name, "v5.1.2");
+        assert_eq!(release.kind, ReleaseKind::Tag);
+        assert_eq!((release.major, release.minor, release.patch), (5, 1, 2));
+        assert!(!release.is_pre_release());
+
+        let release = parse_release_ref("refs/tags/v5.4-beta1").unwrap();
+        assert_eq!(release.pre_release, Some("beta1".to_string()));
+
+        let release = parse_release_ref("refs/heads/release/v4.4").unwrap();
+        assert_eq!(release.name, "release/v4.4");
+        assert_eq!(release.kind, ReleaseKind::Branch);
+
+        assert_eq!(parse_release_ref("refs/tags/v5.1.2^{}"), None);
+        assert_eq!(parse_release_ref("refs/heads/master"), None);
+        assert_eq!(parse_release_ref("refs/heads/feature/v5.1"), None);
+        assert_eq!(parse_release_ref("refs/tags/qa-test-v5.1"), None);
+    }
+
+    #[test]
+    fn test_sort_releases() {
+        let mut releases: Vec<IdfRelease> = vec![
+            "refs/tags/v5.1-rc1",
+            "refs/tags/v5.1-beta2",
+            "refs/tags/v5.1-dev",
+            "refs/tags/v5.1-beta10",
+            "refs/tags/v5.1.2",
+            "refs/heads/release/v5.1",
+            "refs/tags/v5.1",
+            "refs/tags/v5.2",
+        ]
+        .into_iter()
+        .filter_map(parse_release_ref)
+        .collect();
+        sort_releases(&mut releases);
+        let names: Vec<&str> = releases.iter().map(|release| release.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["v5.2", "release/v5.1", "v5.1.2", "v5.1", "v5.1-rc1", "v5.1-beta10", "v5.1-beta2", "v5.1-dev"]
+        );
+    }
+
+    #[test]
+    fn test_mark_end_of_life() {
+        let mut releases: Vec<IdfRelease> = vec![
+            "refs/tags/v6.0-beta1",
+            "refs/tags/v5.5",
+            "refs/tags/v5.4.1",
+            "refs/tags/v5.3",
+            "refs/tags/v5.2.3",
+            "refs/tags/v5.1",
+            "refs/heads/release/v5.0",
+            "refs/tags/v5.0.4",
+            "refs/heads/release/v4.4",
+        ]
+        .into_iter()
+        .filter_map(parse_release_ref)
+        .collect();
+        mark_end_of_life(&mut releases);
+        let end_of_life: Vec<&str> = releases
+            .iter()
+            .filter(|release| release.is_end_of_life())
+            .map(|release| release.name.as_str())
+            .collect();
+        assert_eq!(end_of_life, vec!["release/v5.0", "v5.0.4", "release/v4.4"]);
+    }
+}
@@ -0,0 +1,230 @@
+use clap::Arg;
+use clap_nested::Command;
+use git2::build::CheckoutBuilder;
+use git2::{Oid, Repository, ResetType, Status, StatusOptions};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::status::{find_local_changes, get_stash_arg, protect_local_changes};
+use crate::config::get_selected_idf_path;
+use crate::plan::get_dry_run_arg;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[derive(Debug, Default)]
+pub struct ResetReport {
+    /// Tracked files with local modifications which are restored
+    pub reverted: Vec<PathBuf>,
+    /// Untracked (and optionally ignored) files and directories which are deleted
+    pub removed: Vec<PathBuf>,
+    /// Nested repositories are never deleted, same as git clean without double -f
+    pub skipped: Vec<PathBuf>,
+}
+
+impl ResetReport {
+    pub fn print(&self, dry_run: bool) {
+        let (revert_label, remove_label) = if dry_run {
+            ("Would revert", "Would remove")
+        } else {
+            ("Reverted", "Removed")
+        };
+        for path in self.reverted.iter() {
+            println!("{} {}", revert_label, path.display());
+        }
+        for path in self.removed.iter() {
+            println!("{} {}", remove_label, path.display());
+        }
+        for path in self.skipped.iter() {
+            println!("Skipping repository {}", path.display());
+        }
+    }
+}
+
+fn collect_changes(repo: &Repository, include_ignored: bool, report: &mut ResetReport) -> Result<()> {
+    let workdir = repo.workdir().ok_or("Bare repository can't be reset")?;
+    let mut options = StatusOptions::new();
+    options
+        .include_untracked(true)
+        .recurse_untracked_dirs(false)
+        .include_ignored(include_ignored)
+        .recurse_ignored_dirs(false)
+        .exclude_submodules(true);
+    for entry in repo.statuses(Some(&mut options))?.iter() {
+        let path = match entry.path() {
+            Some(path) => workdir.join(path),
+            None => continue,
+        };
+        let status = entry.status();
+        if status.intersects(Status::WT_NEW | Status::IGNORED) {
+            if path.is_dir() && path.join(".git").exists() {
+                report.skipped.push(path);
+            } else {
+                report.removed.push(path);
+            }
+        } else if !status.is_empty() {
+            report.reverted.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn reset_to(repo: &Repository, target: Option<Oid>) -> Result<()> {
+    let commit = match target.and_then(|oid| repo.find_commit(oid).ok()) {
+        Some(commit) => commit,
+        None => repo.head()?.peel_to_commit()?,
+    };
+    repo.reset(commit.as_object(), ResetType::Hard, Some(CheckoutBuilder::new().force()))?;
+    Ok(())
+}
+
+fn remove_path(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+    } else {
+        fs::remove_file(path)?;
+    }
+    Ok(())
+}
+
+/// Reset repository and its initialized submodules recursively. Submodules are reset to commit
+/// recorded by the superproject, or to their HEAD when the commit is not available locally.
+fn reset_recursive(
+    repo: &Repository,
+    target: Option<Oid>,
+    include_ignored: bool,
+    dry_run: bool,
+    report: &mut ResetReport,
+) -> Result<()> {
+    let mut changes = ResetReport::default();
+    collect_changes(repo, include_ignored, &mut changes)?;
+
+    if !dry_run {
+        reset_to(repo, target)?;
+        for path in changes.removed.iter() {
+            remove_path(path).map_err(|e| format!("Unable to remove {}: {}", path.display(), e))?;
+        }
+    }
+    report.reverted.extend(changes.reverted);
+    report.removed.extend(changes.removed);
+    report.skipped.extend(changes.skipped);
+
+    for submodule in repo.submodules()? {
+        // Uninitialized submodules have no repository
+        let submodule_repo = match submodule.open() {
+            Ok(submodule_repo) => submodule_repo,
+            Err(_e) => continue,
+        };
+        reset_recursive(&submodule_repo, submodule.head_id(), include_ignored, dry_run, report)?;
+    }
+    Ok(())
+}
+
+/// Hard reset of ESP-IDF repository with all submodules and removal of untracked files.
+/// With dry_run the repository is not modified, the report lists what would be changed.
+pub fn reset_repository(idf_path: &str, include_ignored: bool, dry_run: bool) -> Result<ResetReport> {
+    let repo = Repository::open(idf_path)?;
+    let mut report = ResetReport::default();
+    reset_recursive(&repo, None, include_ignored, dry_run, &mut report)?;
+    Ok(report)
+}
+
+fn get_reset_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    let idf_path = match matches.value_of("idf-path") {
+        Some(idf_path) => idf_path.to_string(),
+        None => get_selected_idf_path(),
+    };
+    let dry_run = matches.is_present("dry-run");
+    if !dry_run {
+        let protected = find_local_changes(&idf_path, true, false).and_then(|changes| {
+            protect_local_changes(&changes, matches.is_present("stash"), matches.is_present("force"), "reset")
+        });
+        if let Err(e) = protected {
+            println!("{}", e);
+            return Ok(());
+        }
+    }
+    println!("Resetting repository: {}", idf_path);
+    match reset_repository(&idf_path, matches.is_present("ignored"), dry_run) {
+        Ok(report) => {
+            report.print(dry_run);
+            println!("Ok");
+        }
+        Err(e) => {
+            println!("Failed: {}", e);
+        }
+    }
+    Ok(())
+}
+
+pub fn get_reset_cmd<'a>() -> Command<'a, str> {
+    Command::new("reset")
+        .description("Reset ESP-IDF git repository to initial state and wipe out modified data")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("idf-path")
+                    .short("d")
+                    .long("idf-path")
+                    .help("Path to existing ESP-IDF, selected installation is used by default")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("ignored")
+                    .short("x")
+                    .long("ignored")
+                    .help("Remove also files ignored by .gitignore, e.g. build directories"),
+            )
+            .arg(
+                Arg::with_name("force")
+                    .short("f")
+                    .long("force")
+                    .help("Discard local changes without stashing them"),
+            )
+            .arg(get_stash_arg())
+            .arg(get_dry_run_arg().help("List files which would be reverted or removed"))
+        })
+        .runner(|_args, matches| get_reset_runner(_args, matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDirectory;
+
+    #[test]
+    fn test_reset_repository() {
+        let test_directory = TestDirectory::new("reset");
+        let repo_path = test_directory.path.clone();
+        let repo = Repository::init(&repo_path).unwrap();
+        fs::write(repo_path.join("tracked.txt"), "original").unwrap();
+        fs::write(repo_path.join(".gitignore"), "build/\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("tracked.txt")).unwrap();
+        index.add_path(Path::new(".gitignore")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+
+        fs::write(repo_path.join("tracked.txt"), "modified content").unwrap();
+        fs::write(repo_path.join("untracked.txt"), "").unwrap();
+        fs::create_dir_all(repo_path.join("build")).unwrap();
+        fs::write(repo_path.join("build/app.bin"), "").unwrap();
+        let idf_path = repo_path.display().to_string();
+
+        let report = reset_repository(&idf_path, false, true).unwrap();
+        assert_eq!(report.reverted, vec![repo_path.join("tracked.txt")]);
+        assert_eq!(report.removed, vec![repo_path.join("untracked.txt")]);
+        assert!(repo_path.join("untracked.txt").exists());
+
+        reset_repository(&idf_path, false, false).unwrap();
+        assert_eq!(fs::read_to_string(repo_path.join("tracked.txt")).unwrap(), "original");
+        assert!(!repo_path.join("untracked.txt").exists());
+        assert!(repo_path.join("build").exists());
+
+        reset_repository(&idf_path, true, false).unwrap();
+        assert!(!repo_path.join("build").exists());
+    }
+}
@@ -0,0 +1,282 @@
+use clap::Arg;
+use clap_nested::Command;
+use git2::{Repository, Signature, StashFlags, Status, StatusOptions};
+use std::path::PathBuf;
+
+use super::installation::{normalize_path, resolve_installation};
+use super::version::{describe_idf_version, get_checkout_reference};
+use crate::config::load_json;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Uncommitted work in ESP-IDF repository or one of its submodules.
+#[derive(Debug, Default, PartialEq)]
+pub struct LocalChanges {
+    /// Working directory of the repository
+    pub repository: PathBuf,
+    /// Tracked files with modifications, including staged ones
+    pub modified: Vec<PathBuf>,
+    pub untracked: Vec<PathBuf>,
+}
+
+impl LocalChanges {
+    pub fn is_empty(&self) -> bool {
+        self.modified.is_empty() && self.untracked.is_empty()
+    }
+}
+
+fn collect_local_changes(
+    repo: &Repository,
+    include_untracked: bool,
+    ignore_gitmodules: bool,
+    changes: &mut Vec<LocalChanges>,
+) -> Result<()> {
+    let workdir = repo.workdir().ok_or("Bare repository has no working directory")?;
+    let mut options = StatusOptions::new();
+    options
+        .include_untracked(include_untracked)
+        .recurse_untracked_dirs(false)
+        .include_ignored(false)
+        .exclude_submodules(true);
+    let mut repository_changes = LocalChanges {
+        // Working directory of git2 ends with separator
+        repository: workdir.components().collect(),
+        ..LocalChanges::default()
+    };
+    for entry in repo.statuses(Some(&mut options))?.iter() {
+        let path = match entry.path() {
+            Some(path) => path,
+            None => continue,
+        };
+        if ignore_gitmodules && path == ".gitmodules" {
+            continue;
+        }
+        let status = entry.status();
+        if status.contains(Status::WT_NEW) {
+            repository_changes.untracked.push(workdir.join(path));
+        } else if !status.is_empty() {
+            repository_changes.modified.push(workdir.join(path));
+        }
+    }
+    if !repository_changes.is_empty() {
+        changes.push(repository_changes);
+    }
+
+    for submodule in repo.submodules()? {
+        // Uninitialized submodules have no repository
+        if let Ok(submodule_repo) = submodule.open() {
+            collect_local_changes(&submodule_repo, include_untracked, ignore_gitmodules, changes)?;
+        }
+    }
+    Ok(())
+}
+
+/// Repositories of the checkout with uncommitted work. .gitmodules is rewritten by idf mirror,
+/// so it can be ignored when the mirror is configured.
+pub fn find_local_changes(idf_path: &str, include_untracked: bool, ignore_gitmodules: bool) -> Result<Vec<LocalChanges>> {
+    let repo = Repository::open(idf_path)?;
+    let mut changes: Vec<LocalChanges> = Vec::new();
+    collect_local_changes(&repo, include_untracked, ignore_gitmodules, &mut changes)?;
+    Ok(changes)
+}
+
+pub fn print_local_changes(changes: &[LocalChanges]) {
+    for repository_changes in changes.iter() {
+        println!("Repository {}:", repository_changes.repository.display());
+        for path in repository_changes.modified.iter() {
+            println!("  modified: {}", path.display());
+        }
+        for path in repository_changes.untracked.iter() {
+            println!("  untracked: {}", path.display());
+        }
+    }
+}
+
+/// Save changes to git stash of each repository, they can be restored by git stash pop.
+pub fn stash_local_changes(changes: &[LocalChanges], message: &str) -> Result<()> {
+    for repository_changes in changes.iter() {
+        let mut repo = Repository::open(&repository_changes.repository)?;
+        // Stash requires identity, which is not configured on fresh CI machines
+        let signature = repo
+            .signature()
+            .or_else(|_e| Signature::now("idf-env", "idf-env@localhost"))?;
+        let flags = if repository_changes.untracked.is_empty() {
+            StashFlags::DEFAULT
+        } else {
+            StashFlags::INCLUDE_UNTRACKED
+        };
+        repo.stash_save(&signature, message, Some(flags))
+            .map_err(|e| format!("Unable to stash changes in {}: {}", repository_changes.repository.display(), e))?;
+        println!("Stashed changes in {}", repository_changes.repository.display());
+    }
+    Ok(())
+}
+
+/// Stop operation which would discard local changes unless they are stashed or discarding is forced.
+pub fn protect_local_changes(changes: &[LocalChanges], stash: bool, force: bool, operation: &str) -> Result<()> {
+    if changes.is_empty() {
+        return Ok(());
+    }
+    print_local_changes(changes);
+    if stash {
+        stash_local_changes(changes, &format!("idf-env {}", operation))
+    } else if force {
+        Ok(())
+    } else {
+        Err("ESP-IDF has local changes, use --stash to keep them or --force to discard them".into())
+    }
+}
+
+/// Submodules which are checked out at other commit than recorded by the parent repository.
+fn find_moved_submodules(repo: &Repository, moved: &mut Vec<PathBuf>) -> Result<()> {
+    let workdir = repo.workdir().ok_or("Bare repository has no working directory")?;
+    for submodule in repo.submodules()? {
+        let submodule_repo = match submodule.open() {
+            Ok(submodule_repo) => submodule_repo,
+            Err(_e) => continue,
+        };
+        if submodule.head_id() != submodule.workdir_id() {
+            moved.push(workdir.join(submodule.path()));
+        }
+        find_moved_submodules(&submodule_repo, moved)?;
+    }
+    Ok(())
+}
+
+/// Number of commits of HEAD which are not in the version and commits of the version
+/// missing in HEAD. Branches are looked up also in origin.
+fn get_divergence(repo: &Repository, version: &str) -> Option<(usize, usize)> {
+    let target = repo
+        .revparse_single(version)
+        .or_else(|_e| repo.revparse_single(&format!("origin/{}", version)))
+        .ok()?
+        .peel_to_commit()
+        .ok()?;
+    let head = repo.head().ok()?.peel_to_commit().ok()?;
+    repo.graph_ahead_behind(head.id(), target.id()).ok()
+}
+
+fn format_commits(count: usize) -> String {
+    format!("{} commit{}", count, if count == 1 { "" } else { "s" })
+}
+
+pub fn format_divergence(divergence: Option<(usize, usize)>, version: &str) -> String {
+    match divergence {
+        None => format!("{} not found in the repository", version),
+        Some((0, 0)) => format!("up to date with {}", version),
+        Some((ahead, 0)) => format!("{} ahead of {}", format_commits(ahead), version),
+        Some((0, behind)) => format!("{} behind {}", format_commits(behind), version),
+        Some((ahead, behind)) => format!("{} ahead and {} behind {}", format_commits(ahead), behind, version),
+    }
+}
+
+pub fn print_status(query: Option<&str>) -> Result<()> {
+    let config = load_json();
+    let idf_id = resolve_installation(&config, query)?;
+    let idf = &config["idfInstalled"][idf_id.as_str()];
+    let idf_path = normalize_path(&idf["path"].to_string());
+    let version = idf["version"].to_string();
+    let repo = Repository::open(&idf_path)?;
+
+    println!("ESP-IDF: {} ({})", idf_path, idf_id);
+    println!(
+        "Checkout: {} ({})",
+        get_checkout_reference(&idf_path).unwrap_or_else(|| "detached HEAD".to_string()),
+        describe_idf_version(&idf_path).unwrap_or_else(|| "unknown".to_string())
+    );
+    println!("Recorded version: {}, {}", version, format_divergence(get_divergence(&repo, &version), &version));
+
+    let mut moved: Vec<PathBuf> = Vec::new();
+    find_moved_submodules(&repo, &mut moved)?;
+    for path in moved.iter() {
+        println!("Submodule not at recorded commit: {}", path.display());
+    }
+
+    let changes = find_local_changes(&idf_path, true, !idf["mirrorProfile"].is_null())?;
+    if changes.is_empty() {
+        println!("No local changes");
+    } else {
+        print_local_changes(&changes);
+    }
+    Ok(())
+}
+
+fn get_status_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    if let Err(e) = print_status(matches.value_of("idf")) {
+        println!("Unable to get status: {}", e);
+    }
+    Ok(())
+}
+
+/// Option shared by commands which would discard local changes of ESP-IDF.
+pub fn get_stash_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("stash")
+        .long("stash")
+        .help("Save local changes of ESP-IDF and its submodules to git stash before proceeding")
+        .conflicts_with("force")
+}
+
+pub fn get_status_cmd<'a>() -> Command<'a, str> {
+    Command::new("status")
+        .description("Show current version, divergence from the recorded version and local changes of ESP-IDF")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("idf")
+                    .long("idf")
+                    .help("Id, version or path of installation, selected installation is used by default")
+                    .takes_value(true),
+            )
+        })
+        .runner(|_args, matches| get_status_runner(_args, matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDirectory;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn test_find_local_changes() {
+        let test_directory = TestDirectory::new("status");
+        let repo_path = test_directory.path.clone();
+        let repo = Repository::init(&repo_path).unwrap();
+        fs::write(repo_path.join("tracked.txt"), "original").unwrap();
+        fs::write(repo_path.join(".gitmodules"), "").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("tracked.txt")).unwrap();
+        index.add_path(Path::new(".gitmodules")).unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("test", "test@example.com").unwrap();
+        repo.commit(Some("HEAD"), &signature, &signature, "initial", &tree, &[]).unwrap();
+        let idf_path = repo_path.display().to_string();
+
+        fs::write(repo_path.join("untracked.txt"), "").unwrap();
+        assert!(find_local_changes(&idf_path, false, false).unwrap().is_empty());
+        let changes = find_local_changes(&idf_path, true, false).unwrap();
+        assert_eq!(changes[0].untracked, vec![repo_path.join("untracked.txt")]);
+
+        fs::write(repo_path.join(".gitmodules"), "[submodule \"mirror\"]").unwrap();
+        assert_eq!(
+            find_local_changes(&idf_path, false, false).unwrap()[0].modified,
+            vec![repo_path.join(".gitmodules")]
+        );
+        assert!(find_local_changes(&idf_path, false, true).unwrap().is_empty());
+
+        fs::write(repo_path.join("tracked.txt"), "modified").unwrap();
+        let changes = find_local_changes(&idf_path, true, true).unwrap();
+        assert_eq!(changes[0].modified, vec![repo_path.join("tracked.txt")]);
+        stash_local_changes(&changes, "test").unwrap();
+        assert_eq!(fs::read_to_string(repo_path.join("tracked.txt")).unwrap(), "original");
+        assert!(!repo_path.join("untracked.txt").exists());
+
+        assert_eq!(format_divergence(Some((0, 0)), "v5.1"), "up to date with v5.1");
+        assert_eq!(format_divergence(Some((1, 0)), "v5.1"), "1 commit ahead of v5.1");
+        assert_eq!(format_divergence(Some((2, 3)), "v5.1"), "2 commits ahead and 3 behind v5.1");
+    }
+}
@@ -0,0 +1,454 @@
+use git2::build::CheckoutBuilder;
+use git2::{ErrorClass, FetchOptions, RemoteCallbacks, Repository, SubmoduleUpdateOptions};
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use super::run_git;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Upper bound of delay between retries in seconds
+const MAX_RETRY_DELAY: u64 = 60;
+
+/// Hook applied to repository of updated submodule before its nested submodules are initialized,
+/// e.g. switch of nested submodules to a mirror.
+pub type SubmoduleHook<'a> = &'a (dyn Fn(&mut Repository) + Sync);
+
+pub struct UpdateOptions {
+    /// Git binary used for shallow updates, git2 can't fetch single commit with depth
+    pub git_path: String,
+    pub jobs: usize,
+    pub depth: Option<u32>,
+    /// Number of retries after transient network error
+    pub retries: u32,
+    /// Discard local modifications of submodules
+    pub force: bool,
+    /// Display transfer progress of each submodule
+    pub progress: bool,
+}
+
+impl Default for UpdateOptions {
+    fn default() -> UpdateOptions {
+        UpdateOptions {
+            git_path: "git".to_string(),
+            jobs: num_cpus::get(),
+            depth: None,
+            retries: 2,
+            force: false,
+            progress: false,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SubmoduleStatus {
+    /// Path relative to the root repository, e.g. components/esptool_py/esptool
+    pub path: String,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Default)]
+pub struct UpdateReport {
+    pub submodules: Vec<SubmoduleStatus>,
+}
+
+impl UpdateReport {
+    pub fn get_failed(&self) -> Vec<&SubmoduleStatus> {
+        self.submodules.iter().filter(|status| status.error.is_some()).collect()
+    }
+
+    pub fn print_summary(&self) {
+        let failed = self.get_failed();
+        println!(
+            "Updated {} submodules, {} failed",
+            self.submodules.len() - failed.len(),
+            failed.len()
+        );
+        for status in failed.iter() {
+            println!("  {}: {}", status.path, status.error.as_deref().unwrap_or(""));
+        }
+    }
+}
+
+struct Task {
+    /// Working directory of repository which contains the submodule
+    parent: PathBuf,
+    name: String,
+    path: String,
+}
+
+#[derive(Default)]
+struct Queue {
+    tasks: VecDeque<Task>,
+    running: usize,
+    /// Repositories whose submodule is being updated. Git locks index and config of the parent,
+    /// so only submodules of different repositories are updated at the same time.
+    busy_parents: Vec<PathBuf>,
+    report: UpdateReport,
+}
+
+impl Queue {
+    /// First task whose parent repository is not used by another worker.
+    fn take_task(&mut self) -> Option<Task> {
+        let position = self.tasks.iter().position(|task| !self.busy_parents.contains(&task.parent))?;
+        let task = self.tasks.remove(position)?;
+        self.busy_parents.push(task.parent.clone());
+        self.running += 1;
+        Some(task)
+    }
+}
+
+/// Network failures which might succeed when repeated, e.g. timeout or reset connection.
+pub fn is_transient_message(message: &str) -> bool {
+    let message = message.to_lowercase();
+    [
+        "timed out",
+        "timeout",
+        "connection reset",
+        "connection refused",
+        "could not resolve host",
+        "early eof",
+        "rpc failed",
+        "unexpected disconnect",
+        "temporary failure",
+    ]
+    .iter()
+    .any(|pattern| message.contains(pattern))
+}
+
+fn is_transient_error(error: &git2::Error) -> bool {
+    matches!(error.class(), ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssl)
+        || is_transient_message(error.message())
+}
+
+/// Initialize submodules of the repository and sync their URLs from .gitmodules.
+fn init_submodules(repo: &Repository, parent_path: &str) -> Result<Vec<Task>> {
+    let workdir = repo.workdir().ok_or("Bare repository has no submodules")?;
+    let mut tasks: Vec<Task> = Vec::new();
+    for mut submodule in repo.submodules()? {
+        let name = submodule.name().ok_or("Submodule name is not valid UTF-8")?.to_string();
+        submodule.init(false)?;
+        submodule.sync()?;
+        let path = submodule.path().display().to_string();
+        let path = if parent_path.is_empty() {
+            path
+        } else {
+            format!("{}/{}", parent_path, path)
+        };
+        tasks.push(Task {
+            parent: workdir.to_path_buf(),
+            name,
+            path,
+        });
+    }
+    Ok(tasks)
+}
+
+fn update_with_git2(task: &Task, options: &UpdateOptions) -> std::result::Result<(), git2::Error> {
+    let repo = Repository::open(&task.parent)?;
+    let mut submodule = repo.find_submodule(&task.name)?;
+    // libgit2 can't clone submodule when its directory is missing
+    if let Err(e) = std::fs::create_dir_all(task.parent.join(submodule.path())) {
+        return Err(git2::Error::from_str(&e.to_string()));
+    }
+    let last_percent = Cell::new(0);
+    let mut callbacks = RemoteCallbacks::new();
+    if options.progress {
+        callbacks.transfer_progress(|stats| {
+            if stats.total_objects() > 0 {
+                let percent = stats.received_objects() * 100 / stats.total_objects();
+                if percent / 10 > last_percent.get() / 10 {
+                    println!("{}: {}% ({}/{} objects)", task.path, percent, stats.received_objects(), stats.total_objects());
+                    last_percent.set(percent);
+                }
+            }
+            true
+        });
+    }
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(callbacks);
+    let mut checkout = CheckoutBuilder::new();
+    if options.force {
+        checkout.force();
+    }
+    let mut update_options = SubmoduleUpdateOptions::new();
+    update_options.fetch(fetch_options).checkout(checkout);
+    submodule.update(true, Some(&mut update_options))
+}
+
+/// Shallow update by git. URL synced by init_submodules after the hook is passed explicitly and git
+/// doesn't recurse, nested submodules are queued so that the hook is applied before they are fetched.
+fn update_with_git(task: &Task, options: &UpdateOptions, depth: u32) -> Result<()> {
+    let parent = task.parent.display().to_string();
+    let parent_repo = Repository::open(&task.parent)?;
+    let submodule = parent_repo.find_submodule(&task.name)?;
+    let url = submodule.url().ok_or("Submodule URL is not valid UTF-8")?;
+    let url_config = format!("submodule.{}.url={}", task.name, url);
+    let depth = depth.to_string();
+    let mut arguments = vec![
+        "-c",
+        &url_config,
+        "-c",
+        "submodule.recurse=false",
+        "submodule",
+        "update",
+        "--init",
+        "--depth",
+        &depth,
+        "--recommend-shallow",
+    ];
+    if options.force {
+        arguments.push("--force");
+    }
+    let path = submodule.path().display().to_string();
+    arguments.push("--");
+    arguments.push(&path);
+    // Git is not configured when ESP-IDF was not installed by idf-env
+    let git_path = if options.git_path.is_empty() { "git" } else { options.git_path.as_str() };
+    run_git(git_path, &parent, &arguments, false)
+}
+
+/// Update single submodule, transient failures are retried with increasing delay.
+fn update_task(task: &Task, options: &UpdateOptions) -> SubmoduleStatus {
+    let mut attempts = 0;
+    loop {
+        attempts += 1;
+        let (error, transient) = match options.depth {
+            Some(depth) => match update_with_git(task, options, depth) {
+                Ok(_) => (None, false),
+                Err(e) => (Some(e.to_string()), is_transient_message(&e.to_string())),
+            },
+            None => match update_with_git2(task, options) {
+                Ok(_) => (None, false),
+                Err(e) => (Some(e.message().to_string()), is_transient_error(&e)),
+            },
+        };
+        match error {
+            Some(error) if transient && attempts <= options.retries => {
+                let delay = 2u64.saturating_pow(attempts).min(MAX_RETRY_DELAY);
+                println!("{}: {}, retrying in {}s", task.path, error, delay);
+                std::thread::sleep(Duration::from_secs(delay));
+            }
+            error => {
+                return SubmoduleStatus {
+                    path: task.path.clone(),
+                    attempts,
+                    error,
+                };
+            }
+        }
+    }
+}
+
+/// Nested submodules are queued when their parent is updated.
+fn get_nested_tasks(task: &Task, hook: Option<SubmoduleHook>) -> Result<Vec<Task>> {
+    let parent = Repository::open(&task.parent)?;
+    let mut repo = parent.find_submodule(&task.name)?.open()?;
+    if let Some(hook) = hook {
+        hook(&mut repo);
+    }
+    init_submodules(&repo, &task.path)
+}
+
+fn run_worker(queue: &Mutex<Queue>, condvar: &Condvar, options: &UpdateOptions, hook: Option<SubmoduleHook>) {
+    loop {
+        let task = {
+            let mut state = queue.lock().unwrap();
+            loop {
+                if let Some(task) = state.take_task() {
+                    break task;
+                }
+                if state.running == 0 {
+                    return;
+                }
+                state = condvar.wait(state).unwrap();
+            }
+        };
+
+        let mut status = update_task(&task, options);
+        let mut nested_tasks: Vec<Task> = Vec::new();
+        if status.error.is_none() {
+            match get_nested_tasks(&task, hook) {
+                Ok(tasks) => nested_tasks = tasks,
+                Err(e) => status.error = Some(format!("Unable to initialize nested submodules: {}", e)),
+            }
+        }
+        match &status.error {
+            None if status.attempts > 1 => println!("{}: ok (attempt {})", status.path, status.attempts),
+            None => println!("{}: ok", status.path),
+            Some(error) => println!("{}: failed: {}", status.path, error),
+        }
+
+        let mut state = queue.lock().unwrap();
+        state.tasks.extend(nested_tasks);
+        state.busy_parents.retain(|parent| *parent != task.parent);
+        state.running -= 1;
+        state.report.submodules.push(status);
+        condvar.notify_all();
+    }
+}
+
+/// Initialize and update submodules of the repository recursively. Submodules of different
+/// repositories are updated concurrently by options.jobs workers. Failure of a submodule doesn't stop the update,
+/// it's recorded in the report.
+pub fn update_submodules_parallel(
+    idf_path: &str,
+    options: &UpdateOptions,
+    hook: Option<SubmoduleHook>,
+) -> Result<UpdateReport> {
+    let repo = Repository::open(idf_path)?;
+    let queue = Mutex::new(Queue {
+        tasks: init_submodules(&repo, "")?.into_iter().collect(),
+        ..Queue::default()
+    });
+    let condvar = Condvar::new();
+    std::thread::scope(|scope| {
+        for _ in 0..options.jobs.max(1) {
+            scope.spawn(|| run_worker(&queue, &condvar, options, hook));
+        }
+    });
+    let mut report = queue.into_inner().unwrap().report;
+    report.submodules.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDirectory;
+
+    #[test]
+    fn test_is_transient_error() {
+        assert!(is_transient_message("fatal: unable to access 'https://github.com/': Could not resolve host: github.com"));
+        assert!(is_transient_message("error: RPC failed; curl 56 GnuTLS recv error"));
+        assert!(!is_transient_message("fatal: reference is not a tree: 1234abcd"));
+        let network_error = git2::Error::new(git2::ErrorCode::GenericError, ErrorClass::Net, "failed to send request");
+        assert!(is_transient_error(&network_error));
+        let checkout_error = git2::Error::new(git2::ErrorCode::Conflict, ErrorClass::Checkout, "1 conflict prevents checkout");
+        assert!(!is_transient_error(&checkout_error));
+    }
+
+    fn commit_all(repo: &Repository, paths: &[&str]) {
+        let mut index = repo.index().unwrap();
+        for path in paths.iter() {
+            index.add_path(std::path::Path::new(path)).unwrap();
+        }
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("test", "test@example.com").unwrap();
+        let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let parents: Vec<&git2::Commit> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &signature, &signature, "commit", &tree, &parents).unwrap();
+    }
+
+    fn create_library(path: &std::path::Path) -> Repository {
+        let library = Repository::init(path).unwrap();
+        std::fs::write(path.join("library.c"), "").unwrap();
+        commit_all(&library, &["library.c"]);
+        library
+    }
+
+    fn add_submodule(repo: &Repository, url: &std::path::Path, path: &str) {
+        let mut submodule = repo
+            .submodule(&url.display().to_string(), std::path::Path::new(path), true)
+            .unwrap();
+        submodule.clone(None).unwrap();
+        submodule.add_finalize().unwrap();
+    }
+
+    /// Repository main with submodules components/{mbedtls,bootloader,esptool} which are not
+    /// checked out yet, mbedtls contains nested submodule framework.
+    fn create_main_repository(test_path: &std::path::Path) -> std::path::PathBuf {
+        let library_names = ["mbedtls", "bootloader", "esptool", "unity"];
+        for name in library_names.iter() {
+            create_library(&test_path.join(name));
+        }
+        let nested = Repository::open(test_path.join("mbedtls")).unwrap();
+        add_submodule(&nested, &test_path.join("unity"), "framework");
+        commit_all(&nested, &[]);
+
+        let main_path = test_path.join("main");
+        let main = Repository::init(&main_path).unwrap();
+        for name in library_names.iter().take(3) {
+            add_submodule(&main, &test_path.join(name), &format!("components/{}", name));
+        }
+        commit_all(&main, &[]);
+        for name in library_names.iter().take(3) {
+            std::fs::remove_dir_all(main_path.join("components").join(name)).unwrap();
+            // Fresh checkout contains empty directory of the submodule
+            std::fs::create_dir(main_path.join("components").join(name)).unwrap();
+        }
+        std::fs::remove_dir_all(main_path.join(".git/modules")).unwrap();
+        main_path
+    }
+
+    fn assert_updated(report: &UpdateReport, main_path: &std::path::Path) {
+        let paths: Vec<&str> = report.submodules.iter().map(|status| status.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "components/bootloader",
+                "components/esptool",
+                "components/mbedtls",
+                "components/mbedtls/framework",
+            ]
+        );
+        assert!(report.get_failed().is_empty());
+        for name in ["mbedtls", "bootloader", "esptool"].iter() {
+            assert!(main_path.join("components").join(name).join("library.c").exists());
+        }
+        assert!(main_path.join("components/mbedtls/framework/library.c").exists());
+    }
+
+    #[test]
+    fn test_update_submodules_parallel() {
+        let test_directory = TestDirectory::new("submodule");
+        // Nested submodule is queued after its parent and updated together with submodules of main
+        let main_path = create_main_repository(&test_directory.path);
+
+        let options = UpdateOptions {
+            jobs: 4,
+            ..UpdateOptions::default()
+        };
+        let report = update_submodules_parallel(&main_path.display().to_string(), &options, None).unwrap();
+        assert_updated(&report, &main_path);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_update_submodules_shallow() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let test_directory = TestDirectory::new("submodule-shallow");
+        let test_path = &test_directory.path;
+        let main_path = create_main_repository(test_path);
+        let mirror_path = test_path.join("unity-mirror");
+        Repository::clone(&test_path.join("unity").display().to_string(), &mirror_path).unwrap();
+        // Git refuses local submodules by default since 2.38.1
+        let git_path = test_path.join("git");
+        std::fs::write(&git_path, "#!/bin/sh\nexec git -c protocol.file.allow=always \"$@\"\n").unwrap();
+        std::fs::set_permissions(&git_path, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mirror_url = mirror_path.display().to_string();
+        let hook = |repo: &mut Repository| {
+            if repo.find_submodule("framework").is_ok() {
+                repo.submodule_set_url("framework", &mirror_url).unwrap();
+            }
+        };
+        let options = UpdateOptions {
+            git_path: git_path.display().to_string(),
+            jobs: 4,
+            depth: Some(1),
+            ..UpdateOptions::default()
+        };
+        let report = update_submodules_parallel(&main_path.display().to_string(), &options, Some(&hook)).unwrap();
+        assert_updated(&report, &main_path);
+        // Nested submodule is fetched from URL changed by the hook
+        let framework = Repository::open(main_path.join("components/mbedtls/framework")).unwrap();
+        assert_eq!(framework.find_remote("origin").unwrap().url(), Some(mirror_url.as_str()));
+    }
+}
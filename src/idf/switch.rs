@@ -0,0 +1,110 @@
+use clap::Arg;
+use clap_nested::Command;
+use std::path::Path;
+
+use super::installation::resolve_installation;
+use super::mirror::get_mirror_profile;
+use super::status::{find_local_changes, get_stash_arg, protect_local_changes};
+use super::tools::read_idf_tools;
+use super::{
+    checkout_version, get_version_name, prepare_python_env, run_git, run_idf_tools, update_submodules,
+};
+use crate::config::{get_git_path, load_json, update_idf_property};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Switch checkout of the installation to another tag or branch and install tools and Python
+/// environment required by the new version. Local modifications are saved to git stash
+/// with stash, or discarded with force.
+pub fn switch_idf(query: Option<&str>, idf_version: &str, stash: bool, force: bool, verbose: bool) -> Result<()> {
+    let config = load_json();
+    let idf_id = resolve_installation(&config, query)?;
+    let idf = &config["idfInstalled"][idf_id.as_str()];
+    let idf_path = idf["path"].to_string();
+    let mirror = idf["mirrorProfile"]
+        .as_str()
+        .map(|name| get_mirror_profile(&config, name).ok_or(format!("Unknown mirror profile {}", name)))
+        .transpose()?;
+    let git_path = match get_git_path() {
+        git_path if git_path.is_empty() => "git".to_string(),
+        git_path => git_path,
+    };
+
+    let changes = find_local_changes(&idf_path, false, mirror.is_some())?;
+    protect_local_changes(&changes, stash, force, &format!("switch to {}", idf_version))?;
+
+    println!("Switching ESP-IDF in {} from {} to {}", idf_path, idf["version"], idf_version);
+    if mirror.is_some() && !force {
+        // Restore .gitmodules of the current version rewritten by idf mirror
+        run_git(&git_path, &idf_path, &["checkout", "--", ".gitmodules"], verbose)?;
+    }
+    checkout_version(&git_path, &idf_path, idf_version, force, verbose)?;
+    update_submodules(&git_path, &idf_path, mirror.as_ref(), force)?;
+
+    let missing_tools = read_idf_tools(&idf_path)?
+        .iter()
+        .any(|tool| !Path::new(&tool.get_install_path()).exists());
+    let python_path = prepare_python_env(&idf_path, None, false, verbose)?;
+    if missing_tools {
+        println!("Installing tools");
+        run_idf_tools(&python_path, &idf_path, &["install"], verbose)?;
+    }
+
+    let version_name = get_version_name(&idf_path)?;
+    println!("Registering ESP-IDF {} in {}", version_name, idf_path);
+    update_idf_property(&idf_id, "version", version_name.into());
+    update_idf_property(&idf_id, "python", python_path.into());
+    Ok(())
+}
+
+fn get_switch_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    let idf_version = matches.value_of("to").unwrap();
+    if let Err(e) = switch_idf(
+        matches.value_of("idf"),
+        idf_version,
+        matches.is_present("stash"),
+        matches.is_present("force"),
+        matches.is_present("verbose"),
+    ) {
+        println!("Switch failed: {}", e);
+    }
+    Ok(())
+}
+
+pub fn get_switch_cmd<'a>() -> Command<'a, str> {
+    Command::new("switch")
+        .description("Switch existing ESP-IDF checkout to another version in place")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("to")
+                    .short("t")
+                    .long("to")
+                    .help("ESP-IDF version - tag or branch, e.g. v5.2 or release/v5.2")
+                    .required(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("idf")
+                    .long("idf")
+                    .help("Id, version or path of installation, selected installation is used by default")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("force")
+                    .short("f")
+                    .long("force")
+                    .help("Discard local modifications of ESP-IDF and its submodules"),
+            )
+            .arg(get_stash_arg())
+            .arg(
+                Arg::with_name("verbose")
+                    .short("w")
+                    .long("verbose")
+                    .help("Display output of git commands"),
+            )
+        })
+        .runner(|_args, matches| get_switch_runner(_args, matches))
+}
@@ -0,0 +1,252 @@
+use std::fs;
+
+use crate::config::get_tools_path;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Tool declared in tools/tools.json of ESP-IDF with its recommended version.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdfTool {
+    pub name: String,
+    pub version: String,
+    /// Directories relative to the tool installation which should be added to PATH
+    pub export_paths: Vec<String>,
+    pub export_vars: Vec<(String, String)>,
+}
+
+impl IdfTool {
+    /// Installation directory used by idf_tools.py, e.g. ~/.espressif/tools/cmake/3.24.0
+    pub fn get_install_path(&self) -> String {
+        self.get_install_path_in(&get_tools_path())
+    }
+
+    /// Installation directory in the given tools directory instead of IDF_TOOLS_PATH
+    pub fn get_install_path_in(&self, tools_path: &str) -> String {
+        format!("{}/tools/{}/{}", tools_path, self.name, self.version)
+    }
+}
+
+pub fn parse_tools_json(content: &str) -> Result<Vec<IdfTool>> {
+    let parsed = json::parse(content)?;
+    let mut tools: Vec<IdfTool> = Vec::new();
+    for tool in parsed["tools"].members() {
+        let version = tool["versions"]
+            .members()
+            .find(|version| version["status"] == "recommended");
+        let version = match version {
+            Some(version) => version["name"].to_string(),
+            None => continue,
+        };
+        let export_paths = tool["export_paths"]
+            .members()
+            .map(|path| {
+                path.members()
+                    .map(|component| component.to_string())
+                    .collect::<Vec<String>>()
+                    .join("/")
+            })
+            .collect();
+        let export_vars = tool["export_vars"]
+            .entries()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        tools.push(IdfTool {
+            name: tool["name"].to_string(),
+            version,
+            export_paths,
+            export_vars,
+        });
+    }
+    Ok(tools)
+}
+
+pub fn read_idf_tools(idf_path: &str) -> Result<Vec<IdfTool>> {
+    let content = fs::read_to_string(format!("{}/tools/tools.json", idf_path))?;
+    parse_tools_json(&content)
+}
+
+/// Download of the recommended tool version for the host, tools.json declares its sha256.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToolArchive {
+    pub name: String,
+    pub version: String,
+    pub url: String,
+    pub sha256: String,
+}
+
+/// Platform key of downloads in tools.json
+pub fn get_tools_platform() -> &'static str {
+    match (std::env::consts::OS, std::env::consts::ARCH) {
+        ("windows", "x86") => "win32",
+        ("windows", _) => "win64",
+        ("macos", "aarch64") => "macos-arm64",
+        ("macos", _) => "macos",
+        ("linux", "aarch64") => "linux-arm64",
+        ("linux", "arm") => "linux-armhf",
+        ("linux", "x86") => "linux-i686",
+        _ => "linux-amd64",
+    }
+}
+
+/// Tools without download for the platform are skipped, platform independent tools use key "any".
+pub fn parse_tool_archives(content: &str, platform: &str) -> Result<Vec<ToolArchive>> {
+    let parsed = json::parse(content)?;
+    let mut archives: Vec<ToolArchive> = Vec::new();
+    for tool in parsed["tools"].members() {
+        let version = match tool["versions"]
+            .members()
+            .find(|version| version["status"] == "recommended")
+        {
+            Some(version) => version,
+            None => continue,
+        };
+        let download = if version[platform].is_object() {
+            &version[platform]
+        } else {
+            &version["any"]
+        };
+        if !download.is_object() {
+            continue;
+        }
+        archives.push(ToolArchive {
+            name: tool["name"].to_string(),
+            version: version["name"].to_string(),
+            url: download["url"].to_string(),
+            sha256: download["sha256"].to_string(),
+        });
+    }
+    Ok(archives)
+}
+
+/// Names of tools which idf_tools.py installs without request on the platform, install mode
+/// of the tool is changed by platform_overrides.
+pub fn parse_always_installed(content: &str, platform: &str) -> Result<Vec<String>> {
+    let parsed = json::parse(content)?;
+    let mut names: Vec<String> = Vec::new();
+    for tool in parsed["tools"].members() {
+        let mut install = tool["install"].as_str().unwrap_or("");
+        for platform_override in tool["platform_overrides"].members() {
+            let matches_platform = platform_override["platforms"].members().any(|name| name == platform);
+            if matches_platform && platform_override["install"].is_string() {
+                install = platform_override["install"].as_str().unwrap_or("");
+            }
+        }
+        if install == "always" {
+            names.push(tool["name"].to_string());
+        }
+    }
+    Ok(names)
+}
+
+pub fn read_tool_archives(idf_path: &str) -> Result<Vec<ToolArchive>> {
+    let content = fs::read_to_string(format!("{}/tools/tools.json", idf_path))?;
+    parse_tool_archives(&content, get_tools_platform())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_tools_json() {
+        let content = r#"{
+            "tools": [
+                {
+                    "name": "cmake",
+                    "export_paths": [["bin"]],
+                    "export_vars": {},
+                    "versions": [
+                        { "name": "3.16.3", "status": "supported" },
+                        { "name": "3.24.0", "status": "recommended" }
+                    ]
+                },
+                {
+                    "name": "openocd-esp32",
+                    "export_paths": [["openocd-esp32", "bin"]],
+                    "export_vars": { "OPENOCD_SCRIPTS": "${TOOL_PATH}/openocd-esp32/share/openocd/scripts" },
+                    "versions": [
+                        { "name": "v0.12.0-esp32-20230921", "status": "recommended" }
+                    ]
+                },
+                {
+                    "name": "deprecated",
+                    "versions": [ { "name": "1.0", "status": "deprecated" } ]
+                }
+            ]
+        }"#;
+        let tools = parse_tools_json(content).unwrap();
+        assert_eq!(tools.len(), 2);
+        assert_eq!(tools[0].name, "cmake");
+        assert_eq!(tools[0].version, "3.24.0");
+        assert_eq!(tools[0].export_paths, vec!["bin".to_string()]);
+        assert_eq!(tools[1].export_paths, vec!["openocd-esp32/bin".to_string()]);
+        assert_eq!(
+            tools[1].export_vars,
+            vec![(
+                "OPENOCD_SCRIPTS".to_string(),
+                "${TOOL_PATH}/openocd-esp32/share/openocd/scripts".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_parse_tool_archives() {
+        let content = r#"{
+            "tools": [
+                {
+                    "name": "xtensa-esp-elf",
+                    "versions": [
+                        {
+                            "name": "esp-13.2.0_20230928",
+                            "status": "recommended",
+                            "linux-amd64": { "url": "https://example.com/xtensa-linux.tar.xz", "sha256": "aa", "size": 1 },
+                            "win64": { "url": "https://example.com/xtensa-win64.zip", "sha256": "bb", "size": 1 }
+                        }
+                    ]
+                },
+                {
+                    "name": "esp-rom-elfs",
+                    "versions": [
+                        {
+                            "name": "20230320",
+                            "status": "recommended",
+                            "any": { "url": "https://example.com/esp-rom-elfs.tar.gz", "sha256": "cc", "size": 1 }
+                        }
+                    ]
+                },
+                {
+                    "name": "dfu-util",
+                    "versions": [
+                        { "name": "0.11", "status": "recommended", "win64": { "url": "https://example.com/dfu.zip", "sha256": "dd", "size": 1 } }
+                    ]
+                }
+            ]
+        }"#;
+        let archives = parse_tool_archives(content, "linux-amd64").unwrap();
+        assert_eq!(archives.len(), 2);
+        assert_eq!(archives[0].sha256, "aa");
+        assert_eq!(archives[0].url, "https://example.com/xtensa-linux.tar.xz");
+        assert_eq!(archives[1].name, "esp-rom-elfs");
+        assert_eq!(parse_tool_archives(content, "win64").unwrap().len(), 3);
+    }
+
+    #[test]
+    fn test_parse_always_installed() {
+        let content = r#"{
+            "tools": [
+                { "name": "xtensa-esp-elf", "install": "always" },
+                { "name": "qemu-xtensa", "install": "on_request" },
+                {
+                    "name": "idf-exe",
+                    "install": "never",
+                    "platform_overrides": [ { "platforms": ["win32", "win64"], "install": "always" } ]
+                }
+            ]
+        }"#;
+        assert_eq!(parse_always_installed(content, "linux-amd64").unwrap(), vec!["xtensa-esp-elf".to_string()]);
+        assert_eq!(
+            parse_always_installed(content, "win64").unwrap(),
+            vec!["xtensa-esp-elf".to_string(), "idf-exe".to_string()]
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use clap::Arg;
+use clap_nested::Command;
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+use walkdir::WalkDir;
+
+use super::installation::resolve_installation;
+use super::python::get_python_env_directory;
+use super::status::{find_local_changes, print_local_changes};
+use super::tools::read_idf_tools;
+use super::worktree::prune_worktrees;
+use crate::config::{load_json, remove_idf_config};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Git stores objects as read-only files which can't be removed on Windows without clearing the flag.
+pub fn remove_directory(path: &str) -> Result<()> {
+    if fs::remove_dir_all(path).is_ok() {
+        return Ok(());
+    }
+    for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
+        let mut permissions = entry.metadata()?.permissions();
+        if permissions.readonly() {
+            #[allow(clippy::permissions_set_readonly_false)]
+            permissions.set_readonly(false);
+            fs::set_permissions(entry.path(), permissions)?;
+        }
+    }
+    fs::remove_dir_all(path)?;
+    Ok(())
+}
+
+pub fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// Tool directories of the installation which are not used by any other installation.
+/// When tools of other installation can't be determined, nothing is removed.
+fn get_unreferenced_tools(config: &json::JsonValue, idf_id: &str) -> Vec<String> {
+    let idf_path = config["idfInstalled"][idf_id]["path"].to_string();
+    let tools = match read_idf_tools(&idf_path) {
+        Ok(tools) => tools,
+        Err(_e) => return Vec::new(),
+    };
+    let mut referenced_tools: HashSet<String> = HashSet::new();
+    for (other_idf_id, other_idf) in config["idfInstalled"].entries() {
+        if other_idf_id == idf_id {
+            continue;
+        }
+        match read_idf_tools(&other_idf["path"].to_string()) {
+            Ok(other_tools) => {
+                referenced_tools.extend(other_tools.iter().map(|tool| tool.get_install_path()));
+            }
+            Err(_e) => {
+                println!(
+                    "Unable to read tools of {}, keeping all tools",
+                    other_idf["path"]
+                );
+                return Vec::new();
+            }
+        }
+    }
+    tools
+        .iter()
+        .map(|tool| tool.get_install_path())
+        .filter(|tool_path| !referenced_tools.contains(tool_path) && Path::new(tool_path).exists())
+        .collect()
+}
+
+/// Checkout with uncommitted work is removed only with force, stash would be removed with it.
+fn check_local_changes(idf_path: &str, force: bool) -> Result<()> {
+    if !Path::new(idf_path).join(".git").exists() {
+        return Ok(());
+    }
+    let changes = find_local_changes(idf_path, true, false)?;
+    if changes.is_empty() || force {
+        return Ok(());
+    }
+    print_local_changes(&changes);
+    Err("ESP-IDF has local changes, back them up or use --force to remove them with the checkout".into())
+}
+
+fn uninstall_idf(query: &str, assume_yes: bool, keep_checkout: bool, force: bool) -> Result<()> {
+    let config = load_json();
+    let idf_id = resolve_installation(&config, Some(query))?;
+    let idf = &config["idfInstalled"][idf_id.as_str()];
+    let idf_path = idf["path"].to_string();
+
+    // Environment might be shared by installations of the same version
+    let python_env = get_python_env_directory(&idf["python"].to_string()).filter(|env_directory| {
+        !config["idfInstalled"]
+            .entries()
+            .filter(|(other_idf_id, _)| *other_idf_id != idf_id)
+            .any(|(_, other_idf)| {
+                get_python_env_directory(&other_idf["python"].to_string()).as_ref() == Some(env_directory)
+            })
+    });
+    let tools = get_unreferenced_tools(&config, &idf_id);
+
+    if !keep_checkout {
+        check_local_changes(&idf_path, force)?;
+    }
+
+    println!("Uninstalling ESP-IDF {} ({})", idf["version"], idf_id);
+    if !keep_checkout {
+        println!("  checkout: {}", idf_path);
+    }
+    if let Some(env_directory) = &python_env {
+        println!("  python environment: {}", env_directory);
+    }
+    for tool_path in tools.iter() {
+        println!("  tool: {}", tool_path);
+    }
+
+    if !assume_yes && !confirm("Remove these files?") {
+        println!("Aborted");
+        return Ok(());
+    }
+
+    let mut removed_directories: Vec<String> = tools;
+    if let Some(env_directory) = python_env {
+        removed_directories.push(env_directory);
+    }
+    if !keep_checkout && Path::new(&idf_path).exists() {
+        removed_directories.push(idf_path);
+    }
+    for directory in removed_directories.iter() {
+        println!("Removing {}", directory);
+        match remove_directory(directory) {
+            Ok(_) => {
+                println!("Ok");
+            }
+            Err(e) => {
+                println!("Failed: {}", e);
+            }
+        }
+    }
+
+    // Checkout created by idf install --shared is registered as worktree of the shared repository
+    if !keep_checkout {
+        match prune_worktrees() {
+            Ok(pruned) => {
+                for name in pruned.iter() {
+                    println!("Pruned worktree {}", name);
+                }
+            }
+            Err(e) => {
+                println!("Unable to prune worktrees: {}", e);
+            }
+        }
+    }
+
+    match remove_idf_config(&idf_id) {
+        Some(selected_idf_id) => println!("Selected installation: {}", selected_idf_id),
+        None => println!("No installation of ESP-IDF left"),
+    }
+    Ok(())
+}
+
+fn get_uninstall_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    let query = matches.value_of("idf").unwrap();
+    if let Err(e) = uninstall_idf(
+        query,
+        matches.is_present("yes"),
+        matches.is_present("keep-checkout"),
+        matches.is_present("force"),
+    ) {
+        println!("{}", e);
+    }
+    Ok(())
+}
+
+pub fn get_uninstall_cmd<'a>() -> Command<'a, str> {
+    Command::new("uninstall")
+        .description("Remove ESP-IDF installation with its Python environment and unused tools")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("idf")
+                    .help("Id, version, path or directory name of the installation")
+                    .required(true)
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("yes")
+                    .short("y")
+                    .long("yes")
+                    .help("Do not ask for confirmation"),
+            )
+            .arg(
+                Arg::with_name("keep-checkout")
+                    .long("keep-checkout")
+                    .help("Keep ESP-IDF repository, remove only Python environment, tools and configuration"),
+            )
+            .arg(
+                Arg::with_name("force")
+                    .short("f")
+                    .long("force")
+                    .help("Remove checkout even when it has local changes"),
+            )
+        })
+        .runner(|_args, matches| get_uninstall_runner(_args, matches))
+}
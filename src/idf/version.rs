@@ -0,0 +1,94 @@
+use git2::{DescribeOptions, Repository};
+use std::fmt;
+use std::fs;
+
+/// Version of ESP-IDF declared in tools/cmake/version.cmake of the checkout.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IdfVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl IdfVersion {
+    /// Version used in names of Python environments and constraint files, e.g. 5.1
+    pub fn get_major_minor(&self) -> String {
+        format!("{}.{}", self.major, self.minor)
+    }
+}
+
+impl fmt::Display for IdfVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "v{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+fn get_cmake_variable(content: &str, name: &str) -> Option<u32> {
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter_map(|line| line.strip_prefix("set("))
+        .filter_map(|line| line.strip_prefix(name))
+        .filter_map(|line| line.trim().strip_suffix(')'))
+        .find_map(|value| value.trim().parse().ok())
+}
+
+pub fn parse_version_cmake(content: &str) -> Option<IdfVersion> {
+    Some(IdfVersion {
+        major: get_cmake_variable(content, "IDF_VERSION_MAJOR")?,
+        minor: get_cmake_variable(content, "IDF_VERSION_MINOR")?,
+        patch: get_cmake_variable(content, "IDF_VERSION_PATCH")?,
+    })
+}
+
+pub fn read_idf_version(idf_path: &str) -> Option<IdfVersion> {
+    let content = fs::read_to_string(format!("{}/tools/cmake/version.cmake", idf_path)).ok()?;
+    parse_version_cmake(&content)
+}
+
+/// Human readable version of the checkout, e.g. v5.1.2 or v5.2-dev-1234-gabcdef.
+/// Falls back to version.cmake when the checkout has no tags.
+pub fn describe_idf_version(idf_path: &str) -> Option<String> {
+    let described = Repository::open(idf_path).ok().and_then(|repo| {
+        repo.describe(DescribeOptions::new().describe_tags())
+            .and_then(|description| description.format(None))
+            .ok()
+    });
+    match described {
+        Some(description) => Some(description),
+        None => read_idf_version(idf_path).map(|version| version.to_string()),
+    }
+}
+
+/// Reference which clones the same sources again: exact tag or branch. None for detached HEAD,
+/// because git clone --branch doesn't accept a commit.
+pub fn get_checkout_reference(idf_path: &str) -> Option<String> {
+    let repo = Repository::open(idf_path).ok()?;
+    let exact_tag = repo
+        .describe(DescribeOptions::new().describe_tags().max_candidates_tags(0))
+        .and_then(|description| description.format(None))
+        .ok();
+    if exact_tag.is_some() {
+        return exact_tag;
+    }
+    let head = repo.head().ok()?;
+    if head.is_branch() {
+        return head.shorthand().map(|branch| branch.to_string());
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version_cmake() {
+        let content = "# ESP-IDF Version\nset(IDF_VERSION_MAJOR 5)\nset(IDF_VERSION_MINOR 1)\nset(IDF_VERSION_PATCH 2)\n\nset(ENV{IDF_VERSION} \"${IDF_VERSION_MAJOR}.${IDF_VERSION_MINOR}.${IDF_VERSION_PATCH}\")\n";
+        let version = parse_version_cmake(content).unwrap();
+        assert_eq!(version, IdfVersion { major: 5, minor: 1, patch: 2 });
+        assert_eq!(version.get_major_minor(), "5.1");
+        assert_eq!(version.to_string(), "v5.1.2");
+        assert_eq!(parse_version_cmake("set(IDF_VERSION_MAJOR 5)"), None);
+    }
+}
@@ -0,0 +1,155 @@
+use git2::Repository;
+use std::path::Path;
+
+use super::release::IDF_REPOSITORY_URL;
+use super::{run_git, run_process};
+use crate::config::get_reference_repository_path;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Submodules of ESP-IDF use URLs relative to the main repository, e.g. ../../espressif/esptool.git
+pub fn resolve_submodule_url(base_url: &str, url: &str) -> String {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return url.to_string();
+    }
+    let mut base = base_url.trim_end_matches('/').to_string();
+    let mut relative = url;
+    loop {
+        if let Some(rest) = relative.strip_prefix("./") {
+            relative = rest;
+        } else if let Some(rest) = relative.strip_prefix("../") {
+            if let Some(position) = base.rfind('/') {
+                base.truncate(position);
+            }
+            relative = rest;
+        } else {
+            break;
+        }
+    }
+    format!("{}/{}", base, relative)
+}
+
+/// Bare repository shared by all worktree installations. Branches of origin are fetched as
+/// remote-tracking branches, so that fetch never touches a branch checked out in a worktree.
+fn prepare_reference_repository(git_path: &str, verbose: bool) -> Result<String> {
+    let reference_path = get_reference_repository_path();
+    if !Path::new(&reference_path).exists() {
+        println!("Cloning reference repository: {}", reference_path);
+        let arguments = vec![
+            "clone".to_string(),
+            "--bare".to_string(),
+            IDF_REPOSITORY_URL.to_string(),
+            reference_path.clone(),
+        ];
+        run_process(git_path, &arguments, verbose)?;
+        run_git(
+            git_path,
+            &reference_path,
+            &["config", "remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*"],
+            verbose,
+        )?;
+    }
+    println!("Fetching reference repository: {}", reference_path);
+    run_git(git_path, &reference_path, &["fetch", "--tags", "--prune", "origin"], verbose)?;
+    Ok(reference_path)
+}
+
+/// Mirror of submodule stored in modules directory of the reference repository.
+fn prepare_submodule_reference(
+    git_path: &str,
+    reference_path: &str,
+    name: &str,
+    url: &str,
+    verbose: bool,
+) -> Result<String> {
+    let submodule_reference_path = format!("{}/modules/{}", reference_path, name);
+    if Path::new(&submodule_reference_path).exists() {
+        run_git(git_path, &submodule_reference_path, &["fetch", "--prune", "origin"], verbose)?;
+    } else {
+        let arguments = vec![
+            "clone".to_string(),
+            "--mirror".to_string(),
+            url.to_string(),
+            submodule_reference_path.clone(),
+        ];
+        run_process(git_path, &arguments, verbose)?;
+    }
+    Ok(submodule_reference_path)
+}
+
+/// Create checkout of ESP-IDF as worktree of the reference repository. Submodules are cloned
+/// with objects borrowed from their mirrors in the reference repository. Nested submodules
+/// are small and they're cloned without reference.
+pub fn add_worktree(git_path: &str, idf_version: &str, idf_path: &str, verbose: bool) -> Result<()> {
+    let reference_path = prepare_reference_repository(git_path, verbose)?;
+    let reference = Repository::open_bare(&reference_path)?;
+    let remote_branch = format!("refs/remotes/origin/{}", idf_version);
+    let revision = if reference.find_reference(&remote_branch).is_ok() {
+        format!("origin/{}", idf_version)
+    } else {
+        idf_version.to_string()
+    };
+    println!("Adding worktree {} of {}", idf_path, reference_path);
+    run_git(
+        git_path,
+        &reference_path,
+        &["worktree", "add", "--detach", idf_path, &revision],
+        verbose,
+    )?;
+
+    let repo = Repository::open(idf_path)?;
+    for submodule in repo.submodules()? {
+        let name = submodule.name().ok_or("Submodule name is not valid UTF-8")?;
+        let path = submodule.path().display().to_string();
+        let url = resolve_submodule_url(IDF_REPOSITORY_URL, submodule.url().unwrap_or_default());
+        let submodule_reference_path =
+            prepare_submodule_reference(git_path, &reference_path, name, &url, verbose)?;
+        run_git(
+            git_path,
+            idf_path,
+            &["submodule", "update", "--init", "--reference", &submodule_reference_path, "--", &path],
+            verbose,
+        )?;
+    }
+    run_git(git_path, idf_path, &["submodule", "update", "--init", "--recursive"], verbose)
+}
+
+/// Remove administrative data of worktrees whose directories were deleted.
+/// Returns names of pruned worktrees.
+pub fn prune_worktrees() -> Result<Vec<String>> {
+    let reference_path = get_reference_repository_path();
+    if !Path::new(&reference_path).exists() {
+        return Ok(Vec::new());
+    }
+    let reference = Repository::open_bare(&reference_path)?;
+    let mut pruned: Vec<String> = Vec::new();
+    for name in reference.worktrees()?.iter().flatten() {
+        let worktree = reference.find_worktree(name)?;
+        if worktree.validate().is_err() {
+            worktree.prune(None)?;
+            pruned.push(name.to_string());
+        }
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_submodule_url() {
+        assert_eq!(
+            resolve_submodule_url(IDF_REPOSITORY_URL, "../../espressif/esptool.git"),
+            "https://github.com/espressif/esptool.git"
+        );
+        assert_eq!(
+            resolve_submodule_url("https://gitee.com/EspressifSystems/esp-idf/", "../mbedtls"),
+            "https://gitee.com/EspressifSystems/mbedtls"
+        );
+        assert_eq!(
+            resolve_submodule_url(IDF_REPOSITORY_URL, "https://github.com/ThrowTheSwitch/Unity.git"),
+            "https://github.com/ThrowTheSwitch/Unity.git"
+        );
+    }
+}
@@ -9,13 +9,19 @@ mod antivirus;
 mod certificate;
 mod companion;
 mod config;
+mod doctor;
 mod driver;
 mod ide;
 mod idf;
 mod launcher;
 mod package;
+mod plan;
+mod project;
 mod rust;
 mod shell;
+mod sync;
+#[cfg(test)]
+mod test_util;
 mod toit;
 
 async fn app() -> Result<()> {
@@ -31,12 +37,14 @@ async fn app() -> Result<()> {
         .add_cmd(certificate::get_multi_cmd())
         .add_cmd(companion::get_multi_cmd())
         .add_cmd(config::get_multi_cmd())
+        .add_cmd(doctor::get_cmd())
         .add_cmd(driver::get_multi_cmd())
         .add_cmd(ide::get_multi_cmd())
         .add_cmd(idf::get_multi_cmd())
         .add_cmd(launcher::get_multi_cmd())
         .add_cmd(rust::get_multi_cmd())
         .add_cmd(shell::get_multi_cmd())
+        .add_cmd(sync::get_cmd())
         .add_cmd(toit::get_multi_cmd())
         .no_cmd(|_args, _matches| {
             println!("No command matched. Use parameter --help");
@@ -2,11 +2,17 @@ pub mod antivirus;
 pub mod certificate;
 pub mod companion;
 pub mod config;
+pub mod doctor;
 pub mod driver;
 pub mod ide;
 pub mod idf;
 pub mod launcher;
 pub mod package;
+pub mod plan;
+pub mod project;
 pub mod rust;
 pub mod shell;
+pub mod sync;
+#[cfg(test)]
+mod test_util;
 pub mod toit;
@@ -3,6 +3,8 @@ use std::{fs, io};
 use std::path::Path;
 use std::io::Cursor;
 use std::fs::File;
+use std::env;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::PathBuf;
 use tar::Archive;
 use flate2::read::GzDecoder;
@@ -11,8 +13,18 @@ use xz2::read::XzDecoder;
 use tokio::runtime::Handle;
 use crate::config::{ get_dist_path, get_tool_path };
 
+pub mod signature;
+pub use signature::SignaturePolicy;
+use signature::verify_package;
+
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
+// Directory with pre-staged artifacts, files are looked up by name before downloading
+const ARTIFACT_DIR_VARIABLE: &str = "IDF_ENV_ARTIFACT_DIR";
+
+// Rough ratio between archive size and extracted content, used when the archive is not downloaded yet
+const ESTIMATED_EXTRACTION_RATIO: u64 = 4;
+
 pub fn unzip(file_path: String, output_directory: String) -> Result<()> {
     let file_name = std::path::Path::new(&file_path);
     let file = fs::File::open(&file_name).unwrap();
@@ -184,25 +196,293 @@ pub fn untargz(file_path: String, output_directory: String) -> Result<()> {
     Ok(())
 }
 
-async fn fetch_url(url: String, output: String) -> Result<()> {
-    let response = reqwest::get(&url).await;
-    match response {
-        Ok(r) => {
-            let mut file = std::fs::File::create(output)?;
-            let mut content = Cursor::new(r.bytes().await?);
-            std::io::copy(&mut content, &mut file)?;
-
-            return Ok(())
+pub fn format_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// Returns the closest existing ancestor, directories like dist/ might not be created yet.
+pub fn get_existing_ancestor(path: &Path) -> PathBuf {
+    let mut current = path.to_path_buf();
+    while !current.exists() {
+        match current.parent() {
+            Some(parent) => current = parent.to_path_buf(),
+            None => break,
+        }
+    }
+    current
+}
+
+#[cfg(unix)]
+fn get_volume(path: &Path) -> String {
+    use std::os::unix::fs::MetadataExt;
+    let existing_path = get_existing_ancestor(path);
+    let device = match fs::metadata(&existing_path) {
+        Ok(metadata) => metadata.dev(),
+        Err(_) => return existing_path.display().to_string(),
+    };
+    // Walk up until the device changes, the last directory on the same device is the mount point
+    let mut volume = existing_path.clone();
+    for ancestor in existing_path.ancestors() {
+        match fs::metadata(ancestor) {
+            Ok(metadata) if metadata.dev() == device => volume = ancestor.to_path_buf(),
+            _ => break,
+        }
+    }
+    volume.display().to_string()
+}
+
+#[cfg(windows)]
+fn get_volume(path: &Path) -> String {
+    use std::path::Component;
+    match path.components().next() {
+        Some(Component::Prefix(prefix)) => prefix.as_os_str().to_string_lossy().to_string(),
+        _ => get_existing_ancestor(path).display().to_string(),
+    }
+}
+
+async fn fetch_content_length(url: String) -> Option<u64> {
+    let client = reqwest::Client::new();
+    match client.head(&url).send().await {
+        Ok(response) if response.status().is_success() => response.content_length(),
+        _ => None,
+    }
+}
+
+fn get_content_length(url: String) -> Option<u64> {
+    let handle = Handle::current().clone();
+    let th = std::thread::spawn(move || handle.block_on(fetch_content_length(url)));
+    th.join().unwrap_or(None)
+}
+
+/// Local path of package source, e.g. "file:///mnt/cache/x.zip" or "/mnt/cache/x.zip".
+fn get_file_url_path(url: &str) -> Option<PathBuf> {
+    if let Some(path) = url.strip_prefix("file://") {
+        // file:///C:/dist/x.zip on Windows
+        #[cfg(windows)]
+        let path = match path.strip_prefix('/') {
+            Some(stripped) if stripped.chars().nth(1) == Some(':') => stripped,
+            _ => path,
+        };
+        return Some(PathBuf::from(path.replace("%20", " ")));
+    }
+    if url.starts_with("http://") || url.starts_with("https://") {
+        return None;
+    }
+    Some(PathBuf::from(url))
+}
+
+/// Find package source which does not require download: local path, file:// URL
+/// or file with the same name in IDF_ENV_ARTIFACT_DIR.
+pub fn get_local_source(package_url: &str, package_archive: &str) -> Option<PathBuf> {
+    if let Some(path) = get_file_url_path(package_url) {
+        return Some(path);
+    }
+
+    let artifact_dir = env::var(ARTIFACT_DIR_VARIABLE).ok()?;
+    let file_names = [
+        Path::new(package_archive).file_name(),
+        Path::new(package_url).file_name(),
+    ];
+    file_names
+        .iter()
+        .flatten()
+        .map(|file_name| Path::new(&artifact_dir).join(file_name))
+        .find(|candidate| candidate.is_file())
+}
+
+fn get_package_size(package_url: &str, package_archive: &str) -> Option<u64> {
+    match get_local_source(package_url, package_archive) {
+        Some(source) => fs::metadata(source).ok().map(|metadata| metadata.len()),
+        None => get_content_length(package_url.to_string()),
+    }
+}
+
+fn read_xz_varint(buffer: &[u8], position: &mut usize) -> Result<u64> {
+    let mut value: u64 = 0;
+    for shift in 0..9 {
+        let byte = *buffer.get(*position).ok_or("Truncated xz index")?;
+        *position += 1;
+        value |= ((byte & 0x7f) as u64) << (shift * 7);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err("Invalid xz index".into())
+}
+
+/// Reads uncompressed size from the index stored at the end of xz stream.
+fn get_xz_uncompressed_size(file_path: &str) -> Result<u64> {
+    let mut file = File::open(file_path)?;
+    let mut footer = [0u8; 12];
+    file.seek(SeekFrom::End(-12))?;
+    file.read_exact(&mut footer)?;
+    if &footer[10..12] != b"YZ" {
+        return Err("Invalid xz footer".into());
+    }
+    let backward_size = u32::from_le_bytes([footer[4], footer[5], footer[6], footer[7]]) as i64;
+    let index_size = (backward_size + 1) * 4;
+    let mut index = vec![0u8; index_size as usize];
+    file.seek(SeekFrom::End(-12 - index_size))?;
+    file.read_exact(&mut index)?;
+    if index[0] != 0 {
+        return Err("Invalid xz index".into());
+    }
+    let mut position = 1;
+    let records = read_xz_varint(&index, &mut position)?;
+    let mut uncompressed_size = 0;
+    for _ in 0..records {
+        let _unpadded_size = read_xz_varint(&index, &mut position)?;
+        uncompressed_size += read_xz_varint(&index, &mut position)?;
+    }
+    Ok(uncompressed_size)
+}
+
+/// Reads uncompressed size from gzip trailer. The value is stored modulo 4 GB.
+fn get_gz_uncompressed_size(file_path: &str) -> Result<u64> {
+    let mut file = File::open(file_path)?;
+    let mut trailer = [0u8; 4];
+    file.seek(SeekFrom::End(-4))?;
+    file.read_exact(&mut trailer)?;
+    Ok(u32::from_le_bytes(trailer) as u64)
+}
+
+fn get_zip_uncompressed_size(file_path: &str) -> Result<u64> {
+    let file = File::open(file_path)?;
+    let mut archive = zip::ZipArchive::new(file)?;
+    let mut uncompressed_size = 0;
+    for i in 0..archive.len() {
+        uncompressed_size += archive.by_index_raw(i)?.size();
+    }
+    Ok(uncompressed_size)
+}
+
+/// Size of extracted content based on the archive index.
+pub fn get_archive_uncompressed_size(file_path: &str) -> Result<u64> {
+    let extension = Path::new(file_path).extension().and_then(|e| e.to_str()).unwrap_or("");
+    match extension {
+        "zip" => get_zip_uncompressed_size(file_path),
+        "gz" => get_gz_uncompressed_size(file_path),
+        "xz" => get_xz_uncompressed_size(file_path),
+        _ => Err(format!("Unsupported archive: {}", file_path).into()),
+    }
+}
+
+/// Verify that volumes with dist/ and tools/ have enough space for the package.
+/// The check is skipped for values which can't be determined, e.g. server without Content-Length.
+pub fn check_disk_space(package_url: &str, package_archive: &str, output_directory: &str) -> Result<()> {
+    let archive_size = match fs::metadata(package_archive) {
+        Ok(metadata) => Some(metadata.len()),
+        Err(_) => None,
+    };
+
+    let (download_size, extracted_size) = match archive_size {
+        Some(size) => (0, get_archive_uncompressed_size(package_archive).unwrap_or(size * ESTIMATED_EXTRACTION_RATIO)),
+        None => match get_package_size(package_url, package_archive) {
+            Some(size) => (size, size * ESTIMATED_EXTRACTION_RATIO),
+            None => return Ok(()),
         },
-        _ => {
-            println!("Download of {} failed", url);
-            // Exit code is 0, there is temporal issue with Windows Installer which does not recover from error exit code
-            #[cfg(windows)]
-            std::process::exit(0);
-            #[cfg(unix)]
-            std::process::exit(1);
+    };
+
+    let mut requirements: Vec<(String, String, u64, u64)> = Vec::new();
+    for (label, path, size) in [
+        ("dist", Path::new(package_archive), download_size),
+        ("tools", Path::new(output_directory), extracted_size),
+    ] {
+        let volume = get_volume(path);
+        let available = match fs2::available_space(get_existing_ancestor(path)) {
+            Ok(available) => available,
+            Err(_) => continue,
+        };
+        match requirements.iter_mut().find(|r| r.0 == volume) {
+            Some(requirement) => {
+                requirement.1 = format!("{}, {}", requirement.1, label);
+                requirement.2 += size;
+            }
+            None => requirements.push((volume, label.to_string(), size, available)),
         }
+    }
+
+    let insufficient: Vec<String> = requirements
+        .iter()
+        .filter(|(_, _, required, available)| required > available)
+        .map(|(volume, labels, required, available)| {
+            format!(
+                "  {} ({}): {} required, {} available",
+                volume,
+                labels,
+                format_size(*required),
+                format_size(*available)
+            )
+        })
+        .collect();
+
+    if insufficient.is_empty() {
+        return Ok(());
+    }
+
+    Err(format!(
+        "Not enough free disk space for {}:\n{}",
+        Path::new(package_archive).file_name().unwrap_or_default().to_string_lossy(),
+        insufficient.join("\n")
+    )
+    .into())
+}
+
+fn get_metadata_path(package_archive: &str) -> String {
+    format!("{}.meta.json", package_archive)
+}
+
+/// Remember validators of downloaded archive, so that later refresh can issue conditional request.
+fn store_metadata(package_archive: &str, url: &str, headers: &reqwest::header::HeaderMap) {
+    let get_header = |name: reqwest::header::HeaderName| {
+        headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("")
+            .to_string()
+    };
+    let metadata = json::object! {
+        "url": url,
+        "etag": get_header(reqwest::header::ETAG),
+        "lastModified": get_header(reqwest::header::LAST_MODIFIED)
     };
+    if let Err(e) = fs::write(get_metadata_path(package_archive), format!("{:#}", metadata)) {
+        println!("Unable to store metadata of {}: {}", package_archive, e);
+    }
+}
+
+fn load_metadata(package_archive: &str) -> Option<json::JsonValue> {
+    let content = fs::read_to_string(get_metadata_path(package_archive)).ok()?;
+    json::parse(&content).ok()
+}
+
+async fn fetch_url(url: String, output: String) -> Result<()> {
+    let response = reqwest::get(&url).await
+        .with_context(|| format!("Download of {} failed", url))?;
+    if !response.status().is_success() {
+        return Err(format!("Download of {} failed: {}", url, response.status()).into());
+    }
+    store_response(response, &url, &output).await
+}
+
+/// Write response body next to the archive first, so an interrupted transfer never looks like a cached archive.
+async fn store_response(response: reqwest::Response, url: &str, output: &str) -> Result<()> {
+    let headers = response.headers().clone();
+    let partial_output = format!("{}.part", output);
+    let mut file = std::fs::File::create(&partial_output)?;
+    let mut content = Cursor::new(response.bytes().await?);
+    std::io::copy(&mut content, &mut file)?;
+    drop(file);
+    fs::rename(&partial_output, output).with_context(|| format!("Unable to store `{}`", output))?;
+    store_metadata(output, url, &headers);
+    Ok(())
 }
 
 async fn download_zip(url: String, output: String) -> Result<()> {
@@ -210,19 +490,76 @@ async fn download_zip(url: String, output: String) -> Result<()> {
         println!("Using cached archive: {}", output);
         return Ok(());
     }
+    if let Some(source) = get_local_source(&url, &output) {
+        println!("Copying {} to {}", source.display(), output);
+        fs::copy(&source, &output).with_context(|| format!("Unable to copy `{}`", source.display()))?;
+        return Ok(());
+    }
     println!("Downloading {} to {}", url, output);
     fetch_url(url, output).await
 }
 
+/// Re-download archive only when the remote changed. Returns true when new content was stored.
+async fn refresh_zip(url: String, output: String) -> Result<bool> {
+    if !Path::new(&output).exists() {
+        download_zip(url, output).await?;
+        return Ok(true);
+    }
+
+    if let Some(source) = get_local_source(&url, &output) {
+        let source_modified = fs::metadata(&source)?.modified()?;
+        if source_modified <= fs::metadata(&output)?.modified()? {
+            println!("Archive is up to date: {}", output);
+            return Ok(false);
+        }
+        println!("Copying {} to {}", source.display(), output);
+        fs::copy(&source, &output).with_context(|| format!("Unable to copy `{}`", source.display()))?;
+        return Ok(true);
+    }
+
+    let mut request = reqwest::Client::new().get(&url);
+    if let Some(metadata) = load_metadata(&output) {
+        if metadata["url"].as_str() == Some(url.as_str()) {
+            if let Some(etag) = metadata["etag"].as_str().filter(|v| !v.is_empty()) {
+                request = request.header(reqwest::header::IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = metadata["lastModified"].as_str().filter(|v| !v.is_empty()) {
+                request = request.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+    }
+
+    println!("Checking {} for update", url);
+    let response = request.send().await?;
+    if response.status() == reqwest::StatusCode::NOT_MODIFIED {
+        println!("Archive is up to date: {}", output);
+        return Ok(false);
+    }
+    if !response.status().is_success() {
+        return Err(format!("Download of {} failed: {}", url, response.status()).into());
+    }
+
+    println!("Downloading {} to {}", url, output);
+    store_response(response, &url, &output).await?;
+    Ok(true)
+}
+
 pub fn download_package(package_url: String, package_archive: String) -> Result<()> {
     let handle = Handle::current().clone();
     let th = std::thread::spawn(move || {
-        handle.block_on(download_zip(package_url, package_archive)).unwrap();
+        handle.block_on(download_zip(package_url, package_archive))
     });
-    Ok(th.join().unwrap())
+    th.join().unwrap()
 }
 
-pub fn prepare_package(package_url: String, package_archive: &str, output_directory: String) -> Result<()> {
+/// Download file, or download it again when it changed on the server. Returns true when new content was stored.
+pub fn refresh_file(url: String, output: String) -> Result<bool> {
+    let handle = Handle::current().clone();
+    let th = std::thread::spawn(move || handle.block_on(refresh_zip(url, output)));
+    th.join().unwrap()
+}
+
+pub fn prepare_package(package_url: String, package_archive: &str, output_directory: String, policy: SignaturePolicy) -> Result<()> {
     if Path::new(&output_directory).exists() {
         println!("Using cached directory: {}", output_directory);
         return Ok(());
@@ -239,9 +576,29 @@ pub fn prepare_package(package_url: String, package_archive: &str, output_direct
 
     let package_archive = get_dist_path(package_archive);
 
-    match download_package(package_url, package_archive.clone()) {
+    if let Err(e) = check_disk_space(&package_url, &package_archive, &output_directory) {
+        println!("{}", e);
+        return Err(e);
+    }
+
+    match download_package(package_url.clone(), package_archive.clone()) {
         Ok(_) => { println!("Download ok"); },
-        Err(_e) => { println!("Download failed");}
+        Err(e) => {
+            println!("Download failed: {}", e);
+            return Err(e);
+        }
+    }
+
+    if let Err(e) = verify_package(&package_url, &package_archive, policy) {
+        println!("{}", e);
+        let _ = fs::remove_file(&package_archive);
+        return Err(e);
+    }
+
+    // Precise check, the archive index is available now
+    if let Err(e) = check_disk_space("", &package_archive, &output_directory) {
+        println!("{}", e);
+        return Err(e);
     }
 
     println!("Extracting to {}", output_directory);
@@ -266,13 +623,36 @@ pub fn prepare_package(package_url: String, package_archive: &str, output_direct
     Ok(())
 }
 
-pub fn prepare_single_binary(package_url: &str, binary_name: &str, output_directory: &str) -> String {
+/// Same as prepare_package, but cached archive is revalidated against the server using ETag/Last-Modified.
+/// Extracted content is replaced only when the remote archive changed.
+pub fn refresh_package(package_url: String, package_archive: &str, output_directory: String, policy: SignaturePolicy) -> Result<()> {
+    refresh_archive(&package_url, package_archive, &output_directory)?;
+    prepare_package(package_url, package_archive, output_directory, policy)
+}
+
+/// Re-download archive when it changed on the server and drop the directory extracted from the old one.
+pub fn refresh_archive(package_url: &str, package_archive: &str, output_directory: &str) -> Result<()> {
+    let archive_path = get_dist_path(package_archive);
+    if let Some(parent) = Path::new(&archive_path).parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let updated = refresh_file(package_url.to_string(), archive_path)?;
+
+    if updated && Path::new(output_directory).exists() {
+        println!("Removing outdated directory: {}", output_directory);
+        fs::remove_dir_all(output_directory).with_context(|| format!("Unable to delete `{}`", output_directory))?;
+    }
+    Ok(())
+}
+
+pub fn prepare_single_binary(package_url: &str, binary_name: &str, output_directory: &str, policy: SignaturePolicy) -> Result<String> {
     let tool_path = get_tool_path(output_directory.to_string());
     let binary_path = format!("{}/{}", tool_path, binary_name);
 
     if Path::new(&binary_path).exists() {
         println!("Using cached tool: {}", binary_path);
-        return binary_path;
+        return Ok(binary_path);
     }
 
     if !Path::new(&tool_path).exists() {
@@ -285,12 +665,22 @@ pub fn prepare_single_binary(package_url: &str, binary_name: &str, output_direct
 
     match download_package(package_url.to_string(), binary_path.to_string()) {
         Ok(_) => { println!("Ok"); },
-        Err(_e) => { println!("Failed");}
+        Err(e) => {
+            println!("Failed");
+            return Err(e);
+        }
     }
-    return binary_path;
+
+    // The binary is executed right after download, never keep it when the signature does not match
+    if let Err(e) = verify_package(package_url, &binary_path, policy) {
+        println!("{}", e);
+        let _ = fs::remove_file(&binary_path);
+        return Err(e);
+    }
+    Ok(binary_path)
 }
 
-pub fn prepare_package_strip_prefix(package_url: &str, package_archive: &str, output_directory: String, strip_prefix: &str) -> Result<()> {
+pub fn prepare_package_strip_prefix(package_url: &str, package_archive: &str, output_directory: String, strip_prefix: &str, policy: SignaturePolicy) -> Result<()> {
     if Path::new(&output_directory).exists() {
         println!("Using cached directory: {}", output_directory);
         return Ok(());
@@ -307,10 +697,31 @@ pub fn prepare_package_strip_prefix(package_url: &str, package_archive: &str, ou
 
     let package_archive = get_dist_path(package_archive);
 
+    if let Err(e) = check_disk_space(package_url, &package_archive, &output_directory) {
+        println!("{}", e);
+        return Err(e);
+    }
+
     match download_package(package_url.to_string(), package_archive.to_string()) {
         Ok(_) => { println!("Downloaded"); },
-        Err(_e) => { println!("Unable to download package"); }
+        Err(e) => {
+            println!("Unable to download package: {}", e);
+            return Err(e);
+        }
     }
+
+    if let Err(e) = verify_package(package_url, &package_archive, policy) {
+        println!("{}", e);
+        let _ = fs::remove_file(&package_archive);
+        return Err(e);
+    }
+
+    // Precise check, the archive index is available now
+    if let Err(e) = check_disk_space("", &package_archive, &output_directory) {
+        println!("{}", e);
+        return Err(e);
+    }
+
     if !Path::new(&output_directory).exists() {
         let package_archive = package_archive.to_string();
         let extension = Path::new(package_archive.as_str()).extension().unwrap().to_str().unwrap();
@@ -335,8 +746,51 @@ pub fn remove_package(package_archive: &str, output_directory: &str) -> Result<(
     if Path::new(package_archive).exists() {
         fs::remove_file(package_archive).with_context(|| format!("Unable to delete `{}`", package_archive))?;
     }
+    let metadata_path = get_metadata_path(package_archive);
+    if Path::new(&metadata_path).exists() {
+        fs::remove_file(&metadata_path).with_context(|| format!("Unable to delete `{}`", metadata_path))?;
+    }
     if Path::new(output_directory).exists() {
         fs::remove_dir_all(output_directory).with_context(|| format!("Unable to delete `{}`", output_directory))?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TestDirectory;
+    use std::io::Write;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0.0 B");
+        assert_eq!(format_size(1536), "1.5 KB");
+        assert_eq!(format_size(3 * 1024 * 1024 * 1024), "3.0 GB");
+    }
+
+    #[test]
+    fn test_get_file_url_path() {
+        assert_eq!(get_file_url_path("https://dl.espressif.com/x.zip"), None);
+        assert_eq!(get_file_url_path("file:///mnt/cache/x.zip"), Some(PathBuf::from("/mnt/cache/x.zip")));
+        assert_eq!(get_file_url_path("/mnt/cache/x.zip"), Some(PathBuf::from("/mnt/cache/x.zip")));
+    }
+
+    #[test]
+    fn test_get_archive_uncompressed_size() {
+        let content = vec![42u8; 100_000];
+        let directory = TestDirectory::new("archive-size");
+
+        let xz_path = directory.join("content.tar.xz").display().to_string();
+        let mut encoder = xz2::write::XzEncoder::new(File::create(&xz_path).unwrap(), 6);
+        encoder.write_all(&content).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(get_archive_uncompressed_size(&xz_path).unwrap(), 100_000);
+
+        let gz_path = directory.join("content.tar.gz").display().to_string();
+        let mut encoder = flate2::write::GzEncoder::new(File::create(&gz_path).unwrap(), flate2::Compression::default());
+        encoder.write_all(&content).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(get_archive_uncompressed_size(&gz_path).unwrap(), 100_000);
+    }
+}
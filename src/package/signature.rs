@@ -0,0 +1,121 @@
+use minisign_verify::{PublicKey, Signature};
+use std::fs::{self, File};
+use std::io::Read;
+use tokio::runtime::Handle;
+
+use crate::package::get_local_source;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+// Minisign public keys of Espressif used to sign drivers, toolchains and tools.
+const ESPRESSIF_PUBLIC_KEYS: &[&str] = &[];
+
+// Additional keys trusted for signatures of artifacts, e.g. of internal mirror, pinned at build time.
+// Multiple keys are separated by comma:
+// IDF_ENV_SIGNATURE_PUBLIC_KEYS="RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3" cargo build --release
+const EXTRA_PUBLIC_KEYS: Option<&str> = option_env!("IDF_ENV_SIGNATURE_PUBLIC_KEYS");
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SignaturePolicy {
+    /// Artifact must be signed by one of the pinned keys, e.g. drivers and toolchains
+    Required,
+    /// Signature is verified only when published next to the artifact, e.g. third-party crates
+    Optional,
+}
+
+fn get_public_keys() -> Vec<PublicKey> {
+    ESPRESSIF_PUBLIC_KEYS
+        .iter()
+        .copied()
+        .chain(EXTRA_PUBLIC_KEYS.unwrap_or("").split(','))
+        .map(|key| key.trim())
+        .filter(|key| !key.is_empty())
+        .filter_map(|key| match PublicKey::from_base64(key) {
+            Ok(public_key) => Some(public_key),
+            Err(e) => {
+                println!("Ignoring invalid public key {}: {}", key, e);
+                None
+            }
+        })
+        .collect()
+}
+
+pub fn get_signature_url(package_url: &str) -> String {
+    format!("{}.minisig", package_url)
+}
+
+async fn fetch_signature(url: String) -> Result<Option<String>> {
+    let response = reqwest::get(&url).await?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !response.status().is_success() {
+        return Err(format!("Download of {} failed: {}", url, response.status()).into());
+    }
+    Ok(Some(response.text().await?))
+}
+
+fn load_signature(package_url: &str, package_archive: &str) -> Result<Option<String>> {
+    if let Some(source) = get_local_source(package_url, package_archive) {
+        let signature_path = format!("{}.minisig", source.display());
+        return match fs::read_to_string(&signature_path) {
+            Ok(signature) => Ok(Some(signature)),
+            Err(_) => Ok(None),
+        };
+    }
+
+    let handle = Handle::current().clone();
+    let signature_url = get_signature_url(package_url);
+    let th = std::thread::spawn(move || handle.block_on(fetch_signature(signature_url)));
+    th.join().unwrap()
+}
+
+fn verify_file(file_path: &str, signature: &Signature, public_keys: &[PublicKey]) -> Result<()> {
+    let public_key = public_keys
+        .iter()
+        .find(|public_key| public_key.verify_stream(signature).is_ok())
+        .ok_or_else(|| format!("{} is not signed by any pinned key", file_path))?;
+
+    let mut verifier = public_key.verify_stream(signature)?;
+    let mut file = File::open(file_path)?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let size = file.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+        verifier.update(&buffer[..size]);
+    }
+    verifier
+        .finalize()
+        .map_err(|e| format!("Signature verification of {} failed: {}", file_path, e))?;
+    Ok(())
+}
+
+/// Verify detached minisign signature of downloaded archive. Artifacts with required signature
+/// are rejected when the signature is missing or no public key is pinned.
+pub fn verify_package(package_url: &str, package_archive: &str, policy: SignaturePolicy) -> Result<()> {
+    let public_keys = get_public_keys();
+    if public_keys.is_empty() {
+        if policy == SignaturePolicy::Required {
+            return Err(format!("No public key is pinned to verify signature of {}", package_archive).into());
+        }
+        println!("No public key is pinned, skipping signature verification of {}", package_archive);
+        return Ok(());
+    }
+
+    let signature = match load_signature(package_url, package_archive)? {
+        Some(signature) => Signature::decode(&signature)?,
+        None => {
+            if policy == SignaturePolicy::Required {
+                return Err(format!("Signature {} not found, the artifact must be signed", get_signature_url(package_url)).into());
+            }
+            println!("Signature not published for {}, skipping verification", package_archive);
+            return Ok(());
+        }
+    };
+
+    verify_file(package_archive, &signature, &public_keys)?;
+    println!("Signature verified: {}", signature.trusted_comment());
+    Ok(())
+}
@@ -0,0 +1,307 @@
+use clap::Arg;
+use std::fs::{copy, create_dir_all, remove_dir_all, remove_file};
+use std::path::Path;
+use std::process::Stdio;
+
+use crate::config::get_dist_path;
+use crate::driver::{install_driver, run_self_elevated};
+use crate::package::{
+    download_package, prepare_package, prepare_package_strip_prefix, prepare_single_binary,
+    refresh_archive, SignaturePolicy,
+};
+use crate::project::lock::get_file_sha256;
+use crate::shell::update_env_path;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Single operation of installation. Steps are collected first, so that the same plan
+/// can be printed by --dry-run or executed.
+pub enum InstallStep {
+    /// Download archive to dist directory and extract it
+    Package {
+        url: String,
+        archive: String,
+        destination: String,
+        strip_prefix: Option<String>,
+        policy: SignaturePolicy,
+        refresh: bool,
+        /// Checksum recorded by idf-env.lock, the archive is verified before extraction
+        sha256: Option<String>,
+    },
+    /// Download single binary to tools directory
+    Binary {
+        url: String,
+        binary_name: String,
+        tool_name: String,
+        policy: SignaturePolicy,
+    },
+    Copy {
+        source: String,
+        destination: String,
+    },
+    Command {
+        program: String,
+        arguments: Vec<String>,
+    },
+    /// Append directory to PATH, on Windows it's stored in registry HKCU\Environment
+    AppendPath {
+        path: String,
+    },
+    Driver {
+        inf: String,
+    },
+    /// Start idf-env with elevated privileges and extra argument, the elevated process continues the installation
+    Elevate {
+        argument: String,
+    },
+    /// Remove directory, e.g. toolchain of another version which is replaced
+    Remove {
+        path: String,
+    },
+    /// Part of installation which is not necessary, e.g. the tool is already installed
+    Skip {
+        reason: String,
+    },
+}
+
+/// Archive is downloaded ahead of extraction, so that content which does not match the lock is
+/// never extracted. Extracted destination is not verified again.
+fn verify_locked_archive(url: &str, archive: &str, destination: &str, sha256: &str) -> Result<()> {
+    if Path::new(destination).exists() {
+        return Ok(());
+    }
+    let archive_path = get_dist_path(archive);
+    if let Some(parent) = Path::new(&archive_path).parent() {
+        create_dir_all(parent)?;
+    }
+    download_package(url.to_string(), archive_path.clone())?;
+    match get_file_sha256(&archive_path) {
+        Some(archive_sha256) if archive_sha256 == sha256 => Ok(()),
+        archive_sha256 => {
+            let _ = remove_file(&archive_path);
+            Err(format!(
+                "Archive {} has sha256 {}, locked {}",
+                archive,
+                archive_sha256.unwrap_or_default(),
+                sha256
+            )
+            .into())
+        }
+    }
+}
+
+impl InstallStep {
+    fn get_type(&self) -> &str {
+        match self {
+            InstallStep::Package { .. } => "package",
+            InstallStep::Binary { .. } => "binary",
+            InstallStep::Copy { .. } => "copy",
+            InstallStep::Command { .. } => "command",
+            InstallStep::AppendPath { .. } => "append-path",
+            InstallStep::Driver { .. } => "driver",
+            InstallStep::Elevate { .. } => "elevate",
+            InstallStep::Remove { .. } => "remove",
+            InstallStep::Skip { .. } => "skip",
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            InstallStep::Package { url, archive, destination, strip_prefix, refresh, sha256, .. } => {
+                let mut description = format!("Download {} as {} and extract to {}", url, archive, destination);
+                if let Some(prefix) = strip_prefix {
+                    description = format!("{} (strip prefix {})", description, prefix);
+                }
+                if *refresh {
+                    description = format!("{}, refresh when changed on the server", description);
+                }
+                if let Some(sha256) = sha256 {
+                    description = format!("{}, verify sha256 {}", description, sha256);
+                }
+                description
+            }
+            InstallStep::Binary { url, binary_name, tool_name, .. } => {
+                format!("Download {} as {} to tool {}", url, binary_name, tool_name)
+            }
+            InstallStep::Copy { source, destination } => format!("Copy {} to {}", source, destination),
+            InstallStep::Command { program, arguments } => format!("Run {} {}", program, arguments.join(" ")),
+            #[cfg(windows)]
+            InstallStep::AppendPath { path } => format!("Append {} to PATH in HKCU\\Environment", path),
+            #[cfg(unix)]
+            InstallStep::AppendPath { path } => format!("Append {} to PATH", path),
+            InstallStep::Driver { inf } => format!("Install driver {}", inf),
+            InstallStep::Elevate { argument } => format!("Run idf-env with elevated privileges and {}", argument),
+            InstallStep::Remove { path } => format!("Remove {}", path),
+            InstallStep::Skip { reason } => format!("Skip: {}", reason),
+        }
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        let properties = match self {
+            InstallStep::Package { url, archive, destination, strip_prefix, policy, refresh, sha256 } => json::object! {
+                "url": url.as_str(),
+                "archive": archive.as_str(),
+                "destination": destination.as_str(),
+                "stripPrefix": strip_prefix.clone(),
+                "signature": format!("{:?}", policy).to_lowercase(),
+                "refresh": *refresh,
+                "sha256": sha256.clone()
+            },
+            InstallStep::Binary { url, binary_name, tool_name, policy } => json::object! {
+                "url": url.as_str(),
+                "binary": binary_name.as_str(),
+                "tool": tool_name.as_str(),
+                "signature": format!("{:?}", policy).to_lowercase()
+            },
+            InstallStep::Copy { source, destination } => json::object! {
+                "source": source.as_str(),
+                "destination": destination.as_str()
+            },
+            InstallStep::Command { program, arguments } => json::object! {
+                "program": program.as_str(),
+                "arguments": arguments.clone()
+            },
+            InstallStep::AppendPath { path } => json::object! { "path": path.as_str() },
+            InstallStep::Driver { inf } => json::object! { "inf": inf.as_str() },
+            InstallStep::Elevate { argument } => json::object! { "argument": argument.as_str() },
+            InstallStep::Remove { path } => json::object! { "path": path.as_str() },
+            InstallStep::Skip { reason } => json::object! { "reason": reason.as_str() },
+        };
+        let mut step = json::object! { "type": self.get_type() };
+        for (key, value) in properties.entries() {
+            step[key] = value.clone();
+        }
+        step
+    }
+
+    pub fn execute(&self) -> Result<()> {
+        match self {
+            InstallStep::Package { url, archive, destination, strip_prefix, policy, refresh, sha256 } => {
+                if *refresh {
+                    refresh_archive(url, archive, destination)?;
+                }
+                if let Some(sha256) = sha256 {
+                    verify_locked_archive(url, archive, destination, sha256)?;
+                }
+                match strip_prefix {
+                    Some(prefix) => prepare_package_strip_prefix(url, archive, destination.to_string(), prefix, *policy),
+                    None => prepare_package(url.to_string(), archive, destination.to_string(), *policy),
+                }
+            }
+            InstallStep::Binary { url, binary_name, tool_name, policy } => {
+                prepare_single_binary(url, binary_name, tool_name, *policy)?;
+                Ok(())
+            }
+            InstallStep::Copy { source, destination } => {
+                copy(source, destination)?;
+                Ok(())
+            }
+            InstallStep::Command { program, arguments } => {
+                let output = std::process::Command::new(program)
+                    .args(arguments)
+                    .stdout(Stdio::piped())
+                    .output()?;
+                println!("{}", String::from_utf8_lossy(&output.stdout));
+                if !output.status.success() {
+                    return Err(format!("{} failed with {}", program, output.status).into());
+                }
+                Ok(())
+            }
+            InstallStep::AppendPath { path } => {
+                #[cfg(windows)]
+                println!("PATH+=\";{}\"", path);
+                #[cfg(unix)]
+                println!("export PATH=\"{}:$PATH\"", path);
+                update_env_path(path);
+                Ok(())
+            }
+            InstallStep::Driver { inf } => install_driver(inf.to_string()),
+            InstallStep::Elevate { argument } => run_self_elevated(argument.to_string()),
+            InstallStep::Remove { path } => {
+                remove_dir_all(path)?;
+                Ok(())
+            }
+            InstallStep::Skip { reason } => {
+                println!("{}", reason);
+                Ok(())
+            }
+        }
+    }
+}
+
+pub struct InstallPlan {
+    pub title: String,
+    pub steps: Vec<InstallStep>,
+}
+
+impl InstallPlan {
+    pub fn new(title: &str) -> InstallPlan {
+        InstallPlan {
+            title: title.to_string(),
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn add(&mut self, step: InstallStep) {
+        self.steps.push(step);
+    }
+
+    pub fn append(&mut self, plan: InstallPlan) {
+        self.steps.extend(plan.steps);
+    }
+
+    pub fn to_json(&self) -> json::JsonValue {
+        let steps: Vec<json::JsonValue> = self.steps.iter().map(|step| step.to_json()).collect();
+        json::object! {
+            "title": self.title.as_str(),
+            "steps": steps
+        }
+    }
+
+    pub fn print(&self) {
+        println!("{}", self.title);
+        for (index, step) in self.steps.iter().enumerate() {
+            println!("{:>3}. {}", index + 1, step.describe());
+        }
+    }
+
+    /// Execute steps in order. Following steps depend on the previous ones, so the installation
+    /// stops at the first failed step.
+    pub fn execute(&self) -> Result<()> {
+        for (index, step) in self.steps.iter().enumerate() {
+            println!("{}", step.describe());
+            if let Err(e) = step.execute() {
+                println!("Failed: {}", e);
+                return Err(format!("Step {} of {} failed: {}", index + 1, self.steps.len(), e).into());
+            }
+            println!("Ok");
+        }
+        Ok(())
+    }
+}
+
+pub fn get_dry_run_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("dry-run")
+        .long("dry-run")
+        .help("Display installation plan without modifying the system")
+}
+
+pub fn get_json_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("json")
+        .long("json")
+        .help("Emit installation plan of --dry-run as JSON")
+}
+
+/// Print the plan for --dry-run, otherwise execute it.
+pub fn run_plan(plan: &InstallPlan, matches: &clap::ArgMatches<'_>) -> Result<()> {
+    if matches.is_present("dry-run") {
+        if matches.is_present("json") {
+            println!("{:#}", plan.to_json());
+        } else {
+            plan.print();
+        }
+        return Ok(());
+    }
+
+    plan.execute()
+}
@@ -0,0 +1,270 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::driver::DRIVER_NAMES;
+use crate::rust::{DEFAULT_LLVM_VERSION, DEFAULT_RUST_TOOLCHAIN_VERSION};
+
+pub mod lock;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Manifest committed in the root of firmware repository
+pub const MANIFEST_FILE: &str = "idf-env.toml";
+
+/// Chip targets accepted by idf_tools.py install --targets
+pub const IDF_TARGETS: [&str; 10] = [
+    "esp32", "esp32s2", "esp32s3", "esp32c2", "esp32c3", "esp32c5", "esp32c6", "esp32h2", "esp32p4",
+    "linux",
+];
+
+#[derive(Debug, PartialEq)]
+pub struct IdfRequirement {
+    /// Tag or branch, e.g. v5.1.2 or release/v5.1
+    pub version: String,
+    /// Tools are installed only for the targets, empty list means all targets
+    pub targets: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RustRequirement {
+    pub toolchain: String,
+    pub llvm: String,
+    pub extra_crates: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct IdeRequirement {
+    /// Download URL of Espressif-IDE, the default release is used when missing
+    pub url: Option<String>,
+    pub destination: String,
+}
+
+/// Environment declared by idf-env.toml, e.g.
+///
+/// [idf]
+/// version = "v5.1.2"
+/// targets = ["esp32", "esp32s3"]
+///
+/// [rust]
+/// toolchain = "1.63.0.0"
+/// extra-crates = ["ldproxy", "espflash"]
+///
+/// [driver]
+/// names = ["silabs", "espressif"]
+///
+/// [build]
+/// targets = ["esp32", "esp32s3"]
+///
+/// [ide]
+/// destination = "C:/Espressif/tools/espressif-ide"
+#[derive(Debug, Default, PartialEq)]
+pub struct ProjectManifest {
+    pub idf: Option<IdfRequirement>,
+    pub rust: Option<RustRequirement>,
+    pub drivers: Vec<String>,
+    /// Chips built by idf build for the project
+    pub build_targets: Vec<String>,
+    pub ide: Option<IdeRequirement>,
+}
+
+fn format_string(value: &str) -> String {
+    toml::Value::String(value.to_string()).to_string()
+}
+
+fn format_string_list(values: &[String]) -> String {
+    let values: Vec<String> = values.iter().map(|value| format_string(value)).collect();
+    format!("[{}]", values.join(", "))
+}
+
+impl ProjectManifest {
+    /// Serialize in the same layout as the example above, sections which are not set are omitted.
+    pub fn to_toml(&self) -> String {
+        let mut sections: Vec<String> = Vec::new();
+        if let Some(idf) = &self.idf {
+            let mut section = format!("[idf]\nversion = {}\n", format_string(&idf.version));
+            if !idf.targets.is_empty() {
+                section.push_str(&format!("targets = {}\n", format_string_list(&idf.targets)));
+            }
+            sections.push(section);
+        }
+        if let Some(rust) = &self.rust {
+            let mut section = format!(
+                "[rust]\ntoolchain = {}\nllvm = {}\n",
+                format_string(&rust.toolchain),
+                format_string(&rust.llvm)
+            );
+            if !rust.extra_crates.is_empty() {
+                section.push_str(&format!("extra-crates = {}\n", format_string_list(&rust.extra_crates)));
+            }
+            sections.push(section);
+        }
+        if !self.drivers.is_empty() {
+            sections.push(format!("[driver]\nnames = {}\n", format_string_list(&self.drivers)));
+        }
+        if !self.build_targets.is_empty() {
+            sections.push(format!("[build]\ntargets = {}\n", format_string_list(&self.build_targets)));
+        }
+        if let Some(ide) = &self.ide {
+            let mut section = String::from("[ide]\n");
+            if let Some(url) = &ide.url {
+                section.push_str(&format!("url = {}\n", format_string(url)));
+            }
+            section.push_str(&format!("destination = {}\n", format_string(&ide.destination)));
+            sections.push(section);
+        }
+        sections.join("\n")
+    }
+}
+
+fn get_string(table: &toml::Value, section: &str, key: &str) -> Result<Option<String>> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(toml::Value::String(value)) => Ok(Some(value.to_string())),
+        Some(_) => Err(format!("{}.{} must be a string", section, key).into()),
+    }
+}
+
+fn get_string_list(table: &toml::Value, section: &str, key: &str) -> Result<Vec<String>> {
+    match table.get(key) {
+        None => Ok(Vec::new()),
+        Some(toml::Value::Array(values)) => values
+            .iter()
+            .map(|value| match value.as_str() {
+                Some(value) => Ok(value.to_string()),
+                None => Err(format!("{}.{} must be a list of strings", section, key).into()),
+            })
+            .collect(),
+        Some(_) => Err(format!("{}.{} must be a list of strings", section, key).into()),
+    }
+}
+
+fn check_names(names: &[String], known_names: &[&str], section: &str, key: &str) -> Result<()> {
+    match names.iter().find(|name| !known_names.contains(&name.as_str())) {
+        Some(name) => Err(format!(
+            "Unknown {}.{} {}, use one of: {}",
+            section,
+            key,
+            name,
+            known_names.join(", ")
+        )
+        .into()),
+        None => Ok(()),
+    }
+}
+
+pub fn parse_manifest(content: &str) -> Result<ProjectManifest> {
+    let parsed = content.parse::<toml::Value>()?;
+    let mut manifest = ProjectManifest::default();
+
+    if let Some(idf) = parsed.get("idf") {
+        let version = get_string(idf, "idf", "version")?.ok_or("idf.version is required")?;
+        let targets = get_string_list(idf, "idf", "targets")?;
+        check_names(&targets, &IDF_TARGETS, "idf", "targets")?;
+        manifest.idf = Some(IdfRequirement { version, targets });
+    }
+
+    if let Some(rust) = parsed.get("rust") {
+        manifest.rust = Some(RustRequirement {
+            toolchain: get_string(rust, "rust", "toolchain")?
+                .unwrap_or_else(|| DEFAULT_RUST_TOOLCHAIN_VERSION.to_string()),
+            llvm: get_string(rust, "rust", "llvm")?.unwrap_or_else(|| DEFAULT_LLVM_VERSION.to_string()),
+            extra_crates: get_string_list(rust, "rust", "extra-crates")?,
+        });
+    }
+
+    if let Some(driver) = parsed.get("driver") {
+        manifest.drivers = get_string_list(driver, "driver", "names")?;
+        check_names(&manifest.drivers, &DRIVER_NAMES, "driver", "names")?;
+    }
+
+    if let Some(build) = parsed.get("build") {
+        manifest.build_targets = get_string_list(build, "build", "targets")?;
+        check_names(&manifest.build_targets, &IDF_TARGETS, "build", "targets")?;
+        // Tools of other chips are not installed
+        if let Some(idf) = manifest.idf.as_ref().filter(|idf| !idf.targets.is_empty()) {
+            let idf_targets: Vec<&str> = idf.targets.iter().map(|target| target.as_str()).collect();
+            check_names(&manifest.build_targets, &idf_targets, "build", "targets")?;
+        }
+    }
+
+    if let Some(ide) = parsed.get("ide") {
+        manifest.ide = Some(IdeRequirement {
+            url: get_string(ide, "ide", "url")?,
+            destination: get_string(ide, "ide", "destination")?.ok_or("ide.destination is required")?,
+        });
+    }
+    Ok(manifest)
+}
+
+/// Find manifest in the directory or in the closest parent directory.
+pub fn find_manifest(directory: &Path) -> Option<PathBuf> {
+    directory
+        .ancestors()
+        .map(|ancestor| ancestor.join(MANIFEST_FILE))
+        .find(|manifest_path| manifest_path.is_file())
+}
+
+pub fn read_manifest(manifest_path: &Path) -> Result<ProjectManifest> {
+    let content = fs::read_to_string(manifest_path)?;
+    parse_manifest(&content).map_err(|e| format!("{}: {}", manifest_path.display(), e).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = parse_manifest(
+            r#"
+            [idf]
+            version = "v5.1.2"
+            targets = ["esp32", "esp32s3"]
+
+            [rust]
+            extra-crates = ["ldproxy"]
+
+            [driver]
+            names = ["silabs"]
+
+            [build]
+            targets = ["esp32s3"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            manifest.idf,
+            Some(IdfRequirement {
+                version: "v5.1.2".to_string(),
+                targets: vec!["esp32".to_string(), "esp32s3".to_string()],
+            })
+        );
+        assert_eq!(
+            manifest.rust,
+            Some(RustRequirement {
+                toolchain: DEFAULT_RUST_TOOLCHAIN_VERSION.to_string(),
+                llvm: DEFAULT_LLVM_VERSION.to_string(),
+                extra_crates: vec!["ldproxy".to_string()],
+            })
+        );
+        assert_eq!(manifest.drivers, vec!["silabs".to_string()]);
+        assert_eq!(manifest.build_targets, vec!["esp32s3".to_string()]);
+        assert_eq!(manifest.ide, None);
+
+        assert_eq!(parse_manifest("").unwrap(), ProjectManifest::default());
+        assert!(parse_manifest("[idf]\ntargets = [\"esp32\"]").is_err());
+        assert!(parse_manifest("[idf]\nversion = \"v5.1\"\ntargets = [\"esp8266\"]").is_err());
+        assert!(parse_manifest("[driver]\nnames = \"silabs\"").is_err());
+        assert!(parse_manifest("[idf]\nversion = \"v5.1\"\ntargets = [\"esp32\"]\n[build]\ntargets = [\"esp32c3\"]").is_err());
+
+        assert_eq!(parse_manifest(&manifest.to_toml()).unwrap(), manifest);
+        let pinned = ProjectManifest {
+            idf: Some(IdfRequirement {
+                version: "release/v5.1".to_string(),
+                targets: vec!["esp32c3".to_string()],
+            }),
+            ..ProjectManifest::default()
+        };
+        assert_eq!(pinned.to_toml(), "[idf]\nversion = \"release/v5.1\"\ntargets = [\"esp32c3\"]\n");
+    }
+}
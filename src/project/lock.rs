@@ -0,0 +1,418 @@
+use git2::Repository;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+use super::RustRequirement;
+use crate::config::get_dist_path;
+use crate::idf::tools::{read_tool_archives, ToolArchive};
+use crate::rust::get_components;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Lock file written next to idf-env.toml
+pub const LOCK_FILE: &str = "idf-env.lock";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LockedSubmodule {
+    /// Path relative to the root of ESP-IDF, e.g. components/esptool_py/esptool
+    pub path: String,
+    pub commit: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LockedIdf {
+    pub version: String,
+    pub commit: String,
+    pub targets: Vec<String>,
+    pub submodules: Vec<LockedSubmodule>,
+    /// Tools of the host platform with sha256 declared by tools.json
+    pub tools: Vec<ToolArchive>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LockedComponent {
+    pub name: String,
+    /// Version reported by binary of extra crate
+    pub version: Option<String>,
+    pub archive: Option<String>,
+    pub sha256: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct LockedRust {
+    pub toolchain: String,
+    pub llvm: String,
+    pub components: Vec<LockedComponent>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProjectLock {
+    pub idf: Option<LockedIdf>,
+    pub rust: Option<LockedRust>,
+}
+
+fn get_str(table: &toml::Value, key: &str) -> Result<String> {
+    table
+        .get(key)
+        .and_then(|value| value.as_str())
+        .map(|value| value.to_string())
+        .ok_or_else(|| format!("{} is missing in {}", key, LOCK_FILE).into())
+}
+
+fn get_optional_str(table: &toml::Value, key: &str) -> Option<String> {
+    table.get(key).and_then(|value| value.as_str()).map(|value| value.to_string())
+}
+
+fn get_tables<'a>(table: &'a toml::Value, key: &str) -> Vec<&'a toml::Value> {
+    match table.get(key).and_then(|value| value.as_array()) {
+        Some(values) => values.iter().collect(),
+        None => Vec::new(),
+    }
+}
+
+fn new_table(entries: Vec<(&str, toml::Value)>) -> toml::Value {
+    toml::Value::Table(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_string(), value))
+            .collect(),
+    )
+}
+
+impl ProjectLock {
+    pub fn to_toml(&self) -> Result<String> {
+        let mut lock = toml::value::Table::new();
+        if let Some(idf) = &self.idf {
+            let submodules = idf
+                .submodules
+                .iter()
+                .map(|submodule| {
+                    new_table(vec![
+                        ("path", submodule.path.clone().into()),
+                        ("commit", submodule.commit.clone().into()),
+                    ])
+                })
+                .collect::<Vec<toml::Value>>();
+            let tools = idf
+                .tools
+                .iter()
+                .map(|tool| {
+                    new_table(vec![
+                        ("name", tool.name.clone().into()),
+                        ("version", tool.version.clone().into()),
+                        ("url", tool.url.clone().into()),
+                        ("sha256", tool.sha256.clone().into()),
+                    ])
+                })
+                .collect::<Vec<toml::Value>>();
+            lock.insert(
+                "idf".to_string(),
+                new_table(vec![
+                    ("version", idf.version.clone().into()),
+                    ("commit", idf.commit.clone().into()),
+                    ("targets", idf.targets.clone().into()),
+                    ("submodules", submodules.into()),
+                    ("tools", tools.into()),
+                ]),
+            );
+        }
+        if let Some(rust) = &self.rust {
+            let components = rust
+                .components
+                .iter()
+                .map(|component| {
+                    let mut entries = vec![("name", component.name.clone().into())];
+                    if let Some(version) = &component.version {
+                        entries.push(("version", version.clone().into()));
+                    }
+                    if let Some(archive) = &component.archive {
+                        entries.push(("archive", archive.clone().into()));
+                    }
+                    if let Some(sha256) = &component.sha256 {
+                        entries.push(("sha256", sha256.clone().into()));
+                    }
+                    new_table(entries)
+                })
+                .collect::<Vec<toml::Value>>();
+            lock.insert(
+                "rust".to_string(),
+                new_table(vec![
+                    ("toolchain", rust.toolchain.clone().into()),
+                    ("llvm", rust.llvm.clone().into()),
+                    ("components", components.into()),
+                ]),
+            );
+        }
+        let content = toml::to_string(&toml::Value::Table(lock))?;
+        Ok(format!("# Generated by idf-env sync, do not edit\n{}", content))
+    }
+}
+
+pub fn parse_lock(content: &str) -> Result<ProjectLock> {
+    let parsed = content.parse::<toml::Value>()?;
+    let mut lock = ProjectLock::default();
+    if let Some(idf) = parsed.get("idf") {
+        let targets = match idf.get("targets").and_then(|targets| targets.as_array()) {
+            Some(targets) => targets
+                .iter()
+                .filter_map(|target| target.as_str())
+                .map(|target| target.to_string())
+                .collect(),
+            None => Vec::new(),
+        };
+        let mut submodules: Vec<LockedSubmodule> = Vec::new();
+        for submodule in get_tables(idf, "submodules") {
+            submodules.push(LockedSubmodule {
+                path: get_str(submodule, "path")?,
+                commit: get_str(submodule, "commit")?,
+            });
+        }
+        let mut tools: Vec<ToolArchive> = Vec::new();
+        for tool in get_tables(idf, "tools") {
+            tools.push(ToolArchive {
+                name: get_str(tool, "name")?,
+                version: get_str(tool, "version")?,
+                url: get_str(tool, "url")?,
+                sha256: get_str(tool, "sha256")?,
+            });
+        }
+        lock.idf = Some(LockedIdf {
+            version: get_str(idf, "version")?,
+            commit: get_str(idf, "commit")?,
+            targets,
+            submodules,
+            tools,
+        });
+    }
+    if let Some(rust) = parsed.get("rust") {
+        let mut components: Vec<LockedComponent> = Vec::new();
+        for component in get_tables(rust, "components") {
+            components.push(LockedComponent {
+                name: get_str(component, "name")?,
+                version: get_optional_str(component, "version"),
+                archive: get_optional_str(component, "archive"),
+                sha256: get_optional_str(component, "sha256"),
+            });
+        }
+        lock.rust = Some(LockedRust {
+            toolchain: get_str(rust, "toolchain")?,
+            llvm: get_str(rust, "llvm")?,
+            components,
+        });
+    }
+    Ok(lock)
+}
+
+pub fn read_lock(lock_path: &Path) -> Result<ProjectLock> {
+    let content = fs::read_to_string(lock_path)?;
+    parse_lock(&content).map_err(|e| format!("{}: {}", lock_path.display(), e).into())
+}
+
+/// Write the lock file, returns false when the content did not change.
+pub fn write_lock(lock_path: &Path, lock: &ProjectLock) -> Result<bool> {
+    let content = lock.to_toml()?;
+    if fs::read_to_string(lock_path).map(|existing| existing == content).unwrap_or(false) {
+        return Ok(false);
+    }
+    fs::write(lock_path, content)?;
+    Ok(true)
+}
+
+pub fn get_file_sha256(path: &str) -> Option<String> {
+    let mut file = fs::File::open(path).ok()?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher).ok()?;
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+fn collect_submodule_commits(repo: &Repository, parent_path: &str, submodules: &mut Vec<LockedSubmodule>) -> Result<()> {
+    for submodule in repo.submodules()? {
+        let path = match parent_path {
+            "" => submodule.path().display().to_string(),
+            _ => format!("{}/{}", parent_path, submodule.path().display()),
+        };
+        let commit = submodule
+            .workdir_id()
+            .ok_or_else(|| format!("Submodule {} is not checked out", path))?;
+        submodules.push(LockedSubmodule {
+            path: path.clone(),
+            commit: commit.to_string(),
+        });
+        collect_submodule_commits(&submodule.open()?, &path, submodules)?;
+    }
+    Ok(())
+}
+
+pub fn get_head_commit(idf_path: &str) -> Result<String> {
+    let repo = Repository::open(idf_path)?;
+    let commit = repo.head()?.peel_to_commit()?.id().to_string();
+    Ok(commit)
+}
+
+/// Commit of the checkout and of all its submodules
+pub fn get_idf_commits(idf_path: &str) -> Result<(String, Vec<LockedSubmodule>)> {
+    let repo = Repository::open(idf_path)?;
+    let commit = get_head_commit(idf_path)?;
+    let mut submodules: Vec<LockedSubmodule> = Vec::new();
+    collect_submodule_commits(&repo, "", &mut submodules)?;
+    submodules.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok((commit, submodules))
+}
+
+pub fn lock_idf(idf_path: &str, version: &str, targets: &[String]) -> Result<LockedIdf> {
+    let (commit, submodules) = get_idf_commits(idf_path)?;
+    Ok(LockedIdf {
+        version: version.to_string(),
+        commit,
+        targets: targets.to_vec(),
+        submodules,
+        tools: read_tool_archives(idf_path)?,
+    })
+}
+
+/// Version reported by binary, e.g. 2.0.0 for "espflash 2.0.0"
+pub fn get_binary_version(bin: &str) -> Option<String> {
+    let output = std::process::Command::new(bin).arg("--version").output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    stdout.split_whitespace().nth(1).map(|version| version.to_string())
+}
+
+/// Archive checksums are known only for archives kept in dist directory.
+pub fn lock_rust(requirement: &RustRequirement) -> LockedRust {
+    let components = get_components(&requirement.toolchain, &requirement.llvm, &requirement.extra_crates)
+        .into_iter()
+        .map(|component| LockedComponent {
+            version: component.bin.as_deref().and_then(get_binary_version),
+            sha256: component
+                .archive
+                .as_deref()
+                .and_then(|archive| get_file_sha256(&get_dist_path(archive))),
+            archive: component.archive,
+            name: component.name,
+        })
+        .collect();
+    LockedRust {
+        toolchain: requirement.toolchain.clone(),
+        llvm: requirement.llvm.clone(),
+        components,
+    }
+}
+
+/// Differences between the checkout and the lock, empty when they match.
+pub fn verify_idf(idf_path: &str, locked: &LockedIdf) -> Result<Vec<String>> {
+    let mut mismatches: Vec<String> = Vec::new();
+    let (commit, submodules) = get_idf_commits(idf_path)?;
+    if commit != locked.commit {
+        mismatches.push(format!("ESP-IDF is at {}, locked {}", commit, locked.commit));
+    }
+    for locked_submodule in locked.submodules.iter() {
+        match submodules.iter().find(|submodule| submodule.path == locked_submodule.path) {
+            Some(submodule) if submodule.commit == locked_submodule.commit => {}
+            Some(submodule) => mismatches.push(format!(
+                "Submodule {} is at {}, locked {}",
+                submodule.path, submodule.commit, locked_submodule.commit
+            )),
+            None => mismatches.push(format!("Submodule {} is missing", locked_submodule.path)),
+        }
+    }
+    // idf_tools.py verifies downloads against sha256 in tools.json
+    let tools = read_tool_archives(idf_path)?;
+    for locked_tool in locked.tools.iter() {
+        match tools.iter().find(|tool| tool.name == locked_tool.name) {
+            Some(tool) if tool == locked_tool => {}
+            Some(tool) => mismatches.push(format!(
+                "Tool {} {} ({}) does not match locked {} ({})",
+                tool.name, tool.version, tool.sha256, locked_tool.version, locked_tool.sha256
+            )),
+            None => mismatches.push(format!("Tool {} is not declared by tools.json", locked_tool.name)),
+        }
+    }
+    Ok(mismatches)
+}
+
+pub fn verify_rust(locked: &LockedRust, extra_crates: &[String]) -> Vec<String> {
+    let installed = lock_rust(&RustRequirement {
+        toolchain: locked.toolchain.clone(),
+        llvm: locked.llvm.clone(),
+        extra_crates: extra_crates.to_vec(),
+    });
+    let mut mismatches: Vec<String> = Vec::new();
+    for locked_component in locked.components.iter() {
+        let component = match installed.components.iter().find(|component| component.name == locked_component.name) {
+            Some(component) => component,
+            None => {
+                mismatches.push(format!("Component {} is not installed", locked_component.name));
+                continue;
+            }
+        };
+        if locked_component.version.is_some() && component.version != locked_component.version {
+            mismatches.push(format!(
+                "{} {} is installed, locked {}",
+                component.name,
+                component.version.as_deref().unwrap_or("unknown version"),
+                locked_component.version.as_deref().unwrap_or("")
+            ));
+        }
+        // Archive removed from dist directory can't be verified, the version is still checked
+        if let (Some(sha256), Some(locked_sha256)) = (&component.sha256, &locked_component.sha256) {
+            if sha256 != locked_sha256 {
+                mismatches.push(format!(
+                    "Archive {} has sha256 {}, locked {}",
+                    component.archive.as_deref().unwrap_or(""),
+                    sha256,
+                    locked_sha256
+                ));
+            }
+        }
+    }
+    mismatches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_round_trip() {
+        let lock = ProjectLock {
+            idf: Some(LockedIdf {
+                version: "v5.1.2".to_string(),
+                commit: "482a8fb2d78e3b58eb21b26da8a5bedf90623213".to_string(),
+                targets: vec!["esp32s3".to_string()],
+                submodules: vec![LockedSubmodule {
+                    path: "components/bt/controller/lib_esp32".to_string(),
+                    commit: "d1a5ba2ed9ed7fc3a5fe8ac0c1f9ac2b1d8fc5a6".to_string(),
+                }],
+                tools: vec![ToolArchive {
+                    name: "cmake".to_string(),
+                    version: "3.24.0".to_string(),
+                    url: "https://example.com/cmake-3.24.0.tar.gz".to_string(),
+                    sha256: "c1b17431a16337d517f7ba78c7067b6f143a12686cb8087f3dd32f3fa45f5aae".to_string(),
+                }],
+            }),
+            rust: Some(LockedRust {
+                toolchain: "1.63.0.0".to_string(),
+                llvm: "esp-14.0.0-20220415".to_string(),
+                components: vec![
+                    LockedComponent {
+                        name: "rust".to_string(),
+                        version: None,
+                        archive: Some("rust-1.63.0.0-x86_64-unknown-linux-gnu.tar.xz".to_string()),
+                        sha256: Some("ab".to_string()),
+                    },
+                    LockedComponent {
+                        name: "espflash".to_string(),
+                        version: Some("2.0.0".to_string()),
+                        archive: None,
+                        sha256: None,
+                    },
+                ],
+            }),
+        };
+        let content = lock.to_toml().unwrap();
+        assert!(content.starts_with("# Generated by idf-env sync"));
+        assert_eq!(parse_lock(&content).unwrap(), lock);
+        assert!(parse_lock("[idf]\nversion = \"v5.1.2\"").is_err());
+    }
+}
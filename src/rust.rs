@@ -2,15 +2,16 @@ use clap::Arg;
 use clap_nested::{Command, Commander, MultiCommand};
 
 use crate::config::get_tool_path;
-use crate::package::{prepare_package, prepare_package_strip_prefix, prepare_single_binary};
-use crate::shell::{run_command, update_env_path};
+use crate::package::SignaturePolicy;
+use crate::plan::{get_dry_run_arg, get_json_arg, run_plan, InstallPlan, InstallStep};
+use crate::project::lock::{get_binary_version, LockedRust};
 use dirs::home_dir;
-use std::fs::{copy, remove_dir_all};
+use std::fs::remove_dir_all;
 use std::path::Path;
 use std::process::Stdio;
 
-const DEFAULT_RUST_TOOLCHAIN_VERSION: &str = "1.63.0.0";
-const DEFAULT_LLVM_VERSION: &str = "esp-14.0.0-20220415";
+pub const DEFAULT_RUST_TOOLCHAIN_VERSION: &str = "1.63.0.0";
+pub const DEFAULT_LLVM_VERSION: &str = "esp-14.0.0-20220415";
 
 struct RustCrate {
     name: String,
@@ -246,173 +247,126 @@ fn build_rust_toolchain(
     }
 }
 
-fn install_rust_stable(default_host: &str) {
-    let rustup_init_path =
-        prepare_single_binary("https://win.rustup.rs/x86_64", "rustup-init.exe", "rustup");
-    println!("rustup stable");
-    match std::process::Command::new(rustup_init_path)
-        .arg("--default-toolchain")
-        .arg("stable")
-        .arg("-y")
-        .arg("--default-host")
-        .arg(default_host)
-        .stdout(Stdio::piped())
-        .output()
-    {
-        Ok(child_output) => {
-            let result = String::from_utf8_lossy(&child_output.stdout);
-            println!("{}", result);
-        }
-        Err(e) => {
-            println!("Error: {}", e);
-        }
-    }
-}
-
-fn install_rust_nightly() {
-    let rustup_path = format!("{}/bin/rustup.exe", get_cargo_home());
-
-    println!("{} install nightly", rustup_path);
-    match std::process::Command::new(rustup_path)
-        .arg("install")
-        .arg("nightly")
-        // .arg("--default-host")
-        // .arg(default_host)
-        .stdout(Stdio::piped())
-        .output()
-    {
-        Ok(child_output) => {
-            let result = String::from_utf8_lossy(&child_output.stdout);
-            println!("Result: {}", result);
-        }
-        Err(e) => {
-            println!("Error: {}", e);
-        }
-    }
+fn get_rust_stable_plan(default_host: &str) -> InstallPlan {
+    let mut plan = InstallPlan::new("Rust stable toolchain");
+    plan.add(InstallStep::Binary {
+        url: "https://win.rustup.rs/x86_64".to_string(),
+        binary_name: "rustup-init.exe".to_string(),
+        tool_name: "rustup".to_string(),
+        policy: SignaturePolicy::Optional,
+    });
+    plan.add(InstallStep::Command {
+        program: format!("{}/rustup-init.exe", get_tool_path("rustup".to_string())),
+        arguments: vec![
+            "--default-toolchain".to_string(),
+            "stable".to_string(),
+            "-y".to_string(),
+            "--default-host".to_string(),
+            default_host.to_string(),
+        ],
+    });
+    plan
 }
 
-fn install_rust(default_host: &str) {
-    install_rust_stable(default_host);
-    install_rust_nightly();
+fn get_rust_nightly_plan() -> InstallPlan {
+    let mut plan = InstallPlan::new("Rust nightly toolchain");
+    plan.add(InstallStep::Command {
+        program: format!("{}/bin/rustup.exe", get_cargo_home()),
+        arguments: vec!["install".to_string(), "nightly".to_string()],
+    });
+    plan
 }
 
-fn install_mingw(toolchain: &RustToolchain) {
+fn get_mingw_plan(toolchain: &RustToolchain) -> InstallPlan {
+    let mut plan = InstallPlan::new("MinGW");
     if Path::new(toolchain.mingw_destination_directory.as_str()).exists() {
-        println!(
-            "Previous installation of MinGW exist in: {}",
-            toolchain.mingw_destination_directory
-        );
-        println!("Please, remove the directory before new installation.");
-        return;
+        plan.add(InstallStep::Skip {
+            reason: format!(
+                "Previous installation of MinGW exist in: {}. Please, remove the directory before new installation.",
+                toolchain.mingw_destination_directory
+            ),
+        });
+        return plan;
     }
 
-    match prepare_package_strip_prefix(
-        &toolchain.mingw_url,
-        &toolchain.mingw_dist_file,
-        toolchain.mingw_destination_directory.clone(),
-        "mingw64",
-    ) {
-        Ok(_) => {
-            println!("Package ready");
-        }
-        Err(_e) => {
-            println!("Unable to prepare package");
-        }
-    }
+    plan.add(InstallStep::Package {
+        url: toolchain.mingw_url.clone(),
+        archive: toolchain.mingw_dist_file.clone(),
+        destination: toolchain.mingw_destination_directory.clone(),
+        strip_prefix: Some("mingw64".to_string()),
+        policy: SignaturePolicy::Required,
+        refresh: false,
+        sha256: None,
+    });
+    plan
 }
 
-fn install_extra_crates(extra_crates: &Vec<RustCrate>) {
-    for extra_crate in extra_crates.into_iter() {
-        println!("Installing crate {}", extra_crate.name);
-
+fn get_extra_crates_plan(extra_crates: &[RustCrate], refresh: bool) -> InstallPlan {
+    let mut plan = InstallPlan::new("Extra crates");
+    for extra_crate in extra_crates.iter() {
         if extra_crate.url.is_empty() {
             // Binary crate is not available, install from source code
-            let cargo_path = format!("{}/bin/cargo.exe", get_cargo_home());
-
-            println!("{} install {}", cargo_path, extra_crate.name);
-            match std::process::Command::new(cargo_path)
-                .arg("install")
-                .arg(extra_crate.name.to_string())
-                .stdout(Stdio::piped())
-                .output()
-            {
-                Ok(child_output) => {
-                    let result = String::from_utf8_lossy(&child_output.stdout);
-                    println!("Crate installed: {}", result);
-                }
-                Err(e) => {
-                    println!("Crate installation failed: {}", e);
-                }
-            }
+            plan.add(InstallStep::Command {
+                program: format!("{}/bin/cargo.exe", get_cargo_home()),
+                arguments: vec!["install".to_string(), extra_crate.name.to_string()],
+            });
         } else {
-            // Binary crate is available donwload it
-            let tmp_path = get_tool_path(extra_crate.name.to_string());
-            match prepare_package(
-                extra_crate.url.to_string(),
-                &extra_crate.dist_file,
-                tmp_path,
-            ) {
-                Ok(_) => {
-                    let source = format!(
-                        "{}/{}",
-                        get_tool_path(extra_crate.name.to_string()),
-                        extra_crate.dist_bin
-                    );
-                    match copy(source.clone(), extra_crate.bin.to_string()) {
-                        Ok(_) => {
-                            println!("Create {} installed.", extra_crate.name);
-                        }
-                        Err(_e) => {
-                            println!(
-                                "Unable to copy crate binary from {} to {}",
-                                source, extra_crate.bin
-                            )
-                        }
-                    }
-                }
-                Err(_e) => {
-                    println!("Unable to unpack bianry crate {}.", extra_crate.name);
-                }
-            };
+            // Binary crate is available donwload it. Binary crates are published under "latest" URLs,
+            // refresh checks whether they changed.
+            plan.add(InstallStep::Package {
+                url: extra_crate.url.to_string(),
+                archive: extra_crate.dist_file.to_string(),
+                destination: get_tool_path(extra_crate.name.to_string()),
+                strip_prefix: None,
+                policy: SignaturePolicy::Optional,
+                refresh,
+                sha256: None,
+            });
+            plan.add(InstallStep::Copy {
+                source: format!(
+                    "{}/{}",
+                    get_tool_path(extra_crate.name.to_string()),
+                    extra_crate.dist_bin
+                ),
+                destination: extra_crate.bin.to_string(),
+            });
         }
     }
+    plan
 }
 
-fn install_vctools() {
+fn get_vctools_plan() -> InstallPlan {
     // installer: https://docs.microsoft.com/en-us/visualstudio/install/use-command-line-parameters-to-install-visual-studio?view=vs-2022
     // Windows 10 SDK - https://docs.microsoft.com/en-us/visualstudio/install/workload-component-id-vs-build-tools?view=vs-2022&preserve-view=true
     // .\vs_BuildTools.exe --passive --wait --add Microsoft.VisualStudio.Component.Windows10SDK.20348
     // .\vs_BuildTools.exe --passive --wait --add Microsoft.VisualStudio.Component.VC.Tools.x86.x64 --add Microsoft.VisualStudio.Component.Windows10SDK.20348
     // path C:\Program Files (x86)\Microsoft Visual Studio\2022\BuildTools\VC\Tools\MSVC\14.33.31629\bin\Hostx64\x64
-
-    let vs_build_tools = prepare_single_binary(
-        "https://aka.ms/vs/17/release/vs_buildtools.exe",
-        "vs_buildtools.exe",
-        "vs_buildtools",
-    );
-    println!("Running VS BuildTools: vs_BuildTools.exe --passive --wait --add Microsoft.VisualStudio.Component.VC.Tools.x86.x64 --add Microsoft.VisualStudio.Component.Windows10SDK.20348");
-
-    match std::process::Command::new(vs_build_tools)
-        .arg("--passive")
-        .arg("--wait")
-        .arg("--add")
-        .arg("Microsoft.VisualStudio.Component.VC.Tools.x86.x64")
-        .arg("--add")
-        .arg("Microsoft.VisualStudio.Component.Windows10SDK.20348")
-        .stdout(Stdio::piped())
-        .output()
-    {
-        Ok(child_output) => {
-            let result = String::from_utf8_lossy(&child_output.stdout);
-            println!("{}", result);
-        }
-        Err(e) => {
-            println!("Error: {}", e);
-        }
-    }
+    let mut plan = InstallPlan::new("Visual Studio Build Tools");
+    plan.add(InstallStep::Binary {
+        url: "https://aka.ms/vs/17/release/vs_buildtools.exe".to_string(),
+        binary_name: "vs_buildtools.exe".to_string(),
+        tool_name: "vs_buildtools".to_string(),
+        policy: SignaturePolicy::Optional,
+    });
+    plan.add(InstallStep::Command {
+        program: format!("{}/vs_buildtools.exe", get_tool_path("vs_buildtools".to_string())),
+        arguments: vec![
+            "--passive".to_string(),
+            "--wait".to_string(),
+            "--add".to_string(),
+            "Microsoft.VisualStudio.Component.VC.Tools.x86.x64".to_string(),
+            "--add".to_string(),
+            "Microsoft.VisualStudio.Component.Windows10SDK.20348".to_string(),
+        ],
+    });
+    plan.add(InstallStep::AppendPath {
+        path: "C:\\Program Files (x86)\\Microsoft Visual Studio\\2022\\BuildTools\\VC\\Tools\\MSVC\\14.33.31629\\bin\\Hostx64\\x64".to_string(),
+    });
+    plan
 }
 
-fn install_rust_toolchain(toolchain: &RustToolchain) {
+fn get_rustup_plan(toolchain: &RustToolchain) -> InstallPlan {
+    let mut plan = InstallPlan::new("rustup");
     match std::process::Command::new("rustup")
         .arg("toolchain")
         .arg("list")
@@ -420,148 +374,111 @@ fn install_rust_toolchain(toolchain: &RustToolchain) {
         .output()
     {
         Ok(child_output) => {
-            println!("rustup - found");
             let result = String::from_utf8_lossy(&child_output.stdout);
             if !result.contains("stable") {
-                println!("stable toolchain not found");
-                install_rust_stable(&toolchain.arch);
+                plan.append(get_rust_stable_plan(&toolchain.arch));
             }
             if !result.contains("nightly") {
-                println!("nightly toolchain not found");
-                install_rust_nightly();
+                plan.append(get_rust_nightly_plan());
             }
-            println!(
-                "rustup - found - {}",
-                String::from_utf8_lossy(&child_output.stdout)
-            );
         }
         Err(e) => {
             if let std::io::ErrorKind::NotFound = e.kind() {
-                println!("rustup was not found.");
-                install_rust(&toolchain.arch);
+                plan.append(get_rust_stable_plan(&toolchain.arch));
+                plan.append(get_rust_nightly_plan());
             }
         }
     }
+    plan
+}
 
-    if Path::new(toolchain.destination_dir.as_str()).exists() {
-        println!(
-            "Previous installation of Rust Toolchain exist in: {}",
-            toolchain.destination_dir
-        );
-        println!("Please, remove the directory before new installation.");
-    } else {
-        // Some platfroms like Windows are available in single bundle rust + src, because install
-        // script in dist is not available for the plaform. It's sufficient to extract the toolchain
-        if toolchain.rust_installer.is_empty() {
-            match prepare_package_strip_prefix(
-                &toolchain.rust_dist_url,
-                &toolchain.rust_dist_file,
-                toolchain.destination_dir.to_string(),
-                "esp",
-            ) {
-                Ok(_) => {
-                    println!("Package ready");
-                }
-                Err(_e) => {
-                    println!("Unable to prepare package");
-                }
-            }
-        } else {
-            match prepare_package_strip_prefix(
-                &toolchain.rust_dist_url,
-                &toolchain.rust_dist_file,
-                toolchain.rust_dist_temp.to_string(),
-                toolchain.rust_dist.as_str(),
-            ) {
-                Ok(_) => {
-                    println!("Package ready");
-                }
-                Err(_e) => {
-                    println!("Unable to prepare package");
-                }
-            }
-
-            let mut arguments: Vec<String> = [].to_vec();
-
-            arguments.push("-c".to_string());
-            arguments.push(format!(
-                "/tmp/rust/install.sh --destdir={} --prefix='' --without=rust-docs",
-                toolchain.destination_dir
-            ));
-
-            match run_command("/bin/bash".to_string(), arguments.clone(), "".to_string()) {
-                Ok(_) => {
-                    println!("Command succeeded");
-                }
-                Err(_e) => {
-                    println!("Command failed");
-                }
-            }
-
-            match prepare_package_strip_prefix(
-                &toolchain.rust_src_dist_url,
-                &toolchain.rust_src_dist_file,
-                toolchain.rust_src_dist_temp.to_string(),
-                toolchain.rust_src_dist.as_str(),
-            ) {
-                Ok(_) => {
-                    println!("Package ready");
-                }
-                Err(_e) => {
-                    println!("Unable to prepare package");
-                }
-            }
-
-            let mut arguments: Vec<String> = [].to_vec();
+/// Existing toolchain is kept unless it's replaced, i.e. removed by a previous step.
+fn get_install_plan(toolchain: &RustToolchain, refresh: bool, replace: bool) -> InstallPlan {
+    let mut plan = InstallPlan::new("Rust environment for Xtensa");
+    plan.append(get_rustup_plan(toolchain));
 
-            arguments.push("-c".to_string());
-            arguments.push(format!(
-                "/tmp/rust-src/install.sh --destdir={} --prefix='' --without=rust-docs",
+    if Path::new(toolchain.destination_dir.as_str()).exists() && !replace {
+        plan.add(InstallStep::Skip {
+            reason: format!(
+                "Previous installation of Rust Toolchain exist in: {}. Please, remove the directory before new installation.",
                 toolchain.destination_dir
-            ));
-
-            match run_command("/bin/bash".to_string(), arguments, "".to_string()) {
-                Ok(_) => {
-                    println!("Command succeeded");
-                }
-                Err(_e) => {
-                    println!("Command failed");
-                }
-            }
-        }
+            ),
+        });
+    } else if toolchain.rust_installer.is_empty() {
+        // Some platfroms like Windows are available in single bundle rust + src, because install
+        // script in dist is not available for the plaform. It's sufficient to extract the toolchain
+        plan.add(InstallStep::Package {
+            url: toolchain.rust_dist_url.clone(),
+            archive: toolchain.rust_dist_file.clone(),
+            destination: toolchain.destination_dir.to_string(),
+            strip_prefix: Some("esp".to_string()),
+            policy: SignaturePolicy::Required,
+            refresh: false,
+            sha256: None,
+        });
+    } else {
+        plan.add(InstallStep::Package {
+            url: toolchain.rust_dist_url.clone(),
+            archive: toolchain.rust_dist_file.clone(),
+            destination: toolchain.rust_dist_temp.to_string(),
+            strip_prefix: Some(toolchain.rust_dist.clone()),
+            policy: SignaturePolicy::Required,
+            refresh: false,
+            sha256: None,
+        });
+        plan.add(InstallStep::Command {
+            program: "/bin/bash".to_string(),
+            arguments: vec![
+                "-c".to_string(),
+                format!(
+                    "/tmp/rust/install.sh --destdir={} --prefix='' --without=rust-docs",
+                    toolchain.destination_dir
+                ),
+            ],
+        });
+        plan.add(InstallStep::Package {
+            url: toolchain.rust_src_dist_url.clone(),
+            archive: toolchain.rust_src_dist_file.clone(),
+            destination: toolchain.rust_src_dist_temp.to_string(),
+            strip_prefix: Some(toolchain.rust_src_dist.clone()),
+            policy: SignaturePolicy::Required,
+            refresh: false,
+            sha256: None,
+        });
+        plan.add(InstallStep::Command {
+            program: "/bin/bash".to_string(),
+            arguments: vec![
+                "-c".to_string(),
+                format!(
+                    "/tmp/rust-src/install.sh --destdir={} --prefix='' --without=rust-docs",
+                    toolchain.destination_dir
+                ),
+            ],
+        });
     }
 
     if Path::new(toolchain.idf_tool_xtensa_elf_clang.as_str()).exists() {
-        println!(
-            "Previous installation of LLVM exist in: {}",
-            toolchain.idf_tool_xtensa_elf_clang
-        );
-        println!("Please, remove the directory before new installation.");
+        plan.add(InstallStep::Skip {
+            reason: format!(
+                "Previous installation of LLVM exist in: {}. Please, remove the directory before new installation.",
+                toolchain.idf_tool_xtensa_elf_clang
+            ),
+        });
     } else {
-        match prepare_package_strip_prefix(
-            &toolchain.llvm_url,
-            &toolchain.llvm_file,
-            toolchain.idf_tool_xtensa_elf_clang.clone(),
-            "xtensa-esp32-elf-clang",
-        ) {
-            Ok(_) => {
-                println!("Package ready");
-            }
-            Err(_e) => {
-                println!("Unable to prepare package");
-            }
-        }
+        plan.add(InstallStep::Package {
+            url: toolchain.llvm_url.clone(),
+            archive: toolchain.llvm_file.clone(),
+            destination: toolchain.idf_tool_xtensa_elf_clang.clone(),
+            strip_prefix: Some("xtensa-esp32-elf-clang".to_string()),
+            policy: SignaturePolicy::Required,
+            refresh: false,
+            sha256: None,
+        });
     }
 
-    println!("Updating environment variables:");
-    let libclang_bin = format!("{}/bin/", toolchain.idf_tool_xtensa_elf_clang);
-
-    #[cfg(windows)]
-    println!("PATH+=\";{}\"", libclang_bin);
-    #[cfg(unix)]
-    println!("export PATH=\"{}:$PATH\"", libclang_bin);
-
-    update_env_path(&libclang_bin);
+    plan.add(InstallStep::AppendPath {
+        path: format!("{}/bin/", toolchain.idf_tool_xtensa_elf_clang),
+    });
 
     // It seems that LIBCLANG_PATH is not necessary for Windows
     // let libclang_path = format!("{}/libclang.dll", libclang_bin);
@@ -569,27 +486,161 @@ fn install_rust_toolchain(toolchain: &RustToolchain) {
     // set_env_variable("LIBCLANG_PATH", libclang_path);
 
     // Install additional dependencies specific for the host
-    // for extra_tool in toolchain.extra_tools
     match toolchain.extra_tools.as_str() {
-        "mingw" => match toolchain.arch.as_str() {
-            "x86_64-pc-windows-gnu" => {
-                install_mingw(toolchain);
-                update_env_path(format!("{}/bin", toolchain.mingw_destination_directory).as_str());
-            }
-            _ => {
-                println!("Ok");
-            }
-        },
+        "mingw" if toolchain.arch.as_str() == "x86_64-pc-windows-gnu" => {
+            plan.append(get_mingw_plan(toolchain));
+            plan.add(InstallStep::AppendPath {
+                path: format!("{}/bin", toolchain.mingw_destination_directory),
+            });
+        }
         "vctools" => {
-            install_vctools();
-            update_env_path("C:\\Program Files (x86)\\Microsoft Visual Studio\\2022\\BuildTools\\VC\\Tools\\MSVC\\14.33.31629\\bin\\Hostx64\\x64");
+            plan.append(get_vctools_plan());
         }
-        _ => {
-            println!("No extra tools selected");
+        _ => {}
+    }
+
+    plan.append(get_extra_crates_plan(&toolchain.extra_crates, refresh));
+    plan
+}
+
+/// Version reported by rustc of installed toolchain, e.g. 1.63.0 for rustc 1.63.0-dev
+fn get_installed_toolchain_version(toolchain: &RustToolchain) -> Option<String> {
+    let rustc = format!(
+        "{}/bin/rustc{}",
+        toolchain.destination_dir,
+        get_os_bin_extension(&toolchain.arch)
+    );
+    let output = std::process::Command::new(rustc).arg("--version").output().ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let version = stdout.split_whitespace().nth(1)?;
+    version.split('-').next().map(|version| version.to_string())
+}
+
+/* Releases of esp-rs contain build number, e.g. 1.63.0.0 is reported as 1.63.0 */
+fn is_toolchain_version(installed_version: &str, version: &str) -> bool {
+    installed_version == version || version.starts_with(&format!("{}.", installed_version))
+}
+
+/// Plan of components missing on the system, the plan is empty when the environment is up to date.
+/// Toolchain of different version is replaced. With lock the plan installs exactly the locked
+/// components: crates are reinstalled in the locked version and archives are verified by sha256.
+pub fn get_sync_plan(version: &str, llvm_version: &str, extra_crates: &[String], locked: Option<&LockedRust>) -> InstallPlan {
+    let arch = guess_host_triple::guess_host_triple().unwrap();
+    let get_locked_version = |name: &str| -> Option<String> {
+        locked?
+            .components
+            .iter()
+            .find(|component| component.name == name)
+            .and_then(|component| component.version.clone())
+    };
+    let missing_crates: Vec<String> = extra_crates
+        .iter()
+        .filter(|name| match get_rust_crate(name, arch) {
+            Some(rust_crate) => match get_locked_version(name) {
+                Some(locked_version) => get_binary_version(&rust_crate.bin) != Some(locked_version),
+                None => !Path::new(&rust_crate.bin).exists(),
+            },
+            None => false,
+        })
+        .cloned()
+        .collect();
+    let toolchain = build_rust_toolchain(version, llvm_version, arch, "", &missing_crates.join(","));
+    let installed_version = get_installed_toolchain_version(&toolchain);
+    let toolchain_ready = match &installed_version {
+        Some(installed_version) => is_toolchain_version(installed_version, version),
+        None => false,
+    };
+    let mut plan = if toolchain_ready && Path::new(&toolchain.idf_tool_xtensa_elf_clang).exists() {
+        get_extra_crates_plan(&toolchain.extra_crates, false)
+    } else {
+        let mut plan = InstallPlan::new("Rust environment for Xtensa");
+        let replace = installed_version.is_some() && !toolchain_ready;
+        if replace {
+            plan.add(InstallStep::Remove {
+                path: toolchain.destination_dir.clone(),
+            });
         }
+        plan.append(get_install_plan(&toolchain, false, replace));
+        plan
+    };
+    if let Some(locked) = locked {
+        pin_locked_components(&mut plan, locked);
     }
+    plan
+}
 
-    install_extra_crates(&toolchain.extra_crates);
+/// Archives of the plan are verified against sha256 of the lock and crates built from source
+/// are installed in the locked version.
+fn pin_locked_components(plan: &mut InstallPlan, locked: &LockedRust) {
+    for step in plan.steps.iter_mut() {
+        match step {
+            InstallStep::Package { archive, refresh, sha256, .. } => {
+                let locked_sha256 = locked
+                    .components
+                    .iter()
+                    .find(|component| component.archive.as_deref() == Some(archive.as_str()))
+                    .and_then(|component| component.sha256.clone());
+                if locked_sha256.is_some() {
+                    // Locked archive must not be replaced by newer content of "latest" URL
+                    *refresh = false;
+                    *sha256 = locked_sha256;
+                }
+            }
+            InstallStep::Command { arguments, .. } if arguments.first().map(|argument| argument.as_str()) == Some("install") => {
+                let locked_version = arguments.get(1).and_then(|name| {
+                    locked
+                        .components
+                        .iter()
+                        .find(|component| component.name == *name)
+                        .and_then(|component| component.version.clone())
+                });
+                if let Some(locked_version) = locked_version {
+                    arguments.push("--version".to_string());
+                    arguments.push(locked_version);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Component of Rust environment recorded in idf-env.lock
+pub struct RustComponent {
+    pub name: String,
+    /// Archive in dist directory, crates installed by cargo install have no archive
+    pub archive: Option<String>,
+    /// Installed binary of extra crate which reports its version
+    pub bin: Option<String>,
+}
+
+pub fn get_components(version: &str, llvm_version: &str, extra_crates: &[String]) -> Vec<RustComponent> {
+    let arch = guess_host_triple::guess_host_triple().unwrap();
+    let toolchain = build_rust_toolchain(version, llvm_version, arch, "", &extra_crates.join(","));
+    let mut components = vec![RustComponent {
+        name: "rust".to_string(),
+        archive: Some(toolchain.rust_dist_file.clone()),
+        bin: None,
+    }];
+    if !toolchain.rust_installer.is_empty() {
+        components.push(RustComponent {
+            name: "rust-src".to_string(),
+            archive: Some(toolchain.rust_src_dist_file.clone()),
+            bin: None,
+        });
+    }
+    components.push(RustComponent {
+        name: "xtensa-esp32-elf-clang".to_string(),
+        archive: Some(toolchain.llvm_file.clone()),
+        bin: None,
+    });
+    for extra_crate in toolchain.extra_crates.iter() {
+        components.push(RustComponent {
+            name: extra_crate.name.clone(),
+            archive: if extra_crate.url.is_empty() { None } else { Some(extra_crate.dist_file.clone()) },
+            bin: Some(extra_crate.bin.clone()),
+        });
+    }
+    components
 }
 
 fn uninstall_rust_toolchain(toolchain: &RustToolchain) {
@@ -641,7 +692,10 @@ fn get_install_runner(
 ) -> std::result::Result<(), clap::Error> {
     let toolchain = get_default_rust_toolchain(matches);
 
-    install_rust_toolchain(&toolchain);
+    if let Err(e) = run_plan(&get_install_plan(&toolchain, matches.is_present("refresh"), false), matches) {
+        println!("Installation failed: {}", e);
+        std::process::exit(1);
+    }
     Ok(())
 }
 
@@ -652,7 +706,9 @@ fn get_reinstall_runner(
     let toolchain = get_default_rust_toolchain(matches);
 
     uninstall_rust_toolchain(&toolchain);
-    install_rust_toolchain(&toolchain);
+    if let Err(e) = get_install_plan(&toolchain, false, false).execute() {
+        println!("{}", e);
+    }
     Ok(())
 }
 
@@ -709,6 +765,14 @@ pub fn get_install_cmd<'a>() -> Command<'a, str> {
                     .takes_value(true)
                     .default_value(""),
             )
+            .arg(
+                Arg::with_name("refresh")
+                    .short("r")
+                    .long("refresh")
+                    .help("Update cached crates when they changed on the server"),
+            )
+            .arg(get_dry_run_arg())
+            .arg(get_json_arg())
         })
         .runner(|_args, matches| get_install_runner(_args, matches))
 }
@@ -793,6 +857,7 @@ pub fn get_multi_cmd<'a>() -> MultiCommand<'a, str, str> {
 mod tests {
     // Note this useful idiom: importing names from outer (for mod tests) scope.
     use super::*;
+    use crate::project::lock::LockedComponent;
 
     #[test]
     fn test_get_extra_crates() {
@@ -810,4 +875,37 @@ mod tests {
         );
         assert_eq!(extra_crates.len(), 5);
     }
+
+    #[test]
+    fn test_pin_locked_components() {
+        let extra_crates = get_extra_crates("cargo-espflash,cargo-generate", "x86_64-pc-windows-gnu");
+        let mut plan = get_extra_crates_plan(&extra_crates, true);
+        let locked = LockedRust {
+            toolchain: "1.63.0.0".to_string(),
+            llvm: "esp-14.0.0-20220415".to_string(),
+            components: vec![
+                LockedComponent {
+                    name: "cargo-espflash".to_string(),
+                    version: Some("1.6.0".to_string()),
+                    archive: Some(extra_crates[0].dist_file.clone()),
+                    sha256: Some("aa".to_string()),
+                },
+                LockedComponent {
+                    name: "cargo-generate".to_string(),
+                    version: Some("0.16.0".to_string()),
+                    archive: None,
+                    sha256: None,
+                },
+            ],
+        };
+        pin_locked_components(&mut plan, &locked);
+        assert!(matches!(
+            &plan.steps[0],
+            InstallStep::Package { refresh: false, sha256: Some(sha256), .. } if sha256 == "aa"
+        ));
+        assert!(matches!(
+            &plan.steps[2],
+            InstallStep::Command { arguments, .. } if arguments[..] == ["install", "cargo-generate", "--version", "0.16.0"]
+        ));
+    }
 }
@@ -0,0 +1,347 @@
+use clap::Arg;
+use clap_nested::Command;
+use std::path::Path;
+
+use crate::config::{load_json, update_idf_property, update_property};
+use crate::driver;
+use crate::ide;
+use crate::idf::installation::{find_installations, normalize_path};
+use crate::idf::switch::switch_idf;
+use crate::idf::{get_default_idf_path, install_idf_tools, install_idf_version};
+use crate::plan::{get_dry_run_arg, InstallPlan};
+use crate::project::lock::{
+    get_head_commit, lock_idf, lock_rust, read_lock, verify_idf, verify_rust, write_lock, ProjectLock, LOCK_FILE,
+};
+use crate::project::{find_manifest, read_manifest, IdfRequirement, ProjectManifest, MANIFEST_FILE};
+use crate::rust;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Difference between the manifest and installed environment
+pub enum SyncAction {
+    InstallIdf {
+        version: String,
+        path: String,
+        targets: Vec<String>,
+    },
+    /// Install tools for targets which were not requested by installation of ESP-IDF
+    InstallTargets {
+        idf_id: String,
+        path: String,
+        python: String,
+        targets: Vec<String>,
+    },
+    SelectIdf {
+        idf_id: String,
+        path: String,
+    },
+    Plan(InstallPlan),
+}
+
+impl SyncAction {
+    pub fn describe(&self) -> String {
+        match self {
+            SyncAction::InstallIdf { version, path, targets } if targets.is_empty() => {
+                format!("Install ESP-IDF {} to {}", version, path)
+            }
+            SyncAction::InstallIdf { version, path, targets } => {
+                format!("Install ESP-IDF {} to {} for {}", version, path, targets.join(", "))
+            }
+            SyncAction::InstallTargets { path, targets, .. } => {
+                format!("Install tools of {} for {}", path, targets.join(", "))
+            }
+            SyncAction::SelectIdf { path, .. } => format!("Select ESP-IDF {}", path),
+            SyncAction::Plan(plan) => plan.title.to_string(),
+        }
+    }
+
+    pub fn execute(&self, verbose: bool) -> Result<()> {
+        match self {
+            SyncAction::InstallIdf { version, path, targets } => {
+                install_idf_version(version, path, targets, false, false, verbose)?;
+            }
+            SyncAction::InstallTargets { idf_id, path, python, targets } => {
+                install_idf_tools(python, path, targets, verbose)?;
+                update_idf_property(idf_id, "targets", targets.clone().into());
+            }
+            SyncAction::SelectIdf { idf_id, .. } => {
+                update_property("idfSelectedId".to_string(), idf_id.to_string());
+            }
+            SyncAction::Plan(plan) => plan.execute()?,
+        }
+        Ok(())
+    }
+}
+
+/// Installation of the version, the version might be also checked out in its default directory.
+fn find_idf_installation(config: &json::JsonValue, version: &str) -> Option<String> {
+    let default_path = normalize_path(&get_default_idf_path(version));
+    find_installations(config, version)
+        .into_iter()
+        .next()
+        .or_else(|| find_installations(config, &default_path).into_iter().next())
+}
+
+/// Installation without recorded targets has tools for all targets.
+pub fn get_idf_actions(config: &json::JsonValue, requirement: &IdfRequirement) -> Vec<SyncAction> {
+    let idf_id = match find_idf_installation(config, &requirement.version) {
+        Some(idf_id) => idf_id,
+        None => {
+            return vec![SyncAction::InstallIdf {
+                version: requirement.version.clone(),
+                path: get_default_idf_path(&requirement.version),
+                targets: requirement.targets.clone(),
+            }]
+        }
+    };
+    let idf = &config["idfInstalled"][idf_id.as_str()];
+    let mut actions: Vec<SyncAction> = Vec::new();
+    if idf["targets"].is_array() {
+        let mut targets: Vec<String> = idf["targets"].members().map(|target| target.to_string()).collect();
+        let missing_targets: Vec<String> = requirement
+            .targets
+            .iter()
+            .filter(|target| !targets.contains(target))
+            .cloned()
+            .collect();
+        if !missing_targets.is_empty() {
+            targets.extend(missing_targets);
+            actions.push(SyncAction::InstallTargets {
+                idf_id: idf_id.clone(),
+                path: idf["path"].to_string(),
+                python: idf["python"].to_string(),
+                targets,
+            });
+        }
+    }
+    if config["idfSelectedId"] != idf_id.as_str() {
+        actions.push(SyncAction::SelectIdf {
+            idf_id: idf_id.clone(),
+            path: idf["path"].to_string(),
+        });
+    }
+    actions
+}
+
+pub fn get_sync_actions(manifest: &ProjectManifest, lock: Option<&ProjectLock>) -> Vec<SyncAction> {
+    let mut actions: Vec<SyncAction> = Vec::new();
+    if let Some(idf) = &manifest.idf {
+        actions.extend(get_idf_actions(&load_json(), idf));
+    }
+    let mut plans: Vec<InstallPlan> = Vec::new();
+    if let Some(rust) = &manifest.rust {
+        let locked = lock.and_then(|lock| lock.rust.as_ref());
+        plans.push(rust::get_sync_plan(&rust.toolchain, &rust.llvm, &rust.extra_crates, locked));
+    }
+    plans.push(driver::get_sync_plan(&manifest.drivers));
+    if let Some(ide) = &manifest.ide {
+        plans.push(ide::get_sync_plan(ide.url.as_deref(), &ide.destination));
+    }
+    actions.extend(
+        plans
+            .into_iter()
+            .filter(|plan| !plan.steps.is_empty())
+            .map(SyncAction::Plan),
+    );
+    actions
+}
+
+/// Lock file must describe the same environment as the manifest.
+fn check_lock(manifest: &ProjectManifest, lock: &ProjectLock) -> Result<()> {
+    let idf_matches = match (&manifest.idf, &lock.idf) {
+        (Some(idf), Some(locked)) => idf.version == locked.version && idf.targets == locked.targets,
+        (None, None) => true,
+        _ => false,
+    };
+    let rust_matches = match (&manifest.rust, &lock.rust) {
+        (Some(rust), Some(locked)) => rust.toolchain == locked.toolchain && rust.llvm == locked.llvm,
+        (None, None) => true,
+        _ => false,
+    };
+    if !idf_matches || !rust_matches {
+        return Err(format!("{} is out of date, run idf-env sync without --locked", LOCK_FILE).into());
+    }
+    Ok(())
+}
+
+/// Record installed environment of the manifest.
+fn create_lock(manifest: &ProjectManifest) -> Result<ProjectLock> {
+    let mut lock = ProjectLock::default();
+    if let Some(idf) = &manifest.idf {
+        let config = load_json();
+        let idf_id = find_idf_installation(&config, &idf.version)
+            .ok_or_else(|| format!("ESP-IDF {} is not installed", idf.version))?;
+        let idf_path = config["idfInstalled"][idf_id.as_str()]["path"].to_string();
+        lock.idf = Some(lock_idf(&idf_path, &idf.version, &idf.targets)?);
+    }
+    if let Some(rust) = &manifest.rust {
+        lock.rust = Some(lock_rust(rust));
+    }
+    Ok(lock)
+}
+
+/// Check out commits of the lock and verify that the environment matches it.
+fn apply_lock(manifest: &ProjectManifest, lock: &ProjectLock, verbose: bool) -> Result<()> {
+    let mut mismatches: Vec<String> = Vec::new();
+    if let Some(locked) = &lock.idf {
+        let config = load_json();
+        let idf_id = find_idf_installation(&config, &locked.version)
+            .ok_or_else(|| format!("ESP-IDF {} is not installed", locked.version))?;
+        let idf_path = config["idfInstalled"][idf_id.as_str()]["path"].to_string();
+        if get_head_commit(&idf_path)? != locked.commit {
+            println!("Checking out locked commit {}", locked.commit);
+            switch_idf(Some(&idf_id), &locked.commit, false, false, verbose)?;
+        }
+        mismatches.extend(verify_idf(&idf_path, locked)?);
+    }
+    if let (Some(rust), Some(locked)) = (&manifest.rust, &lock.rust) {
+        mismatches.extend(verify_rust(locked, &rust.extra_crates));
+    }
+    if !mismatches.is_empty() {
+        for mismatch in mismatches.iter() {
+            println!("{}", mismatch);
+        }
+        return Err(format!("Environment does not match {}", LOCK_FILE).into());
+    }
+    Ok(())
+}
+
+fn sync(matches: &clap::ArgMatches<'_>) -> Result<()> {
+    let directory = match matches.value_of("project") {
+        Some(project) => Path::new(project).to_path_buf(),
+        None => std::env::current_dir()?,
+    };
+    let manifest_path = find_manifest(&directory)
+        .ok_or_else(|| format!("{} not found in {} or its parent directories", MANIFEST_FILE, directory.display()))?;
+    let manifest = read_manifest(&manifest_path)?;
+    let lock_path = manifest_path.with_file_name(LOCK_FILE);
+    let locked = matches.is_present("locked");
+    let lock = if locked {
+        if !lock_path.exists() {
+            return Err(format!("{} not found, run idf-env sync to create it", lock_path.display()).into());
+        }
+        let lock = read_lock(&lock_path)?;
+        check_lock(&manifest, &lock)?;
+        Some(lock)
+    } else {
+        None
+    };
+    let actions = get_sync_actions(&manifest, lock.as_ref());
+
+    if matches.is_present("dry-run") {
+        if actions.is_empty() {
+            println!("Environment of {} is up to date", manifest_path.display());
+        }
+        for (index, action) in actions.iter().enumerate() {
+            println!("{:>3}. {}", index + 1, action.describe());
+            if let SyncAction::Plan(plan) = action {
+                for step in plan.steps.iter() {
+                    println!("       {}", step.describe());
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let verbose = matches.is_present("verbose");
+    let mut failed_actions = 0;
+    for action in actions.iter() {
+        println!("{}", action.describe());
+        if let Err(e) = action.execute(verbose) {
+            println!("Failed: {}", e);
+            failed_actions += 1;
+        }
+    }
+    if failed_actions > 0 {
+        return Err(format!("{} of {} actions failed", failed_actions, actions.len()).into());
+    }
+
+    match &lock {
+        Some(lock) => {
+            apply_lock(&manifest, lock, verbose)?;
+            println!("Environment matches {}", lock_path.display());
+        }
+        None => {
+            if actions.is_empty() {
+                println!("Environment of {} is up to date", manifest_path.display());
+            }
+            if write_lock(&lock_path, &create_lock(&manifest)?)? {
+                println!("Updated {}", lock_path.display());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn get_sync_runner(
+    _args: &str,
+    matches: &clap::ArgMatches<'_>,
+) -> std::result::Result<(), clap::Error> {
+    if let Err(e) = sync(matches) {
+        println!("Sync failed: {}", e);
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+pub fn get_cmd<'a>() -> Command<'a, str> {
+    Command::new("sync")
+        .description("Install and select environment declared by idf-env.toml of the project")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("project")
+                    .short("p")
+                    .long("project")
+                    .help("Project directory, current directory by default")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("verbose")
+                    .short("w")
+                    .long("verbose")
+                    .takes_value(false)
+                    .help("display diagnostic log of installation"),
+            )
+            .arg(
+                Arg::with_name("locked")
+                    .long("locked")
+                    .help("Install exactly the versions recorded in idf-env.lock, fail when they can't be matched"),
+            )
+            .arg(get_dry_run_arg())
+        })
+        .runner(|_args, matches| get_sync_runner(_args, matches))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_idf_actions() {
+        let config = json::object! {
+            "idfInstalled": {
+                "esp-idf-1": { "version": "v5.1.2", "path": "/esp/esp-idf-v5.1.2", "python": "python", "targets": ["esp32"] },
+                "esp-idf-2": { "version": "v5.2", "path": "/esp/esp-idf-v5.2", "python": "python" }
+            },
+            "idfSelectedId": "esp-idf-1"
+        };
+        let requirement = |version: &str, targets: &[&str]| IdfRequirement {
+            version: version.to_string(),
+            targets: targets.iter().map(|target| target.to_string()).collect(),
+        };
+
+        assert!(get_idf_actions(&config, &requirement("v5.1.2", &["esp32"])).is_empty());
+
+        let actions = get_idf_actions(&config, &requirement("v5.1.2", &["esp32s3"]));
+        assert_eq!(actions.len(), 1);
+        assert!(matches!(&actions[0], SyncAction::InstallTargets { targets, .. } if targets == &["esp32", "esp32s3"]));
+
+        // Installation without recorded targets has all tools
+        let actions = get_idf_actions(&config, &requirement("v5.2", &["esp32c3"]));
+        assert_eq!(actions.len(), 1);
+        assert!(matches!(&actions[0], SyncAction::SelectIdf { idf_id, .. } if idf_id == "esp-idf-2"));
+
+        let actions = get_idf_actions(&config, &requirement("v5.3", &[]));
+        assert!(matches!(&actions[0], SyncAction::InstallIdf { version, .. } if version == "v5.3"));
+    }
+}
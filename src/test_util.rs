@@ -0,0 +1,27 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Temporary directory of a test. The name contains id of the process, so that concurrent runs
+/// of tests don't collide, and the directory is removed when dropped.
+pub struct TestDirectory {
+    pub path: PathBuf,
+}
+
+impl TestDirectory {
+    pub fn new(name: &str) -> TestDirectory {
+        let path = std::env::temp_dir().join(format!("idf-env-test-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        TestDirectory { path }
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for TestDirectory {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
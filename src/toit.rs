@@ -4,8 +4,8 @@ use clap_nested::{Command, Commander, MultiCommand};
 use dirs::home_dir;
 use std::path::Path;
 use std::fs::{remove_dir_all};
-use crate::package::{prepare_package};
-use crate::shell::{update_env_path};
+use crate::package::{SignaturePolicy};
+use crate::plan::{get_dry_run_arg, get_json_arg, run_plan, InstallPlan, InstallStep};
 
 struct ToitTools {
     jaguar_dist_file: String,
@@ -25,30 +25,27 @@ fn build_toit_tools() -> ToitTools {
     }
 }
 
-fn install_toit_tools(toit_tools:&ToitTools) {
+fn get_install_plan(toit_tools:&ToitTools, refresh: bool) -> InstallPlan {
+    let mut plan = InstallPlan::new("Toit - Jaguar");
 
-    if Path::new(&toit_tools.jaguar_destination_dir.as_str()).exists() {
-        println!("Previous installation of Toit - Jaguar exist in: {}", toit_tools.jaguar_destination_dir);
-        println!("Please, remove the directory before new installation.");
+    if !refresh && Path::new(&toit_tools.jaguar_destination_dir.as_str()).exists() {
+        plan.add(InstallStep::Skip {
+            reason: format!("Previous installation of Toit - Jaguar exist in: {}. Please, remove the directory before new installation.", toit_tools.jaguar_destination_dir)
+        });
     } else {
-        match prepare_package(toit_tools.jaguar_dist_url.to_string(),
-                                     &toit_tools.jaguar_dist_file,
-                                     toit_tools.jaguar_destination_dir.to_string()) {
-                                        Ok(_) => { println!("Toit package ready"); },
-                                        Err(_e) => {
-                                            println!("Unable to prepare the package.");
-                                            return;
-                                        }
-                                     }
+        plan.add(InstallStep::Package {
+            url: toit_tools.jaguar_dist_url.to_string(),
+            archive: toit_tools.jaguar_dist_file.to_string(),
+            destination: toit_tools.jaguar_destination_dir.to_string(),
+            strip_prefix: None,
+            policy: SignaturePolicy::Optional,
+            refresh,
+            sha256: None
+        });
     }
 
-    #[cfg(windows)]
-    println!("PATH+=\";{}\"", &toit_tools.jaguar_destination_dir);
-    #[cfg(unix)]
-    println!("export PATH=\"{}:$PATH\"", &toit_tools.jaguar_destination_dir);
-
-    update_env_path(&toit_tools.jaguar_destination_dir);
-
+    plan.add(InstallStep::AppendPath { path: toit_tools.jaguar_destination_dir.to_string() });
+    plan
 }
 
 fn uninstall_toit_tools(toit_tools:&ToitTools) {
@@ -68,7 +65,10 @@ fn get_default_toit_tools(_matches: &clap::ArgMatches<'_>) -> ToitTools {
 fn get_install_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
     let toit_tools = get_default_toit_tools(&matches);
     if matches.is_present("jaguar") {
-        install_toit_tools(&toit_tools);
+        if let Err(e) = run_plan(&get_install_plan(&toit_tools, matches.is_present("refresh")), matches) {
+            println!("Installation failed: {}", e);
+            std::process::exit(1);
+        }
     }
     Ok(())
 }
@@ -77,7 +77,9 @@ fn get_reinstall_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::res
     let toit_tools = get_default_toit_tools(matches);
     if matches.is_present("jaguar") {
         uninstall_toit_tools(&toit_tools);
-        install_toit_tools(&toit_tools);
+        if let Err(e) = get_install_plan(&toit_tools, false).execute() {
+            println!("{}", e);
+        }
     }
     Ok(())
 }
@@ -100,6 +102,14 @@ pub fn get_install_cmd<'a>() -> Command<'a, str> {
                     .long("jaguar")
 
             )
+            .arg(
+                Arg::with_name("refresh")
+                    .short("r")
+                    .long("refresh")
+                    .help("Update cached packages when they changed on the server")
+            )
+            .arg(get_dry_run_arg())
+            .arg(get_json_arg())
         })
         .runner(|_args, matches|
             get_install_runner(_args, matches)